@@ -1,13 +1,15 @@
 // BENCHMARK - OVERALL SIMULATION ----------------------------------------------------------------
 use criterion::{criterion_group, criterion_main, Criterion};
 use rand::Rng;
-use rts_stockv3::
-    stock_object::{MarketFactors, Stock}
-;
+use rts_stockv3::{
+    market::MarketState,
+    price::Price,
+    stock_object::{MarketFactors, Stock},
+};
 use std::sync::mpsc::{channel, Sender};
 use std::sync::{
     atomic::{AtomicBool, AtomicUsize},
-    Arc, RwLock,
+    Arc, Barrier, Mutex, RwLock,
 };
 use std::thread;
 
@@ -71,19 +73,23 @@ pub fn benchmark_simulation(c: &mut Criterion) {
                         let mut stocks = trader.stocks.write().unwrap();
                         let stock_index = rng.gen_range(0..stocks.len());
                         let stock = &mut stocks[stock_index];
-                        let original_price = stock.current_price;
+                        let original_price = stock.current_price.to_f64();
                         let price_change: f64 = rng.gen_range(-0.2..0.2);
 
                         stock.adjust_price(&market_news);
                         let activity = if price_change < 0.0 { "buy" } else { "sell" };
 
-                        if activity == "buy" {
-                            stock.current_price += original_price * (price_change + 0.05);
+                        let adjusted_price = stock.current_price.to_f64();
+                        let new_price = if activity == "buy" {
+                            adjusted_price + original_price * (price_change + 0.05)
                         // Example logic for buying
                         } else {
-                            stock.current_price += original_price * (price_change - 0.05);
+                            adjusted_price + original_price * (price_change - 0.05)
                             // Example logic for selling
-                        }
+                        };
+                        stock.current_price = Price::from_f64(new_price)
+                            .round_to_tick(stock.tick_size)
+                            .max(stock.tick_size);
 
                         orders_generated += 1;
                         trader
@@ -139,7 +145,104 @@ impl Trader {
     }
 }
 
-criterion_group!(benches, benchmark_simulation);
+// --------------------------------------------------------------------------------------
+// Market State: one Mutex<Vec<Stock>> vs per-symbol locks ------------------------------
+
+const STOCK_LIST: [(&str, f64); 5] = [
+    ("NIKE", 1500.0),
+    ("ADIDAS", 2500.0),
+    ("PUMA", 3300.0),
+    ("YONEX", 3000.0),
+    ("LINING", 4500.0),
+];
+const UPDATES_PER_THREAD: usize = 1_000;
+
+fn stock_list() -> Vec<Stock> {
+    STOCK_LIST
+        .iter()
+        .map(|&(name, price)| Stock::new(name, price))
+        .collect()
+}
+
+// How the broker used to apply a trade: lock everything, then search linearly.
+// The stock is serialized while the lock is held, like traders used to do.
+fn update_vec(stocks: &Mutex<Vec<Stock>>, name: &str, price: Price) {
+    let mut current_stocks = stocks.lock().unwrap();
+    if let Some(stock) = current_stocks.iter_mut().find(|s| s.stock_name == name) {
+        stock.current_price = price;
+        criterion::black_box(serde_json::to_string(&*stock).unwrap());
+    }
+}
+
+fn update_market_state(stocks: &MarketState, name: &str, price: Price) {
+    if let Some(mut stock) = stocks.lock(name) {
+        stock.current_price = price;
+        criterion::black_box(serde_json::to_string(&*stock).unwrap());
+    }
+}
+
+pub fn benchmark_market_state(c: &mut Criterion) {
+    let price = Price::from_f64(1234.5);
+
+    let vec_stocks = Mutex::new(stock_list());
+    c.bench_function("lookup_vec_mutex", |b| {
+        b.iter(|| update_vec(&vec_stocks, "LINING", price))
+    });
+
+    let market_state = MarketState::new(stock_list());
+    c.bench_function("lookup_market_state", |b| {
+        b.iter(|| update_market_state(&market_state, "LINING", price))
+    });
+
+    // One thread per symbol, released together so the updates really overlap
+    let vec_stocks = Arc::new(Mutex::new(stock_list()));
+    c.bench_function("contended_vec_mutex", |b| {
+        b.iter(|| {
+            let start = Arc::new(Barrier::new(STOCK_LIST.len()));
+            let handles: Vec<_> = STOCK_LIST
+                .iter()
+                .map(|&(name, _)| {
+                    let stocks = Arc::clone(&vec_stocks);
+                    let start = Arc::clone(&start);
+                    thread::spawn(move || {
+                        start.wait();
+                        for _ in 0..UPDATES_PER_THREAD {
+                            update_vec(&stocks, name, price);
+                        }
+                    })
+                })
+                .collect();
+            for handle in handles {
+                handle.join().unwrap();
+            }
+        })
+    });
+
+    let market_state = Arc::new(MarketState::new(stock_list()));
+    c.bench_function("contended_market_state", |b| {
+        b.iter(|| {
+            let start = Arc::new(Barrier::new(STOCK_LIST.len()));
+            let handles: Vec<_> = STOCK_LIST
+                .iter()
+                .map(|&(name, _)| {
+                    let stocks = Arc::clone(&market_state);
+                    let start = Arc::clone(&start);
+                    thread::spawn(move || {
+                        start.wait();
+                        for _ in 0..UPDATES_PER_THREAD {
+                            update_market_state(&stocks, name, price);
+                        }
+                    })
+                })
+                .collect();
+            for handle in handles {
+                handle.join().unwrap();
+            }
+        })
+    });
+}
+
+criterion_group!(benches, benchmark_simulation, benchmark_market_state);
 criterion_main!(benches);
 
 // --------------------------------------------------------------------------------------
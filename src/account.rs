@@ -0,0 +1,469 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use chrono::{Datelike, NaiveDate};
+
+use crate::fees::{FeeBreakdown, FeeCharge};
+use crate::fx::{FxRates, BASE_CURRENCY};
+use crate::order::Side;
+use crate::price::{Amount, Price, Quantity};
+use crate::settlement::Obligation;
+use crate::stock_object::Stock;
+
+pub const INITIAL_CASH: Amount = Amount::from_units(1_000_000);
+pub const INITIAL_SHARES: i64 = 1_000;
+
+// Cash accounts trade only with the cash they hold; margin accounts can borrow
+// against their equity and short
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccountType {
+    Cash,
+    Margin,
+}
+
+impl AccountType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AccountType::Cash => "cash",
+            AccountType::Margin => "margin",
+        }
+    }
+}
+
+// Which kind of account the traders open, as in `--accounts cash`. Mixed gives every
+// other trader a margin account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AccountMix {
+    Cash,
+    Margin,
+    #[default]
+    Mixed,
+}
+
+impl AccountMix {
+    pub fn account_type(&self, trader_id: usize) -> AccountType {
+        match self {
+            AccountMix::Cash => AccountType::Cash,
+            AccountMix::Margin => AccountType::Margin,
+            AccountMix::Mixed if trader_id.is_multiple_of(2) => AccountType::Margin,
+            AccountMix::Mixed => AccountType::Cash,
+        }
+    }
+}
+
+impl FromStr for AccountMix {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cash" => Ok(AccountMix::Cash),
+            "margin" => Ok(AccountMix::Margin),
+            "mixed" => Ok(AccountMix::Mixed),
+            _ => Err(format!("unknown account type {}, expected cash, margin or mixed", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Account {
+    pub trader_id: usize,
+    pub account_type: AccountType,
+    // Settled balances, cash by currency
+    pub cash: HashMap<String, Amount>,
+    pub positions: HashMap<String, i64>,
+    // Traded but not yet settled: what fills have added or taken away
+    pub unsettled_cash: HashMap<String, Amount>,
+    pub unsettled_positions: HashMap<String, i64>,
+    // Currency each stock trades and settles in
+    currencies: HashMap<String, String>,
+    // Shares borrowed to cover short positions
+    pub borrowed: HashMap<String, u64>,
+    // Futures positions in contracts; their gains and losses are paid in cash every day
+    pub futures: HashMap<String, i64>,
+    // Equity in the base currency at the previous end-of-day mark, used for daily P&L
+    pub last_marked_equity: Amount,
+    pub fee_charges: Vec<FeeCharge>,
+    // Shares traded in the current calendar month, for volume-tiered fees
+    monthly_volume: u64,
+    volume_month: Option<(i32, u32)>,
+}
+
+// Every account a broker books to. Sharded brokers share one, along with the marks
+// each last left for the symbols it trades, so any of them can value a whole account.
+#[derive(Debug, Default)]
+pub struct Ledger {
+    pub accounts: HashMap<usize, Account>,
+    pub marks: HashMap<String, Price>,
+}
+
+impl Account {
+    // Accounts start with INITIAL_CASH in the base currency
+    pub fn new(trader_id: usize, stocks: &[Stock], account_type: AccountType, fx: &FxRates) -> Self {
+        let positions = stocks.iter()
+            .map(|s| (s.stock_name.clone(), INITIAL_SHARES))
+            .collect();
+        let currencies = stocks.iter()
+            .map(|s| (s.stock_name.clone(), s.currency.clone()))
+            .collect();
+        let mut account = Account {
+            trader_id,
+            account_type,
+            cash: HashMap::from([(BASE_CURRENCY.to_string(), INITIAL_CASH)]),
+            positions,
+            unsettled_cash: HashMap::new(),
+            unsettled_positions: HashMap::new(),
+            currencies,
+            borrowed: HashMap::new(),
+            futures: HashMap::new(),
+            last_marked_equity: Amount::ZERO,
+            fee_charges: Vec::new(),
+            monthly_volume: 0,
+            volume_month: None,
+        };
+        let prices = stocks.iter().map(|s| (s.stock_name.clone(), s.current_price)).collect();
+        account.last_marked_equity = account.equity(&prices, fx);
+        account
+    }
+
+    // Instruments listed after the account was opened, such as option contracts
+    pub fn set_currency(&mut self, symbol: &str, currency: &str) {
+        self.currencies.insert(symbol.to_string(), currency.to_string());
+    }
+
+    pub fn currency(&self, stock_name: &str) -> &str {
+        self.currencies.get(stock_name).map_or(BASE_CURRENCY, |c| c.as_str())
+    }
+
+    // Settled plus unsettled cash in one currency
+    pub fn balance(&self, currency: &str) -> Amount {
+        self.cash.get(currency).copied().unwrap_or_default() + self.unsettled_cash.get(currency).copied().unwrap_or_default()
+    }
+
+    // Every currency the account has touched, base currency first
+    pub fn balance_currencies(&self) -> Vec<String> {
+        let mut currencies: Vec<String> = self.cash.keys().chain(self.unsettled_cash.keys()).cloned().collect();
+        currencies.sort_by_key(|c| (c != BASE_CURRENCY, c.clone()));
+        currencies.dedup();
+        currencies
+    }
+
+    // Settled cash in, or out when negative
+    pub fn credit(&mut self, currency: &str, amount: Amount) {
+        *self.cash.entry(currency.to_string()).or_default() += amount;
+    }
+
+    // Cash accounts cannot borrow, in a foreign currency or any other, so a purchase that
+    // takes `currency` below zero is funded by buying the shortfall with base currency
+    // at `fx`. Returns the foreign and base amounts exchanged.
+    pub fn fund_in(&mut self, currency: &str, fx: &FxRates) -> Option<(Amount, Amount)> {
+        let shortfall = -self.balance(currency);
+        if self.account_type != AccountType::Cash || currency == BASE_CURRENCY || shortfall <= Amount::ZERO {
+            return None;
+        }
+        let cost = fx.convert(shortfall, currency, BASE_CURRENCY)?;
+        self.credit(currency, shortfall);
+        self.credit(BASE_CURRENCY, -cost);
+        Some((shortfall, cost))
+    }
+
+    fn credit_unsettled(&mut self, currency: &str, amount: Amount) {
+        *self.unsettled_cash.entry(currency.to_string()).or_default() += amount;
+    }
+
+    // A fill only changes unsettled balances; settlement moves them over later
+    pub fn apply_fill(&mut self, stock_name: &str, side: Side, price: Price, quantity: Quantity) {
+        let notional = price.notional(quantity);
+        let currency = self.currency(stock_name).to_string();
+        let position = self.unsettled_positions.entry(stock_name.to_string()).or_insert(0);
+        match side {
+            Side::Buy => {
+                *position += quantity.value() as i64;
+                self.credit_unsettled(&currency, -notional);
+            }
+            Side::Sell => {
+                *position -= quantity.value() as i64;
+                self.credit_unsettled(&currency, notional);
+            }
+        }
+    }
+
+    // Option premium changes hands on the trade date; positions are in contracts
+    pub fn apply_option_fill(&mut self, symbol: &str, side: Side, premium: Price, contracts: Quantity, multiplier: u64) {
+        let cash = premium.notional(contracts) * multiplier as i64;
+        let currency = self.currency(symbol).to_string();
+        let position = self.positions.entry(symbol.to_string()).or_insert(0);
+        match side {
+            Side::Buy => {
+                *position += contracts.value() as i64;
+                self.credit(&currency, -cash);
+            }
+            Side::Sell => {
+                *position -= contracts.value() as i64;
+                self.credit(&currency, cash);
+            }
+        }
+    }
+
+    // Shares received (buy) or delivered (sell) other than in a trade, such as in an ETF
+    // creation. Like a fill this only changes unsettled positions.
+    pub fn apply_transfer(&mut self, symbol: &str, side: Side, quantity: Quantity) {
+        let shares = quantity.value() as i64;
+        *self.unsettled_positions.entry(symbol.to_string()).or_insert(0) += match side {
+            Side::Buy => shares,
+            Side::Sell => -shares,
+        };
+    }
+
+    // Expired or exercised contracts leave the account; returns the position they had
+    pub fn close_option(&mut self, symbol: &str) -> i64 {
+        self.positions.remove(symbol).unwrap_or(0)
+    }
+
+    // Futures fill against the last settlement price: the difference is paid now and
+    // the move from there comes with the next variation margin
+    pub fn apply_future_fill(&mut self, symbol: &str, side: Side, price: Price, contracts: Quantity, multiplier: u64, settlement_price: Price) {
+        let difference = (settlement_price - price).notional(contracts) * multiplier as i64;
+        let currency = self.currency(symbol).to_string();
+        let position = self.futures.entry(symbol.to_string()).or_insert(0);
+        match side {
+            Side::Buy => {
+                *position += contracts.value() as i64;
+                self.credit(&currency, difference);
+            }
+            Side::Sell => {
+                *position -= contracts.value() as i64;
+                self.credit(&currency, -difference);
+            }
+        }
+    }
+
+    // Daily variation margin on the whole position for the move from the `previous`
+    // settlement price to `price`; returns the cash paid in, or out when negative
+    pub fn settle_variation(&mut self, symbol: &str, price: Price, previous: Price, multiplier: u64) -> Amount {
+        let variation = (price - previous).position_value(self.futures_position(symbol)) * multiplier as i64;
+        let currency = self.currency(symbol).to_string();
+        self.credit(&currency, variation);
+        variation
+    }
+
+    pub fn futures_position(&self, symbol: &str) -> i64 {
+        self.futures.get(symbol).copied().unwrap_or(0)
+    }
+
+    // Expired futures leave the account; returns the position they had
+    pub fn close_future(&mut self, symbol: &str) -> i64 {
+        self.futures.remove(symbol).unwrap_or(0)
+    }
+
+    // Deliveries need settled or borrowed shares; returns the shortfall if there are not enough
+    pub fn settle(&mut self, obligation: &Obligation) -> Result<(), Quantity> {
+        let shares = obligation.quantity.value() as i64;
+        let settled = self.positions.get(&obligation.stock_name).copied().unwrap_or(0);
+        let borrowed = self.borrowed.get(&obligation.stock_name).copied().unwrap_or(0) as i64;
+        let (shares, cash) = match obligation.side {
+            Side::Buy => (shares, -obligation.cash - obligation.fees),
+            Side::Sell => {
+                let deliverable = settled.max(0) + borrowed;
+                if deliverable < shares {
+                    return Err(Quantity::new((shares - deliverable) as u64));
+                }
+                (-shares, obligation.cash - obligation.fees)
+            }
+        };
+        *self.positions.entry(obligation.stock_name.clone()).or_insert(0) += shares;
+        *self.unsettled_positions.entry(obligation.stock_name.clone()).or_insert(0) -= shares;
+        let currency = self.currency(&obligation.stock_name).to_string();
+        self.credit(&currency, cash);
+        self.credit_unsettled(&currency, -cash);
+        Ok(())
+    }
+
+    // Forced purchase to return recalled borrow; the shares go straight back to the lender
+    pub fn buy_in(&mut self, stock_name: &str, price: Price, quantity: Quantity) {
+        *self.positions.entry(stock_name.to_string()).or_insert(0) += quantity.value() as i64;
+        let currency = self.currency(stock_name).to_string();
+        self.credit(&currency, -price.notional(quantity));
+        if let Some(borrowed) = self.borrowed.get_mut(stock_name) {
+            *borrowed = borrowed.saturating_sub(quantity.value());
+            if *borrowed == 0 {
+                self.borrowed.remove(stock_name);
+            }
+        }
+    }
+
+    pub fn rename_stock(&mut self, old: &str, new: &str) {
+        for positions in [&mut self.positions, &mut self.unsettled_positions] {
+            if let Some(position) = positions.remove(old) {
+                positions.insert(new.to_string(), position);
+            }
+        }
+        if let Some(borrowed) = self.borrowed.remove(old) {
+            self.borrowed.insert(new.to_string(), borrowed);
+        }
+        if let Some(currency) = self.currencies.remove(old) {
+            self.currencies.insert(new.to_string(), currency);
+        }
+    }
+
+    // Scale holdings for a `new_shares` for `old_shares` split. Only whole shares of the net
+    // position are kept; the fraction is paid out in cash at the post-split `price`, and
+    // returned. Unsettled shares scale like the obligations behind them and settled shares take the rest.
+    pub fn split(&mut self, stock_name: &str, new_shares: u64, old_shares: u64, price: Price) -> Amount {
+        let (new_shares, old_shares) = (new_shares as i64, old_shares as i64);
+        let scaled = self.position(stock_name) * new_shares;
+        if let Some(unsettled) = self.unsettled_positions.get_mut(stock_name) {
+            *unsettled = *unsettled * new_shares / old_shares;
+        }
+        let unsettled = self.unsettled_positions.get(stock_name).copied().unwrap_or(0);
+        let settled = scaled / old_shares - unsettled;
+        if settled != 0 || self.positions.contains_key(stock_name) {
+            self.positions.insert(stock_name.to_string(), settled);
+        }
+        let fractions = scaled % old_shares;
+        if let Some(borrowed) = self.borrowed.get_mut(stock_name) {
+            *borrowed = *borrowed * new_shares as u64 / old_shares as u64;
+        }
+        let cash_in_lieu = price.position_value(fractions).scale(1.0 / old_shares as f64);
+        let currency = self.currency(stock_name).to_string();
+        self.credit(&currency, cash_in_lieu);
+        cash_in_lieu
+    }
+
+    // Any position, settled or not, in a stock, option or future
+    pub fn holds(&self, symbol: &str) -> bool {
+        self.position(symbol) != 0 || self.futures.get(symbol).is_some_and(|&contracts| contracts != 0)
+    }
+
+    // Settled plus unsettled shares
+    pub fn position(&self, stock_name: &str) -> i64 {
+        self.positions.get(stock_name).copied().unwrap_or(0) + self.unsettled_positions.get(stock_name).copied().unwrap_or(0)
+    }
+
+    pub fn monthly_volume(&self, date: NaiveDate) -> u64 {
+        if self.volume_month == Some((date.year(), date.month())) {
+            self.monthly_volume
+        } else {
+            0
+        }
+    }
+
+    // Fees are charged in the currency the stock trades in
+    pub fn charge_fees(&mut self, charge: FeeCharge) {
+        let currency = self.currency(&charge.stock_name).to_string();
+        self.credit_unsettled(&currency, -charge.fees.total());
+        self.monthly_volume = self.monthly_volume(charge.date) + charge.quantity.value();
+        self.volume_month = Some((charge.date.year(), charge.date.month()));
+        self.fee_charges.push(charge);
+    }
+
+    pub fn fees_on(&self, date: NaiveDate, currency: &str) -> FeeBreakdown {
+        let mut total = FeeBreakdown::default();
+        for charge in self.fee_charges.iter().filter(|c| c.date == date && self.currency(&c.stock_name) == currency) {
+            total += charge.fees;
+        }
+        total
+    }
+
+    // Every fill of the day with its fees broken down, then the day's totals
+    pub fn statement(&self, date: NaiveDate) -> Vec<String> {
+        let mut lines: Vec<String> = self.fee_charges.iter()
+            .filter(|c| c.date == date)
+            .map(|c| format!("  {} {} {} @ {:.2} {} ({}): per share {:.2}, notional {:.2}, minimum {:.2}, exchange {:+.2}, total {:.2}",
+            c.side.as_str(), c.quantity, c.stock_name, c.price, self.currency(&c.stock_name), c.liquidity.as_str(), c.fees.per_share,
+            c.fees.percentage, c.fees.minimum_top_up, c.fees.exchange_fee, c.fees.total()))
+            .collect();
+        let mut currencies: Vec<&str> = self.fee_charges.iter().filter(|c| c.date == date).map(|c| self.currency(&c.stock_name)).collect();
+        currencies.sort();
+        currencies.dedup();
+        if currencies.is_empty() {
+            currencies.push(BASE_CURRENCY);
+        }
+        for currency in currencies {
+            let total = self.fees_on(date, currency);
+            lines.push(format!("  FEES {}: commission {:.2}, exchange {:+.2}, total {:.2}", currency, total.commission(), total.exchange_fee,
+            total.total()));
+        }
+        lines
+    }
+
+    // Valued on trade date in the base currency: unsettled shares count as much as settled ones.
+    // Option contracts are valued at whatever `prices` holds for one contract.
+    pub fn market_value(&self, prices: &HashMap<String, Price>, fx: &FxRates) -> Amount {
+        prices.iter()
+            .map(|(name, price)| fx.to_base(price.position_value(self.position(name)), self.currency(name)))
+            .sum()
+    }
+
+    // All cash, settled and unsettled, in the base currency
+    pub fn cash_value(&self, fx: &FxRates) -> Amount {
+        self.balance_currencies().iter()
+            .map(|currency| fx.to_base(self.balance(currency), currency))
+            .sum()
+    }
+
+    // FX moves show up here as well as price moves
+    pub fn equity(&self, prices: &HashMap<String, Price>, fx: &FxRates) -> Amount {
+        self.cash_value(fx) + self.market_value(prices, fx)
+    }
+
+    // Mark positions at the given prices and rates and return (equity, P&L since the last mark)
+    pub fn mark_to_market(&mut self, prices: &HashMap<String, Price>, fx: &FxRates) -> (Amount, Amount) {
+        let equity = self.equity(prices, fx);
+        let pnl = equity - self.last_marked_equity;
+        self.last_marked_equity = equity;
+        (equity, pnl)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reverse_split_pays_fractional_shares_in_cash() {
+        let mut account = Account::new(0, &[Stock::new("TEST", 10.0)], AccountType::Margin, &FxRates::default());
+        account.apply_fill("TEST", Side::Buy, Price::from_f64(10.0), Quantity::new(5));
+        // 1005 shares make exactly 335: the thirds left over from 1000 and from 5 are one more whole share
+        let cash_in_lieu = account.split("TEST", 1, 3, Price::from_f64(30.0));
+        assert_eq!(cash_in_lieu, Amount::ZERO);
+        assert_eq!(account.positions["TEST"], 334);
+        assert_eq!(account.unsettled_positions["TEST"], 1);
+        assert_eq!(account.position("TEST"), 335);
+
+        account.apply_fill("TEST", Side::Buy, Price::from_f64(30.0), Quantity::new(2));
+        let cash_in_lieu = account.split("TEST", 1, 3, Price::from_f64(90.0));
+        assert_eq!(cash_in_lieu, Amount::from_units(30));
+        assert_eq!(account.position("TEST"), 112);
+    }
+
+    #[test]
+    fn split_pays_the_fraction_of_the_net_position() {
+        let mut account = Account::new(0, &[Stock::new("TEST", 10.0)], AccountType::Margin, &FxRates::default());
+        account.apply_fill("TEST", Side::Sell, Price::from_f64(10.0), Quantity::new(5));
+        let cash = account.balance(BASE_CURRENCY);
+        // 1000 settled less 5 unsettled is 995, or 99 and a half shares after a 1-for-10
+        let cash_in_lieu = account.split("TEST", 1, 10, Price::from_f64(100.0));
+        assert_eq!(cash_in_lieu, Amount::from_units(50));
+        assert_eq!(account.positions["TEST"], 99);
+        assert_eq!(account.unsettled_positions["TEST"], 0);
+        assert_eq!(account.balance(BASE_CURRENCY), cash + Amount::from_units(50));
+    }
+
+    #[test]
+    fn cash_account_buys_foreign_currency_it_spends() {
+        let fx = FxRates::default();
+        let mut cash = Account::new(0, &[Stock::new("TEST", 100.0)], AccountType::Cash, &fx);
+        let mut margin = Account::new(1, &[Stock::new("TEST", 100.0)], AccountType::Margin, &fx);
+        for account in [&mut cash, &mut margin] {
+            account.set_currency("TEST", "EUR");
+            account.apply_fill("TEST", Side::Buy, Price::from_f64(100.0), Quantity::new(10));
+        }
+        let cost = fx.convert(Amount::from_units(1000), "EUR", BASE_CURRENCY).unwrap();
+        assert_eq!(cash.fund_in("EUR", &fx), Some((Amount::from_units(1000), cost)));
+        assert_eq!(cash.balance("EUR"), Amount::ZERO);
+        assert_eq!(cash.balance(BASE_CURRENCY), INITIAL_CASH - cost);
+        assert_eq!(cash.fund_in("EUR", &fx), None);
+        // Margin accounts may carry a foreign currency debit
+        assert_eq!(margin.fund_in("EUR", &fx), None);
+        assert_eq!(margin.balance("EUR"), -Amount::from_units(1000));
+    }
+}
@@ -0,0 +1,238 @@
+use chrono::NaiveDateTime;
+use std::collections::HashMap;
+
+use crate::order::Side;
+use crate::price::{Amount, Price, Quantity};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpreadStats {
+    pub samples: usize,
+    pub average: Price,
+    pub min: Price,
+    pub max: Price,
+    pub last: Price,
+}
+
+// Everything the analytics know about one symbol at a point in time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SymbolStats {
+    pub trades: usize,
+    pub volume: Quantity,
+    pub vwap: Option<Price>,
+    pub twap: Option<Price>,
+    // Square root of the summed squared log returns between trades
+    pub realized_volatility: f64,
+    pub spread: Option<SpreadStats>,
+    // (buyer-initiated - seller-initiated) / total aggressor volume, from -1 to 1
+    pub order_flow_imbalance: Option<f64>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct SymbolAnalytics {
+    trades: usize,
+    volume: u64,
+    notional: Amount,
+    // Each trade price is held until the next trade on the same day
+    last_trade: Option<(Price, NaiveDateTime)>,
+    time_weighted_sum: f64,
+    time_weighted_seconds: f64,
+    squared_returns: f64,
+    spread_samples: usize,
+    spread_sum: i64,
+    spread_min: Option<Price>,
+    spread_max: Option<Price>,
+    spread_last: Option<Price>,
+    buy_volume: u64,
+    sell_volume: u64,
+}
+
+impl SymbolAnalytics {
+    fn on_trade(&mut self, price: Price, quantity: Quantity, aggressor: Option<Side>, time: NaiveDateTime) {
+        if let Some((last_price, last_time)) = self.last_trade {
+            if last_time.date() == time.date() {
+                let seconds = (time - last_time).num_milliseconds().max(0) as f64 / 1000.0;
+                self.time_weighted_sum += last_price.to_f64() * seconds;
+                self.time_weighted_seconds += seconds;
+            }
+            if last_price > Price::ZERO && price > Price::ZERO {
+                let log_return = (price.to_f64() / last_price.to_f64()).ln();
+                self.squared_returns += log_return * log_return;
+            }
+        }
+        self.last_trade = Some((price, time));
+        self.trades += 1;
+        self.volume += quantity.value();
+        self.notional += price.notional(quantity);
+        match aggressor {
+            Some(Side::Buy) => self.buy_volume += quantity.value(),
+            Some(Side::Sell) => self.sell_volume += quantity.value(),
+            None => {}
+        }
+    }
+
+    // Restate everything in post-split shares, so the first trade after the split is no jump
+    fn split(&mut self, new_shares: u64, old_shares: u64) {
+        let factor = old_shares as f64 / new_shares as f64;
+        self.volume = self.volume * new_shares / old_shares;
+        self.buy_volume = self.buy_volume * new_shares / old_shares;
+        self.sell_volume = self.sell_volume * new_shares / old_shares;
+        self.last_trade = self.last_trade.map(|(price, time)| (price.scale(factor), time));
+        self.time_weighted_sum *= factor;
+        self.spread_sum = (self.spread_sum as f64 * factor).round() as i64;
+        self.spread_min = self.spread_min.map(|spread| spread.scale(factor));
+        self.spread_max = self.spread_max.map(|spread| spread.scale(factor));
+        self.spread_last = self.spread_last.map(|spread| spread.scale(factor));
+    }
+
+    // Only two-sided quotes have a spread
+    fn on_quote(&mut self, best_bid: Option<Price>, best_ask: Option<Price>) {
+        let (Some(bid), Some(ask)) = (best_bid, best_ask) else {
+            return;
+        };
+        let spread = ask - bid;
+        self.spread_samples += 1;
+        self.spread_sum += spread.raw();
+        self.spread_min = Some(self.spread_min.map_or(spread, |min| min.min(spread)));
+        self.spread_max = Some(self.spread_max.map_or(spread, |max| max.max(spread)));
+        self.spread_last = Some(spread);
+    }
+
+    fn stats(&self) -> SymbolStats {
+        let vwap = (self.volume > 0).then(|| Price::from_f64(self.notional.to_f64() / self.volume as f64));
+        // Until time has passed between two trades the TWAP is just the last price
+        let twap = if self.time_weighted_seconds > 0.0 {
+            Some(Price::from_f64(self.time_weighted_sum / self.time_weighted_seconds))
+        } else {
+            self.last_trade.map(|(price, _)| price)
+        };
+        let spread = match (self.spread_min, self.spread_max, self.spread_last) {
+            (Some(min), Some(max), Some(last)) => Some(SpreadStats {
+                samples: self.spread_samples,
+                average: Price::from_raw(self.spread_sum / self.spread_samples as i64),
+                min,
+                max,
+                last,
+            }),
+            _ => None,
+        };
+        let aggressor_volume = self.buy_volume + self.sell_volume;
+        let order_flow_imbalance = (aggressor_volume > 0)
+            .then(|| (self.buy_volume as f64 - self.sell_volume as f64) / aggressor_volume as f64);
+        SymbolStats {
+            trades: self.trades,
+            volume: Quantity::new(self.volume),
+            vwap,
+            twap,
+            realized_volatility: self.squared_returns.sqrt(),
+            spread,
+            order_flow_imbalance,
+        }
+    }
+}
+
+// Running per-symbol statistics over the trade and quote stream
+#[derive(Debug, Clone, Default)]
+pub struct Analytics {
+    symbols: HashMap<String, SymbolAnalytics>,
+}
+
+impl Analytics {
+    pub fn new() -> Self {
+        Analytics::default()
+    }
+
+    pub fn on_trade(&mut self, symbol: &str, price: Price, quantity: Quantity, aggressor: Option<Side>, time: NaiveDateTime) {
+        self.symbols.entry(symbol.to_string()).or_default().on_trade(price, quantity, aggressor, time);
+    }
+
+    pub fn on_quote(&mut self, symbol: &str, best_bid: Option<Price>, best_ask: Option<Price>) {
+        self.symbols.entry(symbol.to_string()).or_default().on_quote(best_bid, best_ask);
+    }
+
+    pub fn stats(&self, symbol: &str) -> Option<SymbolStats> {
+        self.symbols.get(symbol).map(|s| s.stats())
+    }
+
+    pub fn split(&mut self, symbol: &str, new_shares: u64, old_shares: u64) {
+        if let Some(analytics) = self.symbols.get_mut(symbol) {
+            analytics.split(new_shares, old_shares);
+        }
+    }
+
+    pub fn rename(&mut self, old: &str, new: &str) {
+        if let Some(analytics) = self.symbols.remove(old) {
+            self.symbols.insert(new.to_string(), analytics);
+        }
+    }
+
+    // One report line per symbol
+    pub fn report(&self, symbol: &str) -> String {
+        let Some(stats) = self.stats(symbol) else {
+            return format!("{}: no activity", symbol);
+        };
+        let price = |p: Option<Price>| p.map_or("-".to_string(), |p| format!("${:.2}", p));
+        let spread = stats.spread.map_or("-".to_string(), |s| format!("avg ${:.2} (min ${:.2}, max ${:.2})", s.average, s.min, s.max));
+        let imbalance = stats.order_flow_imbalance.map_or("-".to_string(), |i| format!("{:+.2}", i));
+        format!("{}: {} trades, volume {}, VWAP {}, TWAP {}, realized vol {:.2}%, spread {}, order flow {}", symbol,
+        stats.trades, stats.volume, price(stats.vwap), price(stats.twap), stats.realized_volatility * 100.0, spread, imbalance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn trades_and_quotes_build_the_statistics() {
+        let time = NaiveDate::from_ymd_opt(2024, 6, 3).unwrap().and_hms_opt(10, 0, 0).unwrap();
+        let mut analytics = Analytics::new();
+        assert!(analytics.stats("TEST").is_none());
+        analytics.on_trade("TEST", Price::from_f64(100.0), Quantity::new(10), Some(Side::Buy), time);
+        analytics.on_trade("TEST", Price::from_f64(110.0), Quantity::new(30), Some(Side::Sell), time + chrono::Duration::seconds(30));
+        // Auction trades have no aggressor
+        analytics.on_trade("TEST", Price::from_f64(100.0), Quantity::new(20), None, time + chrono::Duration::seconds(90));
+        analytics.on_quote("TEST", Some(Price::from_f64(99.0)), Some(Price::from_f64(101.0)));
+        analytics.on_quote("TEST", Some(Price::from_f64(98.0)), Some(Price::from_f64(100.0)));
+        analytics.on_quote("TEST", Some(Price::from_f64(100.0)), Some(Price::from_f64(101.0)));
+        analytics.on_quote("TEST", None, Some(Price::from_f64(101.0)));
+
+        let stats = analytics.stats("TEST").unwrap();
+        assert_eq!((stats.trades, stats.volume), (3, Quantity::new(60)));
+        assert_eq!(stats.vwap, Some(Price::from_f64(105.0)));
+        // 100 held for 30 seconds, then 110 for 60
+        assert!((stats.twap.unwrap().to_f64() - 9600.0 / 90.0).abs() < 0.01);
+        assert!((stats.realized_volatility - 1.1f64.ln() * 2f64.sqrt()).abs() < 1e-12);
+        assert_eq!(stats.order_flow_imbalance, Some(-0.5));
+        let spread = stats.spread.unwrap();
+        assert_eq!((spread.samples, spread.min, spread.max, spread.last), (3, Price::from_f64(1.0), Price::from_f64(2.0), Price::from_f64(1.0)));
+        assert!((spread.average.to_f64() - 5.0 / 3.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn overnight_gaps_do_not_weight_the_twap() {
+        let time = NaiveDate::from_ymd_opt(2024, 6, 3).unwrap().and_hms_opt(15, 0, 0).unwrap();
+        let mut analytics = Analytics::new();
+        analytics.on_trade("TEST", Price::from_f64(100.0), Quantity::new(10), None, time);
+        analytics.on_trade("TEST", Price::from_f64(120.0), Quantity::new(10), None, time + chrono::Duration::hours(18));
+        assert_eq!(analytics.stats("TEST").unwrap().twap, Some(Price::from_f64(120.0)));
+    }
+
+    #[test]
+    fn split_restates_history_without_a_return() {
+        let time = NaiveDate::from_ymd_opt(2024, 6, 3).unwrap().and_hms_opt(10, 0, 0).unwrap();
+        let mut analytics = Analytics::new();
+        analytics.on_trade("TEST", Price::from_f64(100.0), Quantity::new(10), Some(Side::Buy), time);
+        analytics.on_quote("TEST", Some(Price::from_f64(99.0)), Some(Price::from_f64(101.0)));
+        analytics.split("TEST", 2, 1);
+        analytics.on_trade("TEST", Price::from_f64(50.0), Quantity::new(20), Some(Side::Sell), time + chrono::Duration::seconds(10));
+
+        let stats = analytics.stats("TEST").unwrap();
+        assert_eq!(stats.realized_volatility, 0.0);
+        assert_eq!(stats.volume, Quantity::new(40));
+        assert_eq!(stats.vwap, Some(Price::from_f64(50.0)));
+        assert_eq!(stats.twap, Some(Price::from_f64(50.0)));
+        assert_eq!(stats.spread.unwrap().last, Price::from_f64(1.0));
+        assert_eq!(stats.order_flow_imbalance, Some(0.0));
+    }
+}
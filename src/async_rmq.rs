@@ -0,0 +1,42 @@
+use lapin::{BasicProperties, Channel, Connection, ConnectionProperties, Consumer, ExchangeKind, Result};
+use lapin::options::{BasicConsumeOptions, BasicPublishOptions, BasicQosOptions, ExchangeDeclareOptions, QueueDeclareOptions};
+use lapin::types::FieldTable;
+
+use crate::rmq::AMQP_URL;
+
+// Deliveries a consumer holds before acknowledging any, so it is never left waiting on the server
+pub const PREFETCH_COUNT: u16 = 100;
+
+// Unlike the blocking client, which connects for every message, the async mode keeps
+// one connection for the whole run and every task opens channels on it.
+pub async fn connect() -> Result<Connection> {
+    Connection::connect(AMQP_URL, ConnectionProperties::default()).await
+}
+
+// A channel for sending to or consuming from `queue_name`, which is declared first so
+// nothing sent before the consumer starts is dropped
+pub async fn open_channel(connection: &Connection, queue_name: &str) -> Result<Channel> {
+    let channel = connection.create_channel().await?;
+    channel.queue_declare(queue_name, QueueDeclareOptions::default(), FieldTable::default()).await?;
+    Ok(channel)
+}
+
+// A channel for publishing on the topic exchange `exchange_name`, declared the same
+// way the blocking client declares it
+pub async fn open_exchange(connection: &Connection, exchange_name: &str) -> Result<Channel> {
+    let channel = connection.create_channel().await?;
+    channel.exchange_declare(exchange_name, ExchangeKind::Topic, ExchangeDeclareOptions::default(), FieldTable::default()).await?;
+    Ok(channel)
+}
+
+pub async fn publish(channel: &Channel, msg: String, exchange_name: &str, routing_key: &str) -> Result<()> {
+    channel.basic_publish(exchange_name, routing_key, BasicPublishOptions::default(), msg.as_bytes(), BasicProperties::default()).await?;
+    Ok(())
+}
+
+// Start a consumer; deliveries must be acknowledged, and any still unacknowledged go
+// back on the queue when the channel closes.
+pub async fn consume(channel: &Channel, queue_name: &str, consumer_tag: &str) -> Result<Consumer> {
+    channel.basic_qos(PREFETCH_COUNT, BasicQosOptions::default()).await?;
+    channel.basic_consume(queue_name, consumer_tag, BasicConsumeOptions::default(), FieldTable::default()).await
+}
@@ -0,0 +1,125 @@
+use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use serde_json::to_string;
+use lapin::{Channel, Connection};
+
+use crate::async_rmq;
+use crate::etf::{CreationAction, CreationRequest, Etf};
+use crate::market_data::{MarketSnapshot, MarketDataSubscriber};
+use crate::rmq::{publish, ORDER_EXCHANGE};
+use crate::shard::routing_key;
+
+pub const NUM_AUTHORIZED_PARTICIPANTS: usize = 1;
+// How far the ETF has to trade from its iNAV before creating or redeeming pays
+pub const ARBITRAGE_THRESHOLD: f64 = 0.005;
+pub const PARTICIPANT_INTERVAL: Duration = Duration::from_millis(1000);
+
+// Arbitrages ETFs against their baskets. At a premium it creates a unit from
+// a freshly bought basket and sells the new ETF shares; at a discount
+// it buys ETF shares, redeems them and sells the basket. The broker trades the
+// legs together with the swap, so one half never goes out without the other.
+pub struct AuthorizedParticipant {
+    id: usize,
+    market_data: MarketDataSubscriber,
+    etfs: Vec<Etf>,
+    stop_signal: Arc<AtomicBool>,
+}
+
+impl AuthorizedParticipant {
+    pub fn new(id: usize, market_data: MarketDataSubscriber, etfs: Vec<Etf>, stop_signal: Arc<AtomicBool>) -> Self {
+        AuthorizedParticipant { id, market_data, etfs, stop_signal }
+    }
+
+    fn run(&self) {
+        loop {
+            thread::sleep(PARTICIPANT_INTERVAL);
+            if self.stop_signal.load(Ordering::SeqCst) {
+                break;
+            }
+            for (symbol, request) in self.requests() {
+                if let Err(e) = publish(request, ORDER_EXCHANGE, &routing_key(&symbol)) {
+                    eprintln!("Authorized participant {}: Failed to send: {}", self.id + 1, e);
+                }
+            }
+        }
+        println!("Authorized participant {} is now stopping.", self.id + 1);
+    }
+
+    // The same as a task in the async mode, sending over `channel`
+    async fn run_async(&self, channel: &Channel) {
+        loop {
+            tokio::time::sleep(PARTICIPANT_INTERVAL).await;
+            if self.stop_signal.load(Ordering::SeqCst) {
+                break;
+            }
+            for (symbol, request) in self.requests() {
+                if let Err(e) = async_rmq::publish(channel, request, ORDER_EXCHANGE, &routing_key(&symbol)).await {
+                    eprintln!("Authorized participant {}: Failed to send: {}", self.id + 1, e);
+                }
+            }
+        }
+        println!("Authorized participant {} is now stopping.", self.id + 1);
+    }
+
+    // This round's creation and redemption requests, serialized, with their ETFs
+    fn requests(&self) -> Vec<(String, String)> {
+        let snapshot = self.market_data.snapshot();
+        self.etfs.iter().filter_map(|etf| self.arbitrage(etf, &snapshot)).collect()
+    }
+
+    fn arbitrage(&self, etf: &Etf, snapshot: &MarketSnapshot) -> Option<(String, String)> {
+        let &nav = snapshot.navs.get(&etf.symbol)?;
+        let quote = snapshot.quote(&etf.symbol);
+        let (action, price) = match (quote.best_bid, quote.best_ask) {
+            (Some(bid), _) if bid.to_f64() > nav.to_f64() * (1.0 + ARBITRAGE_THRESHOLD) => (CreationAction::Create, bid),
+            (_, Some(ask)) if ask.to_f64() < nav.to_f64() * (1.0 - ARBITRAGE_THRESHOLD) => (CreationAction::Redeem, ask),
+            _ => return None,
+        };
+        println!("\x1b[36mAUTHORIZED PARTICIPANT {}: {} at ${:.2} vs iNAV ${:.2} ({:+.2}%), {} one unit\x1b[0m", self.id + 1, etf.symbol, price, nav,
+        (price.to_f64() / nav.to_f64() - 1.0) * 100.0, action.as_str());
+
+        // The ETF leg trades no worse than the touch it saw
+        let request = CreationRequest { participant_id: self.id, etf: etf.symbol.clone(), action, units: 1, etf_limit: Some(price) };
+        match to_string(&request) {
+            Ok(request) => Some((etf.symbol.clone(), request)),
+            Err(e) => {
+                eprintln!("Authorized participant {}: Failed to serialize: {}", self.id + 1, e);
+                None
+            }
+        }
+    }
+}
+
+// Participants have accounts after the market maker's, from `first_id`
+pub fn authorized_participants(market_data: MarketDataSubscriber, etfs: Vec<Etf>, first_id: usize, stop_signal: Arc<AtomicBool>) -> Vec<AuthorizedParticipant> {
+    (first_id..first_id + NUM_AUTHORIZED_PARTICIPANTS)
+        .map(|id| AuthorizedParticipant::new(id, market_data.clone(), etfs.clone(), Arc::clone(&stop_signal)))
+        .collect()
+}
+
+pub fn start_authorized_participants(participants: Vec<AuthorizedParticipant>) -> Vec<JoinHandle<()>> {
+    participants.into_iter()
+        .map(|participant| thread::spawn(move || participant.run()))
+        .collect()
+}
+
+// The async mode's participants: tasks sending over one channel on the shared connection
+pub async fn run_authorized_participants(connection: &Connection, participants: Vec<AuthorizedParticipant>) {
+    let channel = match async_rmq::open_exchange(connection, ORDER_EXCHANGE).await {
+        Ok(channel) => channel,
+        Err(e) => {
+            eprintln!("Authorized participants: Failed to open a channel: {}", e);
+            return;
+        }
+    };
+    let handles: Vec<_> = participants.into_iter()
+        .map(|participant| {
+            let channel = channel.clone();
+            tokio::spawn(async move { participant.run_async(&channel).await })
+        })
+        .collect();
+    for handle in handles {
+        handle.await.unwrap();
+    }
+}
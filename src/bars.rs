@@ -0,0 +1,258 @@
+use serde::{Serialize, Deserialize};
+use chrono::{NaiveDateTime, Timelike};
+use std::collections::{HashMap, VecDeque};
+
+use crate::price::{Price, Quantity};
+
+// Completed bars kept per symbol and interval; older ones are dropped
+pub const BAR_HISTORY_LEN: usize = 500;
+pub const DEFAULT_BAR_INTERVALS: [BarInterval; 4] = [
+    BarInterval::ONE_SECOND,
+    BarInterval::ONE_MINUTE,
+    BarInterval::FIVE_MINUTES,
+    BarInterval::Daily,
+];
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BarInterval {
+    // Intraday bars aligned to multiples of this many seconds after midnight
+    Seconds(u32),
+    Daily,
+}
+
+impl BarInterval {
+    pub const ONE_SECOND: BarInterval = BarInterval::Seconds(1);
+    pub const ONE_MINUTE: BarInterval = BarInterval::Seconds(60);
+    pub const FIVE_MINUTES: BarInterval = BarInterval::Seconds(300);
+
+    pub fn name(&self) -> String {
+        match self {
+            BarInterval::Daily => "daily".to_string(),
+            BarInterval::Seconds(seconds) if seconds % 60 == 0 => format!("{}m", seconds / 60),
+            BarInterval::Seconds(seconds) => format!("{}s", seconds),
+        }
+    }
+
+    // Start of the bar a trade at `time` belongs to
+    pub fn bar_start(&self, time: NaiveDateTime) -> NaiveDateTime {
+        let midnight = time.date().and_hms_opt(0, 0, 0).unwrap();
+        match self {
+            BarInterval::Daily => midnight,
+            BarInterval::Seconds(seconds) => {
+                let seconds = (*seconds).max(1);
+                let elapsed = time.num_seconds_from_midnight() / seconds * seconds;
+                midnight + chrono::Duration::seconds(elapsed as i64)
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Bar {
+    pub interval: BarInterval,
+    pub start: NaiveDateTime,
+    pub open: Price,
+    pub high: Price,
+    pub low: Price,
+    pub close: Price,
+    pub volume: Quantity,
+    pub trades: usize,
+}
+
+impl Bar {
+    pub fn new(interval: BarInterval, start: NaiveDateTime, price: Price, quantity: Quantity) -> Self {
+        Bar { interval, start, open: price, high: price, low: price, close: price, volume: quantity, trades: 1 }
+    }
+
+    pub fn update(&mut self, price: Price, quantity: Quantity) {
+        self.high = self.high.max(price);
+        self.low = self.low.min(price);
+        self.close = price;
+        self.volume += quantity;
+        self.trades += 1;
+    }
+
+    // The same bar in post-split prices and shares
+    pub fn split(&mut self, new_shares: u64, old_shares: u64) {
+        let factor = old_shares as f64 / new_shares as f64;
+        self.open = self.open.scale(factor);
+        self.high = self.high.scale(factor);
+        self.low = self.low.scale(factor);
+        self.close = self.close.scale(factor);
+        self.volume = Quantity::new(self.volume.value() * new_shares / old_shares);
+    }
+}
+
+// Bars of one interval for one symbol: the one being built and a rolling history
+#[derive(Debug, Clone)]
+pub struct BarSeries {
+    pub interval: BarInterval,
+    current: Option<Bar>,
+    completed: VecDeque<Bar>,
+    history_len: usize,
+}
+
+impl BarSeries {
+    pub fn new(interval: BarInterval, history_len: usize) -> Self {
+        BarSeries { interval, current: None, completed: VecDeque::new(), history_len }
+    }
+
+    // Returns the bar that was completed by this trade, if it opened a new one
+    pub fn on_trade(&mut self, price: Price, quantity: Quantity, time: NaiveDateTime) -> Option<Bar> {
+        let start = self.interval.bar_start(time);
+        match self.current.as_mut() {
+            Some(bar) if bar.start == start => {
+                bar.update(price, quantity);
+                None
+            }
+            _ => {
+                let finished = self.close();
+                self.current = Some(Bar::new(self.interval, start, price, quantity));
+                finished
+            }
+        }
+    }
+
+    // Finish the bar being built, e.g. when the session ends
+    pub fn close(&mut self) -> Option<Bar> {
+        let bar = self.current.take()?;
+        self.completed.push_back(bar);
+        while self.completed.len() > self.history_len {
+            self.completed.pop_front();
+        }
+        Some(bar)
+    }
+
+    pub fn current(&self) -> Option<Bar> {
+        self.current
+    }
+
+    pub fn split(&mut self, new_shares: u64, old_shares: u64) {
+        for bar in self.current.iter_mut().chain(self.completed.iter_mut()) {
+            bar.split(new_shares, old_shares);
+        }
+    }
+
+    // Completed bars oldest first, followed by the one being built
+    pub fn bars(&self) -> Vec<Bar> {
+        self.completed.iter().copied().chain(self.current).collect()
+    }
+}
+
+// Builds bars at every configured interval from the trades of each symbol
+#[derive(Debug, Clone)]
+pub struct BarAggregator {
+    pub intervals: Vec<BarInterval>,
+    pub history_len: usize,
+    series: HashMap<String, Vec<BarSeries>>,
+}
+
+impl Default for BarAggregator {
+    fn default() -> Self {
+        BarAggregator::new(DEFAULT_BAR_INTERVALS.to_vec(), BAR_HISTORY_LEN)
+    }
+}
+
+impl BarAggregator {
+    pub fn new(intervals: Vec<BarInterval>, history_len: usize) -> Self {
+        BarAggregator { intervals, history_len, series: HashMap::new() }
+    }
+
+    pub fn on_trade(&mut self, symbol: &str, price: Price, quantity: Quantity, time: NaiveDateTime) -> Vec<Bar> {
+        let intervals = &self.intervals;
+        let history_len = self.history_len;
+        self.series.entry(symbol.to_string())
+            .or_insert_with(|| intervals.iter().map(|&i| BarSeries::new(i, history_len)).collect())
+            .iter_mut()
+            .filter_map(|series| series.on_trade(price, quantity, time))
+            .collect()
+    }
+
+    // Finish every bar in progress for a symbol
+    pub fn close(&mut self, symbol: &str) -> Vec<Bar> {
+        self.series.get_mut(symbol)
+            .map(|all| all.iter_mut().filter_map(|series| series.close()).collect())
+            .unwrap_or_default()
+    }
+
+    // History is restated so it lines up with the trades after the split
+    pub fn split(&mut self, symbol: &str, new_shares: u64, old_shares: u64) {
+        for series in self.series.get_mut(symbol).into_iter().flatten() {
+            series.split(new_shares, old_shares);
+        }
+    }
+
+    // History carries over to the new symbol
+    pub fn rename(&mut self, old: &str, new: &str) {
+        if let Some(series) = self.series.remove(old) {
+            self.series.insert(new.to_string(), series);
+        }
+    }
+
+    fn series(&self, symbol: &str, interval: BarInterval) -> Option<&BarSeries> {
+        self.series.get(symbol)?.iter().find(|s| s.interval == interval)
+    }
+
+    pub fn bars(&self, symbol: &str, interval: BarInterval) -> Vec<Bar> {
+        self.series(symbol, interval).map(|s| s.bars()).unwrap_or_default()
+    }
+
+    // The most recent bar, finished or not
+    pub fn latest(&self, symbol: &str, interval: BarInterval) -> Option<Bar> {
+        self.series(symbol, interval).and_then(|s| s.current().or_else(|| s.completed.back().copied()))
+    }
+
+    // The most recent finished bar
+    pub fn last_completed(&self, symbol: &str, interval: BarInterval) -> Option<Bar> {
+        self.series(symbol, interval).and_then(|s| s.completed.back().copied())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn at(hour: u32, minute: u32, second: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 6, 3).unwrap().and_hms_opt(hour, minute, second).unwrap()
+    }
+
+    #[test]
+    fn bars_start_on_interval_boundaries() {
+        assert_eq!(BarInterval::ONE_MINUTE.bar_start(at(10, 7, 59)), at(10, 7, 0));
+        assert_eq!(BarInterval::FIVE_MINUTES.bar_start(at(10, 9, 30)), at(10, 5, 0));
+        assert_eq!(BarInterval::Daily.bar_start(at(15, 59, 59)), at(0, 0, 0));
+        assert_eq!(BarInterval::FIVE_MINUTES.name(), "5m");
+        assert_eq!(BarInterval::Seconds(30).name(), "30s");
+    }
+
+    #[test]
+    fn a_trade_in_the_next_interval_completes_the_bar() {
+        let mut series = BarSeries::new(BarInterval::ONE_MINUTE, 2);
+        assert!(series.on_trade(Price::from_f64(10.0), Quantity::new(5), at(10, 0, 1)).is_none());
+        assert!(series.on_trade(Price::from_f64(9.0), Quantity::new(5), at(10, 0, 30)).is_none());
+        let bar = series.on_trade(Price::from_f64(11.0), Quantity::new(5), at(10, 1, 0)).unwrap();
+        assert_eq!((bar.start, bar.open, bar.low, bar.close, bar.volume, bar.trades), (at(10, 0, 0), Price::from_f64(10.0), Price::from_f64(9.0), Price::from_f64(9.0), Quantity::new(10), 2));
+
+        // Only the last `history_len` completed bars are kept, plus the one being built
+        series.on_trade(Price::from_f64(12.0), Quantity::new(5), at(10, 2, 0));
+        series.on_trade(Price::from_f64(13.0), Quantity::new(5), at(10, 3, 0));
+        let starts: Vec<NaiveDateTime> = series.bars().iter().map(|b| b.start).collect();
+        assert_eq!(starts, vec![at(10, 1, 0), at(10, 2, 0), at(10, 3, 0)]);
+    }
+
+    #[test]
+    fn split_restates_bars_in_post_split_prices() {
+        let time = NaiveDate::from_ymd_opt(2024, 6, 3).unwrap().and_hms_opt(10, 0, 0).unwrap();
+        let mut bars = BarAggregator::new(vec![BarInterval::Daily], BAR_HISTORY_LEN);
+        bars.on_trade("TEST", Price::from_f64(100.0), Quantity::new(10), time);
+        bars.on_trade("TEST", Price::from_f64(104.0), Quantity::new(10), time);
+        bars.split("TEST", 2, 1);
+        bars.on_trade("TEST", Price::from_f64(51.0), Quantity::new(20), time);
+
+        let bar = bars.latest("TEST", BarInterval::Daily).unwrap();
+        assert_eq!((bar.open, bar.high, bar.low, bar.close), (Price::from_f64(50.0), Price::from_f64(52.0), Price::from_f64(50.0), Price::from_f64(51.0)));
+        assert_eq!(bar.volume, Quantity::new(60));
+        assert_eq!(bar.trades, 3);
+    }
+}
@@ -0,0 +1,238 @@
+use rand::Rng;
+use std::collections::HashMap;
+
+use crate::account::Account;
+use crate::instrument::InstrumentRegistry;
+use crate::price::{Amount, Price, Quantity};
+
+// Borrow fees are quoted as an annual rate and charged per calendar day on this basis
+pub const BORROW_DAY_COUNT: f64 = 360.0;
+// Chance each day that lenders recall part of a stock's pool, and how much they take back
+pub const RECALL_PROBABILITY: f64 = 0.10;
+pub const RECALL_FRACTION: f64 = 0.5;
+
+// Shares lenders make available for shorting one stock
+#[derive(Debug, Clone, PartialEq)]
+pub struct BorrowPool {
+    pub total: u64,
+    // Annual fee rate on the value of borrowed shares
+    pub rate: f64,
+    // Borrowed against actual short positions at the last reconciliation
+    pub on_loan: u64,
+    // Reserved by locates today but not necessarily used yet
+    pub located: u64,
+}
+
+impl BorrowPool {
+    pub fn available(&self) -> u64 {
+        self.total.saturating_sub(self.on_loan + self.located)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BorrowFee {
+    pub trader_id: usize,
+    pub stock_name: String,
+    pub quantity: u64,
+    // Calendar days charged for
+    pub days: u32,
+    // In the stock's currency
+    pub amount: Amount,
+    pub currency: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BuyIn {
+    pub trader_id: usize,
+    pub stock_name: String,
+    pub quantity: Quantity,
+    pub price: Price,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BorrowDesk {
+    pools: HashMap<String, BorrowPool>,
+    // Located shares per (trader, stock) for today
+    locates: HashMap<(usize, String), u64>,
+}
+
+impl BorrowDesk {
+    pub fn new(registry: &InstrumentRegistry) -> Self {
+        let pools = registry.instruments().iter()
+            .map(|i| (i.symbol.clone(), BorrowPool { total: i.borrow_pool, rate: i.borrow_rate, on_loan: 0, located: 0 }))
+            .collect();
+        BorrowDesk { pools, locates: HashMap::new() }
+    }
+
+    pub fn pool(&self, stock_name: &str) -> Option<&BorrowPool> {
+        self.pools.get(stock_name)
+    }
+
+    pub fn located(&self, trader_id: usize, stock_name: &str) -> u64 {
+        self.locates.get(&(trader_id, stock_name.to_string())).copied().unwrap_or(0)
+    }
+
+    // Reserve borrow for a short sale of `needed` shares
+    pub fn locate(&mut self, trader_id: usize, stock_name: &str, needed: u64) -> Result<(), String> {
+        let pool = self.pools.get_mut(stock_name).ok_or_else(|| format!("no borrow for {}", stock_name))?;
+        if pool.available() < needed {
+            return Err(format!("locate failed: {} {} shares needed, {} available to borrow", needed, stock_name, pool.available()));
+        }
+        pool.located += needed;
+        *self.locates.entry((trader_id, stock_name.to_string())).or_insert(0) += needed;
+        Ok(())
+    }
+
+    // End of day: borrows follow the short positions that actually exist, covered
+    // shorts are returned to the pool and unused locates lapse
+    pub fn reconcile(&mut self, accounts: &mut HashMap<usize, Account>) {
+        self.locates.clear();
+        for (stock_name, pool) in self.pools.iter_mut() {
+            pool.located = 0;
+            pool.on_loan = 0;
+            for account in accounts.values_mut() {
+                let short = (-account.position(stock_name)).max(0) as u64;
+                if short > 0 {
+                    account.borrowed.insert(stock_name.clone(), short);
+                } else {
+                    account.borrowed.remove(stock_name);
+                }
+                pool.on_loan += short;
+            }
+        }
+    }
+
+    // Borrow fees for `days` calendar days on every open borrow, valued at `prices`
+    pub fn accrue_fees(&self, accounts: &mut HashMap<usize, Account>, prices: &HashMap<String, Price>, days: u32) -> Vec<BorrowFee> {
+        let mut fees = Vec::new();
+        for account in accounts.values_mut() {
+            let mut charged = Vec::new();
+            for (stock_name, &quantity) in account.borrowed.iter() {
+                let (Some(pool), Some(price)) = (self.pools.get(stock_name), prices.get(stock_name)) else {
+                    continue;
+                };
+                let amount = price.notional(Quantity::new(quantity)).scale(pool.rate * days as f64 / BORROW_DAY_COUNT);
+                let currency = account.currency(stock_name).to_string();
+                charged.push(BorrowFee { trader_id: account.trader_id, stock_name: stock_name.clone(), quantity, days, amount, currency });
+            }
+            for fee in &charged {
+                account.credit(&fee.currency, -fee.amount);
+            }
+            fees.extend(charged);
+        }
+        fees.sort_by_key(|f| (f.trader_id, f.stock_name.clone()));
+        fees
+    }
+
+    pub fn rename(&mut self, old: &str, new: &str) {
+        if let Some(pool) = self.pools.remove(old) {
+            self.pools.insert(new.to_string(), pool);
+        }
+    }
+
+    // Lenders hold the split shares too
+    pub fn split(&mut self, stock_name: &str, new_shares: u64, old_shares: u64) {
+        if let Some(pool) = self.pools.get_mut(stock_name) {
+            pool.total = pool.total * new_shares / old_shares;
+            pool.on_loan = pool.on_loan * new_shares / old_shares;
+        }
+    }
+
+    // Lenders take back part of a pool. Whatever is then lent out beyond the pool
+    // is bought in at `prices`, largest borrower first.
+    pub fn recall(&mut self, accounts: &mut HashMap<usize, Account>, prices: &HashMap<String, Price>) -> Vec<BuyIn> {
+        let mut rng = rand::thread_rng();
+        let mut buy_ins = Vec::new();
+        let mut names: Vec<String> = self.pools.keys().cloned().collect();
+        names.sort();
+        for stock_name in names {
+            let pool = self.pools.get_mut(&stock_name).unwrap();
+            if pool.on_loan == 0 || !rng.gen_bool(RECALL_PROBABILITY) {
+                continue;
+            }
+            let recalled = (pool.total as f64 * RECALL_FRACTION) as u64;
+            pool.total -= recalled;
+            println!("\x1b[33mRECALL: lenders recalled {} {} shares, {} left to borrow\x1b[0m", recalled, stock_name, pool.total);
+
+            let Some(&price) = prices.get(&stock_name) else {
+                continue;
+            };
+            let mut borrowers: Vec<(usize, u64)> = accounts.values()
+                .filter_map(|a| a.borrowed.get(&stock_name).map(|&q| (a.trader_id, q)))
+                .collect();
+            borrowers.sort_by_key(|&(id, quantity)| (std::cmp::Reverse(quantity), id));
+            for (trader_id, borrowed) in borrowers {
+                if pool.on_loan <= pool.total {
+                    break;
+                }
+                let quantity = borrowed.min(pool.on_loan - pool.total);
+                let account = accounts.get_mut(&trader_id).unwrap();
+                account.buy_in(&stock_name, price, Quantity::new(quantity));
+                pool.on_loan -= quantity;
+                buy_ins.push(BuyIn { trader_id, stock_name: stock_name.clone(), quantity: Quantity::new(quantity), price });
+            }
+        }
+        buy_ins
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use crate::account::{AccountType, INITIAL_CASH, INITIAL_SHARES};
+    use crate::calendar::TradingCalendar;
+    use crate::fx::FxRates;
+    use crate::instrument::INSTRUMENTS_FILE;
+    use crate::order::Side;
+
+    #[test]
+    fn locates_reserve_the_pool_until_reconciliation() {
+        let registry = InstrumentRegistry::load(INSTRUMENTS_FILE).unwrap();
+        let mut desk = BorrowDesk::new(&registry);
+        let mut accounts: HashMap<usize, Account> = (0..2)
+            .map(|id| (id, Account::new(id, &registry.stocks(), AccountType::Margin, &FxRates::default())))
+            .collect();
+
+        // NIKE lenders have 20,000 shares
+        desk.locate(0, "NIKE", 15_000).unwrap();
+        assert!(desk.locate(1, "NIKE", 6_000).unwrap_err().contains("5000 available"));
+        desk.locate(1, "NIKE", 5_000).unwrap();
+        assert_eq!(desk.pool("NIKE").unwrap().available(), 0);
+        assert!(desk.locate(0, "REEBOK", 1).is_err());
+
+        // Only trader 0 went short; the rest of the locates lapse at the end of the day
+        accounts.get_mut(&0).unwrap().apply_fill("NIKE", Side::Sell, Price::from_f64(100.0), Quantity::new(INITIAL_SHARES as u64 + 300));
+        desk.reconcile(&mut accounts);
+        assert_eq!(accounts[&0].borrowed.get("NIKE"), Some(&300));
+        assert!(accounts[&1].borrowed.is_empty());
+        assert_eq!((desk.pool("NIKE").unwrap().on_loan, desk.located(0, "NIKE")), (300, 0));
+        assert_eq!(desk.pool("NIKE").unwrap().available(), 19_700);
+
+        // Covering the short returns the shares
+        accounts.get_mut(&0).unwrap().apply_fill("NIKE", Side::Buy, Price::from_f64(100.0), Quantity::new(300));
+        desk.reconcile(&mut accounts);
+        assert!(accounts[&0].borrowed.is_empty());
+        assert_eq!(desk.pool("NIKE").unwrap().available(), 20_000);
+    }
+
+    #[test]
+    fn fees_run_over_the_weekend() {
+        let registry = InstrumentRegistry::load(INSTRUMENTS_FILE).unwrap();
+        let desk = BorrowDesk::new(&registry);
+        let mut account = Account::new(0, &registry.stocks(), AccountType::Margin, &FxRates::default());
+        account.borrowed.insert("PUMA".to_string(), 1000);
+        let mut accounts = HashMap::from([(0, account)]);
+        let prices = HashMap::from([("PUMA".to_string(), Price::from_f64(100.0))]);
+
+        // Friday's close to Monday's session
+        let friday = NaiveDate::from_ymd_opt(2024, 12, 27).unwrap();
+        let days = (TradingCalendar::default_calendar().next_trading_day(friday) - friday).num_days() as u32;
+        assert_eq!(days, 3);
+        let fees = desk.accrue_fees(&mut accounts, &prices, days);
+        let rate = desk.pool("PUMA").unwrap().rate;
+        assert_eq!(fees.len(), 1);
+        assert_eq!(fees[0].amount, Amount::from_units(100_000).scale(rate * 3.0 / BORROW_DAY_COUNT));
+        assert_eq!(accounts[&0].balance(accounts[&0].currency("PUMA")), INITIAL_CASH - fees[0].amount);
+    }
+}
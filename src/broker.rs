@@ -1,63 +1,1657 @@
 
-use std::sync::{Arc, Mutex, atomic::{AtomicUsize, Ordering, AtomicBool}};
-use chrono::Local;
+use std::collections::HashMap;
+use std::sync::{Arc, atomic::{AtomicUsize, Ordering, AtomicBool}};
+use std::time::{Duration, Instant};
+use chrono::{Local, NaiveDate};
 
-use crate::stock_object::{MarketFactors, Stock};
-use crate::rmq::consume;
+use crate::stock_object::MarketFactors;
+use crate::bars::{Bar, BarAggregator, BarInterval};
+use crate::borrow::BorrowDesk;
+use crate::calendar::TradingCalendar;
+use crate::market::MarketState;
+use crate::market_data::{MarketDataEvent, MarketDataPublisher};
+use crate::account::{AccountType, Ledger};
+use crate::async_rmq;
+use crate::analytics::Analytics;
+use crate::corporate_actions::{CorporateAction, CorporateActions, DividendEntitlement};
+use crate::circuit_breaker::{CircuitBreaker, OrderCheck, TradingState};
+use crate::index::{IndexLevel, IndexWeighting};
+use crate::depth::{DepthFeed, SnapshotRequest};
+use crate::etf::{CreationAction, CreationRequest, EtfDesk};
+use crate::fees::{FeeCharge, Liquidity, OrderTickets};
+use crate::fx::{FxProcess, BASE_CURRENCY, FX_SYMBOL};
+use crate::futures::{FutureContract, FuturesMarket, FUTURE_INITIAL_MARGIN, FUTURE_MAINTENANCE_MARGIN};
+use crate::instrument::{normalize_symbol, InstrumentRegistry};
+use crate::margin::{opening_shares, MarginDesk, MarginEvent, MarginRequirement};
+use crate::options::{OptionContract, OptionKind, OptionMarket};
+use crate::order::{Order, Side, TimeInForce, Trade};
+use crate::price::{Amount, Price, Quantity};
+use crate::session::{MarketPhase, SessionSchedule, SESSION_SYMBOL};
+use crate::settlement::{SettlementEngine, SettlementResult, SETTLEMENT_DAYS};
+use crate::shard::{routing_key, Shard, SharedMarket};
+use crate::tape::TradeTape;
+use crate::rmq::{declare_queue, QueueConsumer, CONSUME_TIMEOUT, MARKET_DATA_EXCHANGE, ORDER_EXCHANGE};
+use crate::router::SmartOrderRouter;
 use std::sync::mpsc::Receiver;
-use crate::trader::{NUM_TRADERS, ORDERS_PER_TRADER};
+use crate::trader::Population;
+use futures_lite::StreamExt;
+use lapin::{options::BasicAckOptions, Connection};
+use crate::venue::{Venues, PRIMARY_VENUE};
 
 pub struct Broker {
-    stocks: Arc<Mutex<Vec<Stock>>>,
-    order_count: Arc<AtomicUsize>,
-    stop_signal: Arc<AtomicBool>,
+    stocks: Arc<MarketState>,
+    registry: Arc<InstrumentRegistry>,
     market_rx: Receiver<MarketFactors>,
+    // The listing exchange and the alternative venues, each with books for every stock
+    venues: Venues,
+    router: SmartOrderRouter,
+    // Commission each order has paid so far, so the minimum ticket is charged once
+    tickets: OrderTickets,
+    schedule: SessionSchedule,
+    calendar: TradingCalendar,
+    phase: MarketPhase,
+    date: NaiveDate,
+    bars: BarAggregator,
+    circuit_breaker: CircuitBreaker,
+    market_data: MarketDataPublisher,
+    // The blocking mode's consumer of the shard's queue; it opens with the first session
+    // and stays open, and after a failure the next wait reopens it
+    orders: Option<QueueConsumer>,
+    depth_feeds: HashMap<String, DepthFeed>,
+    tape: TradeTape,
+    analytics: Analytics,
+    settlement: SettlementEngine,
+    borrow_desk: BorrowDesk,
+    margin: MarginDesk,
+    corporate_actions: CorporateActions,
+    fx: FxProcess,
+    options: OptionMarket,
+    futures: FuturesMarket,
+    etfs: EtfDesk,
+    shard: Shard,
+    // The accounts, session clock and index, shared with the other shards
+    market: SharedMarket,
+    population: Population,
 }
 
 impl Broker {
-    pub fn new(stocks: Arc<Mutex<Vec<Stock>>>, order_count: Arc<AtomicUsize>, stop_signal: Arc<AtomicBool>, market_rx: Receiver<MarketFactors>) -> Self {
-        Broker { stocks, order_count, stop_signal, market_rx }
+    pub fn new(registry: Arc<InstrumentRegistry>, calendar: TradingCalendar, corporate_actions: CorporateActions, order_count: Arc<AtomicUsize>, stop_signal: Arc<AtomicBool>, market_rx: Receiver<MarketFactors>) -> Self {
+        let market = SharedMarket::new(&registry, 1, Population::default(), order_count, stop_signal);
+        Broker::for_shard(Shard::whole_market(), registry, calendar, corporate_actions, market, market_rx)
     }
 
-    pub fn process_orders(&self) {
-        let current_time = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
-        loop {
-            if self.stop_signal.load(Ordering::SeqCst) {
-                break;
+    // A broker for one shard trades only the instruments in `registry`, booking to the
+    // accounts and moving the index it shares with the other shards in `market`
+    pub fn for_shard(shard: Shard, registry: Arc<InstrumentRegistry>, calendar: TradingCalendar, corporate_actions: CorporateActions, market: SharedMarket,
+        market_rx: Receiver<MarketFactors>) -> Self {
+        // The broker owns the only copy of the market; everyone else sees it through market data
+        let stocks = Arc::new(MarketState::new(registry.stocks()));
+        let venues = Venues::new(stocks.symbols());
+        let fx = FxProcess::default();
+        for instrument in registry.instruments() {
+            if fx.rates().rate(&instrument.currency).is_none() {
+                eprintln!("No FX rate for {} ({}); it will be valued at zero", instrument.currency, instrument.symbol);
+            }
+        }
+        let options = OptionMarket::new(&registry, &calendar);
+        // There is one index for the whole market, so only the first shard lists futures on it
+        let futures = FuturesMarket::new(&registry, &calendar, (shard.id == 0).then_some(&*market.index.lock().unwrap()));
+        let population = market.population;
+        let schedule = SessionSchedule::new(population.total_orders());
+        let borrow_desk = BorrowDesk::new(&registry);
+        let etfs = EtfDesk::new(&registry);
+        let mut margin = MarginDesk::new(&registry);
+        for contract in futures.contracts() {
+            margin.list(&contract.symbol, MarginRequirement { initial: FUTURE_INITIAL_MARGIN, maintenance: FUTURE_MAINTENANCE_MARGIN });
+        }
+        let broker = Broker { stocks, registry, market_rx, venues, router: SmartOrderRouter, tickets: OrderTickets::default(), schedule, calendar: calendar.clone(), phase: MarketPhase::PreOpen,
+            date: Local::now().date_naive(), bars: BarAggregator::default(),
+            circuit_breaker: CircuitBreaker::default(), market_data: MarketDataPublisher::new(MARKET_DATA_EXCHANGE, shard.id), orders: None, depth_feeds: HashMap::new(), tape: TradeTape::new(),
+            analytics: Analytics::new(),
+            settlement: SettlementEngine::new(SETTLEMENT_DAYS, calendar), borrow_desk, margin,
+            corporate_actions, fx, options, futures, etfs, shard, market, population };
+        broker.list_contracts();
+        broker
+    }
+
+    // A single broker for more or fewer traders than the default, each with a new account
+    pub fn with_population(mut self, population: Population) -> Self {
+        self.market.population = population;
+        self.market.open_accounts(&self.stocks.snapshot());
+        self.list_contracts();
+        self.schedule = SessionSchedule::new(population.total_orders());
+        self.population = population;
+        self
+    }
+
+    // Accounts hold this shard's options and futures in the currency they trade in
+    fn list_contracts(&self) {
+        let mut ledger = self.market.ledger.lock().unwrap();
+        for account in ledger.accounts.values_mut() {
+            for contract in self.options.contracts() {
+                account.set_currency(&contract.symbol, &contract.currency);
+            }
+            for contract in self.futures.contracts() {
+                account.set_currency(&contract.symbol, &contract.currency);
+            }
+        }
+    }
+
+    pub fn market_state(&self) -> &MarketState {
+        &self.stocks
+    }
+
+    // Symbol changes replace the registry, so callers take the current one each session
+    pub fn registry(&self) -> Arc<InstrumentRegistry> {
+        Arc::clone(&self.registry)
+    }
+
+    // This broker's market data publisher and the sequence number of the last message it published
+    pub fn market_data_sequence(&self) -> (usize, u64) {
+        (self.market_data.id(), self.market_data.sequence())
+    }
+
+    // The async mode publishes market data over the shared connection for the rest of the run
+    pub async fn publish_market_data_on(&mut self, connection: &Connection) -> lapin::Result<()> {
+        let channel = async_rmq::open_exchange(connection, MARKET_DATA_EXCHANGE).await?;
+        self.market_data.publish_on(channel);
+        Ok(())
+    }
+
+    // Top of the listing exchange's book plus whatever changed in its depth since it
+    // was last published, then the best bid and offer across every venue
+    fn publish_book(&mut self, stock_name: &str) {
+        let Some(book) = self.venues.primary.books.get(stock_name) else {
+            return;
+        };
+        self.analytics.on_quote(stock_name, book.best_bid(), book.best_ask());
+        let event = MarketDataEvent::Quote { best_bid: book.best_bid(), best_ask: book.best_ask() };
+        self.market_data.publish(stock_name, event);
+        self.publish_depth(stock_name);
+        let consolidated = self.venues.consolidated(stock_name);
+        self.market_data.publish(stock_name, MarketDataEvent::Bbo(consolidated));
+    }
+
+    // Depth of the listing exchange's book, which during a call shows the orders collected so far
+    fn publish_depth(&mut self, stock_name: &str) {
+        let Some(book) = self.venues.primary.books.get(stock_name) else {
+            return;
+        };
+        let feed = self.depth_feeds.entry(stock_name.to_string()).or_default();
+        for message in feed.update(book) {
+            self.market_data.publish(stock_name, MarketDataEvent::Depth(message));
+        }
+    }
+
+    // Options and futures are published top of book only
+    fn publish_derivative_quote(&mut self, symbol: &str) {
+        let Some(book) = self.options.book(symbol).or_else(|| self.futures.book(symbol)) else {
+            return;
+        };
+        let event = MarketDataEvent::Quote { best_bid: book.best_bid(), best_ask: book.best_ask() };
+        self.market_data.publish(symbol, event);
+    }
+
+    // Full depth of every book, so subscribers can (re)build theirs
+    fn publish_depth_snapshots(&mut self) {
+        for symbol in self.stocks.symbols().to_vec() {
+            self.publish_depth_snapshot(&symbol);
+        }
+    }
+
+    fn publish_depth_snapshot(&mut self, symbol: &str) {
+        let Some(book) = self.venues.primary.books.get(symbol) else {
+            return;
+        };
+        let message = self.depth_feeds.entry(symbol.to_string()).or_default().snapshot(book);
+        self.market_data.publish(symbol, MarketDataEvent::Depth(message));
+    }
+
+    // A subscriber found a gap in a book's depth updates
+    fn handle_snapshot_request(&mut self, request: SnapshotRequest) {
+        let symbol = normalize_symbol(&request.symbol);
+        if self.venues.primary.books.contains_key(&symbol) {
+            println!("\x1b[33mDEPTH SNAPSHOT: {} resent on request\x1b[0m", symbol);
+            self.publish_depth_snapshot(&symbol);
+        }
+    }
+
+    pub fn index(&self) -> IndexLevel {
+        self.market.index.lock().unwrap().level()
+    }
+
+    fn publish_index(&mut self) {
+        let level = self.index();
+        let name = level.name.clone();
+        self.market_data.publish(&name, MarketDataEvent::Index(level));
+    }
+
+    // iNAVs move with FX as well as with their baskets
+    fn publish_fx(&mut self) {
+        self.market_data.publish(FX_SYMBOL, MarketDataEvent::Fx(self.fx.rates().clone()));
+        let etfs: Vec<String> = self.etfs.etfs().iter().map(|e| e.symbol.clone()).collect();
+        for etf in etfs {
+            self.publish_nav(&etf);
+        }
+    }
+
+    fn publish_nav(&mut self, etf: &str) {
+        if let Some(nav) = self.etfs.nav(etf, &self.stocks.prices(), self.fx.rates()) {
+            self.market_data.publish(etf, MarketDataEvent::Nav { nav });
+        }
+    }
+
+    // Listed option contracts with the volatility surface they are priced from
+    pub fn options(&self) -> &OptionMarket {
+        &self.options
+    }
+
+    pub fn shard(&self) -> &Shard {
+        &self.shard
+    }
+
+    pub fn venues(&self) -> &Venues {
+        &self.venues
+    }
+
+    pub fn etfs(&self) -> &EtfDesk {
+        &self.etfs
+    }
+
+    pub fn futures(&self) -> &FuturesMarket {
+        &self.futures
+    }
+
+    pub fn analytics(&self) -> &Analytics {
+        &self.analytics
+    }
+
+    pub fn tape(&self) -> &TradeTape {
+        &self.tape
+    }
+
+    pub fn bars(&self, stock_name: &str, interval: BarInterval) -> Vec<Bar> {
+        self.bars.bars(stock_name, interval)
+    }
+
+    // Pre-open for a trading day: resting GTC orders from earlier sessions are still in the books
+    pub fn start_session(&mut self, date: NaiveDate) {
+        self.date = date;
+        self.market.start_session();
+        self.tickets.clear();
+        self.phase = MarketPhase::PreOpen;
+        self.apply_corporate_actions(date);
+        self.bind_orders();
+        let current_stocks = self.stocks.snapshot();
+        let previous_closes = current_stocks.iter()
+            .map(|s| (s.stock_name.clone(), s.closing_price.unwrap_or(s.current_price)))
+            .collect();
+        self.circuit_breaker.start_session(previous_closes);
+
+        let carried_over: usize = self.venues.iter().flat_map(|v| v.books.values()).map(|b| b.len()).sum();
+        println!("\x1b[35m=== {} {} ===\x1b[0m", self.phase.name(), self.date);
+        println!("{} good-till-cancel orders carried over", carried_over);
+        self.market_data.publish(SESSION_SYMBOL, MarketDataEvent::Phase(self.phase));
+        self.settle(date);
+        self.publish_depth_snapshots();
+        self.fx.set_rates(self.market.clock.fx_rates());
+        self.publish_fx();
+    }
+
+    // Orders reach this broker by the symbols it trades, as listed for the day
+    fn bind_orders(&self) {
+        let keys: Vec<String> = self.stocks.symbols().iter()
+            .chain(self.options.contracts().iter().map(|c| &c.symbol))
+            .chain(self.futures.contracts().iter().map(|c| &c.symbol))
+            .map(|symbol| routing_key(symbol))
+            .collect();
+        if let Err(e) = declare_queue(&self.shard.queue, ORDER_EXCHANGE, &keys) {
+            eprintln!("Failed to bind {} to its symbols: {}", self.shard.queue, e);
+        }
+    }
+
+    // Corporate actions take effect before the open; dividends are paid on their pay date
+    fn apply_corporate_actions(&mut self, date: NaiveDate) {
+        let actions = self.corporate_actions.due(date);
+        for action in &actions {
+            let result = match action {
+                CorporateAction::Split { symbol, new_shares, old_shares, .. } => self.split(symbol, *new_shares, *old_shares),
+                CorporateAction::Dividend { symbol, pay_date, amount, .. } => self.go_ex_dividend(symbol, *amount, *pay_date),
+                CorporateAction::Rename { symbol, new_symbol, .. } => self.rename(symbol, new_symbol),
+            };
+            if let Err(reason) = result {
+                eprintln!("Corporate action on {} not applied: {}", action.symbol(), reason);
+            }
+        }
+        if !actions.is_empty() {
+            self.publish_index();
+        }
+
+        for dividend in self.corporate_actions.payable(date) {
+            if let Some(account) = self.market.ledger.lock().unwrap().accounts.get_mut(&dividend.trader_id) {
+                account.credit(&dividend.currency, dividend.amount);
+                println!("DIVIDEND PAID {}: Trader {} {:+.2} {} on {} {} shares", date, dividend.trader_id + 1, dividend.amount,
+                dividend.currency, dividend.shares, dividend.stock_name);
+            }
+        }
+    }
+
+    fn split(&mut self, symbol: &str, new_shares: u64, old_shares: u64) -> Result<(), String> {
+        let (price, stock) = {
+            let mut stock = self.stocks.lock(symbol).ok_or_else(|| format!("unknown symbol {}", symbol))?;
+            stock.split(new_shares, old_shares);
+            (stock.closing_price.unwrap_or(stock.current_price), stock.clone())
+        };
+        let cancelled: usize = self.venues.iter_mut()
+            .filter_map(|venue| venue.books.get_mut(symbol))
+            .map(|book| book.split(new_shares, old_shares, stock.tick_size, stock.lot_size))
+            .sum();
+        {
+            let mut ledger = self.market.ledger.lock().unwrap();
+            let mut ids: Vec<usize> = ledger.accounts.keys().copied().collect();
+            ids.sort();
+            for id in ids {
+                let account = ledger.accounts.get_mut(&id).unwrap();
+                let cash_in_lieu = account.split(symbol, new_shares, old_shares, price);
+                if cash_in_lieu != Amount::ZERO {
+                    println!("  Trader {} receives {:+.2} {} cash in lieu of fractional {} shares", id + 1, cash_in_lieu, account.currency(symbol), symbol);
+                }
+            }
+        }
+        self.settlement.split(symbol, new_shares, old_shares);
+        self.borrow_desk.split(symbol, new_shares, old_shares);
+        self.analytics.split(symbol, new_shares, old_shares);
+        self.bars.split(symbol, new_shares, old_shares);
+        self.etfs.split(symbol, new_shares, old_shares);
+        if let Some(instrument) = Arc::make_mut(&mut self.registry).get_mut(symbol) {
+            instrument.shares_outstanding = instrument.shares_outstanding * new_shares / old_shares;
+        }
+        {
+            let mut index = self.market.index.lock().unwrap();
+            if let Some(weight) = index.weight(symbol) {
+                let weight = match index.weighting {
+                    IndexWeighting::MarketCap => weight * new_shares as f64 / old_shares as f64,
+                    IndexWeighting::Price => weight,
+                };
+                index.adjust(symbol, price, weight);
             }
+        }
 
-            let order = consume("stock_order");
-            if order.is_empty() {
-                if self.order_count.load(Ordering::SeqCst) >= NUM_TRADERS * ORDERS_PER_TRADER {
+        let option_orders = self.options.split(symbol, new_shares, old_shares);
+        let future_orders = self.futures.split(symbol, new_shares, old_shares);
+
+        println!("\x1b[35mSPLIT {}: {} {} for {}, now ${:.2}; {} resting orders too small to keep were cancelled\x1b[0m", self.date, symbol,
+        new_shares, old_shares, price, cancelled);
+        let adjusted: Vec<String> = self.options.contracts().iter()
+            .filter(|c| c.underlying == symbol)
+            .map(|c| c.symbol.clone())
+            .collect();
+        if !adjusted.is_empty() {
+            println!("\x1b[35m  {} options on {} adjusted to {} shares per contract, {} resting option orders cancelled\x1b[0m", adjusted.len(), symbol,
+            self.options.get(&adjusted[0]).map_or(0, |c| c.multiplier), option_orders);
+        }
+        for option in adjusted {
+            self.publish_derivative_quote(&option);
+        }
+        let adjusted: Vec<String> = self.futures.contracts().iter()
+            .filter(|c| c.underlying == symbol && !c.is_index)
+            .map(|c| c.symbol.clone())
+            .collect();
+        if !adjusted.is_empty() {
+            println!("\x1b[35m  {} futures on {} adjusted to {} shares per contract, {} resting futures orders cancelled\x1b[0m", adjusted.len(), symbol,
+            self.futures.get(&adjusted[0]).map_or(0, |c| c.multiplier), future_orders);
+        }
+        for future in adjusted {
+            self.publish_derivative_quote(&future);
+        }
+        self.market_data.publish(symbol, MarketDataEvent::Split { new_shares, old_shares });
+        self.market_data.publish(symbol, MarketDataEvent::Adjustment { current_price: stock.current_price, closing_price: stock.closing_price });
+        self.publish_book(symbol);
+        Ok(())
+    }
+
+    // Whoever holds the stock going into the ex-date is owed the dividend; shorts pay it
+    fn go_ex_dividend(&mut self, symbol: &str, amount: Price, pay_date: NaiveDate) -> Result<(), String> {
+        let (price, stock) = {
+            let mut stock = self.stocks.lock(symbol).ok_or_else(|| format!("unknown symbol {}", symbol))?;
+            stock.ex_dividend(amount);
+            (stock.closing_price.unwrap_or(stock.current_price), stock.clone())
+        };
+        for account in self.market.ledger.lock().unwrap().accounts.values() {
+            let shares = account.position(symbol);
+            if shares != 0 {
+                self.corporate_actions.entitle(DividendEntitlement { trader_id: account.trader_id, stock_name: symbol.to_string(), shares,
+                    amount: amount.position_value(shares), currency: account.currency(symbol).to_string(), pay_date });
+            }
+        }
+        {
+            let mut index = self.market.index.lock().unwrap();
+            if let Some(weight) = index.weight(symbol) {
+                index.adjust(symbol, price, weight);
+            }
+        }
+
+        println!("\x1b[35mEX-DIVIDEND {}: {} ${:.2} per share payable {}, now ${:.2}\x1b[0m", self.date, symbol, amount, pay_date, price);
+        self.market_data.publish(symbol, MarketDataEvent::Adjustment { current_price: stock.current_price, closing_price: stock.closing_price });
+        Ok(())
+    }
+
+    // Everything keyed by symbol moves over; the tape keeps the symbol each trade printed under
+    fn rename(&mut self, old: &str, new: &str) -> Result<(), String> {
+        Arc::make_mut(&mut self.registry).rename(old, new)?;
+        let new = normalize_symbol(new);
+        let stocks = self.stocks.snapshot().into_iter()
+            .map(|mut stock| {
+                if stock.stock_name == old {
+                    stock.stock_name = new.clone();
+                }
+                stock
+            })
+            .collect();
+        self.stocks = Arc::new(MarketState::new(stocks));
+        for venue in self.venues.iter_mut() {
+            if let Some(mut book) = venue.books.remove(old) {
+                book.rename(&new);
+                venue.books.insert(new.clone(), book);
+            }
+        }
+        if let Some(feed) = self.depth_feeds.remove(old) {
+            self.depth_feeds.insert(new.clone(), feed);
+        }
+        {
+            let mut ledger = self.market.ledger.lock().unwrap();
+            for account in ledger.accounts.values_mut() {
+                account.rename_stock(old, &new);
+            }
+            if let Some(mark) = ledger.marks.remove(old) {
+                ledger.marks.insert(new.clone(), mark);
+            }
+        }
+        self.bars.rename(old, &new);
+        self.analytics.rename(old, &new);
+        self.market.index.lock().unwrap().rename(old, &new);
+        self.settlement.rename(old, &new);
+        self.borrow_desk.rename(old, &new);
+        self.margin.rename(old, &new);
+        self.corporate_actions.rename(old, &new);
+        self.options.rename(old, &new);
+        self.futures.rename(old, &new);
+        self.etfs.rename(old, &new);
+
+        println!("\x1b[35mSYMBOL CHANGE {}: {} now trades as {}\x1b[0m", self.date, old, new);
+        self.market_data.publish(old, MarketDataEvent::SymbolChange { new_symbol: new });
+        Ok(())
+    }
+
+    // Settle what is due before the day's trading starts
+    fn settle(&mut self, date: NaiveDate) {
+        let results = self.settlement.settle(date, &mut self.market.ledger.lock().unwrap().accounts);
+        let settled = results.iter().filter(|r| matches!(r, SettlementResult::Settled(_))).count();
+        for result in &results {
+            if let SettlementResult::Failed { obligation, shortfall } = result {
+                println!("\x1b[31mSETTLEMENT FAIL: Trader {} short {} {} shares for the {} trade of {} (due {})\x1b[0m",
+                obligation.trader_id + 1, shortfall, obligation.stock_name, obligation.side.as_str(), obligation.trade_date,
+                obligation.settlement_date);
+            }
+        }
+        println!("SETTLEMENT {}: {} obligations settled, {} failed, {} pending", date, settled, results.len() - settled,
+        self.settlement.pending().len());
+    }
+
+    pub fn process_orders(&mut self) {
+        while self.market.clock.orders_received() < self.schedule.total_orders {
+            let order = self.next_message();
+            self.follow_market();
+            self.release_orders(Instant::now());
+            let Some(order) = order else {
+                if self.traders_finished() {
                     break;
                 }
                 continue;
+            };
+            self.handle_message(&order);
+        }
+        self.close_session();
+    }
+
+    // The next message on the shard's queue, or none within the wait
+    fn next_message(&mut self) -> Option<String> {
+        let timeout = self.wait_timeout();
+        let consumer = match self.orders.take() {
+            Some(consumer) => consumer,
+            None => match QueueConsumer::open(&self.shard.queue) {
+                Ok(consumer) => consumer,
+                Err(e) => {
+                    eprintln!("Failed to consume {}: {}", self.shard.queue, e);
+                    std::thread::sleep(timeout);
+                    return None;
+                }
+            },
+        };
+        match consumer.next(timeout) {
+            Ok(message) => {
+                self.orders = Some(consumer);
+                message
+            }
+            Err(e) => {
+                eprintln!("Failed to consume {}: {}", self.shard.queue, e);
+                None
             }
+        }
+    }
 
-            // Deserialize the JSON to a Stock object
-            match serde_json::from_str::<Stock>(&order) {
-                Ok(stock) => {
-                    println!("* Received order: stock_name: {}, current_price: ${:.2}", stock.stock_name.trim(), 
-                    stock.current_price);
-                    
-                    let mut current_stocks = self.stocks.lock().unwrap();
-                    if let Some(existing_stock) = current_stocks.iter_mut().find(|s| 
-                        s.stock_name == stock.stock_name) {
-                        existing_stock.current_price = stock.current_price;
+    // The async mode's broker: one task consuming over the shared connection. It stops
+    // the same way as the blocking loop. Only the close blocks, waiting for the other shards,
+    // so it tells the runtime to move other tasks off this worker thread; that needs the multi-threaded runtime.
+    pub async fn process_orders_async(&mut self, connection: &Connection) {
+        let channel = match async_rmq::open_channel(connection, &self.shard.queue).await {
+            Ok(channel) => channel,
+            Err(e) => {
+                eprintln!("Failed to open a channel for {}: {}", self.shard.queue, e);
+                return;
+            }
+        };
+        let mut consumer = match async_rmq::consume(&channel, &self.shard.queue, &self.shard.queue).await {
+            Ok(consumer) => consumer,
+            Err(e) => {
+                eprintln!("Failed to consume {}: {}", self.shard.queue, e);
+                return;
+            }
+        };
 
-                        println!("{}, Order processing... {} share prices updated at ${:.2}", current_time, 
-                        existing_stock.stock_name.trim(), existing_stock.current_price);
+        while self.market.clock.orders_received() < self.schedule.total_orders {
+            let next = match tokio::time::timeout(self.wait_timeout(), consumer.next()).await {
+                Ok(next) => next,
+                Err(_) => {
+                    self.follow_market();
+                    self.release_orders(Instant::now());
+                    if self.traders_finished() {
+                        break;
                     }
-                },
-                Err(e) => {
-                    eprintln!("Failed to deserialize stock: {}", e);
+                    continue;
                 }
+            };
+            let delivery = match next {
+                Some(Ok(delivery)) => delivery,
+                Some(Err(e)) => {
+                    eprintln!("Consumer ended: {}", e);
+                    break;
+                }
+                None => break,
+            };
+            if let Err(e) = delivery.ack(BasicAckOptions::default()).await {
+                eprintln!("Failed to acknowledge order: {}", e);
             }
+            let order = String::from_utf8_lossy(&delivery.data).to_string();
+            self.handle_message(&order);
+        }
+        tokio::task::block_in_place(|| self.close_session());
+        // Whatever arrived after the close goes back on the queue for the next session
+        if let Err(e) = channel.close(200, "session closed").await {
+            eprintln!("Failed to close channel: {}", e);
         }
+    }
+
+    // Orders on their way to a venue arrive on time even when no new order comes in
+    fn wait_timeout(&self) -> Duration {
+        let now = Instant::now();
+        self.venues.next_arrival().map_or(CONSUME_TIMEOUT, |due| due.saturating_duration_since(now).clamp(Duration::from_millis(1), CONSUME_TIMEOUT))
+    }
+
+    // Every trader has sent all its orders
+    fn traders_finished(&self) -> bool {
+        self.market.stop_signal.load(Ordering::SeqCst) && self.market.order_count.load(Ordering::SeqCst) >= self.population.total_orders()
+    }
+
+    // Deserialize the JSON to an Order; authorized participants also send creation
+    // requests, and market data subscribers depth snapshot requests
+    pub fn handle_message(&mut self, message: &str) {
+        match serde_json::from_str::<Order>(message) {
+            Ok(order) => self.handle_order(order),
+            Err(e) => match serde_json::from_str::<CreationRequest>(message) {
+                Ok(request) => self.handle_creation(request),
+                Err(_) => match serde_json::from_str::<SnapshotRequest>(message) {
+                    Ok(request) => self.handle_snapshot_request(request),
+                    Err(_) => eprintln!("Failed to deserialize order: {}", e),
+                },
+            },
+        }
+    }
+
+    // Catch up with the other shards: the phase the session clock has reached, the FX
+    // rates and any market-wide halt
+    fn follow_market(&mut self) {
+        let received = self.market.clock.orders_received();
+        if received > 0 {
+            self.enter_phase(self.schedule.phase_for(received - 1));
+        }
+        let rates = self.market.clock.fx_rates();
+        if rates != *self.fx.rates() {
+            self.fx.set_rates(rates);
+            self.publish_fx();
+        }
+        let breaker = self.market.breaker.lock().unwrap();
+        self.circuit_breaker.follow(&breaker);
+    }
+
+    fn close_session(&mut self) {
+        self.enter_phase(MarketPhase::PostClose);
+        self.end_of_day();
+        self.enter_phase(MarketPhase::Closed);
         println!("\nBroker has finished processing all orders.");
     }
-}
 
- 
+    pub fn handle_order(&mut self, mut order: Order) {
+        // Orders routed earlier may have reached their venues by now
+        self.release_orders(Instant::now());
+
+        // Only traders' orders move the session along; the market maker's and participants' do not
+        if self.population.is_trader(order.trader_id) {
+            self.market.clock.tick();
+        }
+        self.follow_market();
+
+        if let Some(contract) = self.options.get(&order.stock_name).cloned() {
+            self.handle_option_order(order, &contract);
+            return;
+        }
+        if let Some(contract) = self.futures.get(&order.stock_name).cloned() {
+            self.handle_future_order(order, &contract);
+            return;
+        }
+
+        println!("* Received order: {} {} {} shares at ${:.2}", order.side.as_str(), order.quantity,
+        order.stock_name, order.price);
+
+        // Unknown and non-listed symbols never reach a book
+        match self.registry.validate_symbol(&order.stock_name) {
+            Ok(instrument) => order.stock_name = instrument.symbol.clone(),
+            Err(reason) => {
+                println!("\x1b[31m  REJECTED order {}: {}\x1b[0m", order.order_id, reason);
+                return;
+            }
+        }
+
+        // Prices must sit on the stock's tick grid and quantities be whole lots
+        let validation = self.stocks.lock(&order.stock_name)
+            .map(|s| s.validate_order(order.price, order.quantity));
+        if let Some(Err(reason)) = validation {
+            println!("\x1b[31m  REJECTED order {}: {}\x1b[0m", order.order_id, reason);
+            return;
+        }
+
+        // Buys and short sales have to fit within the account's buying power
+        if let Err(reason) = self.check_buying_power(&order) {
+            println!("\x1b[31m  REJECTED order {}: {}\x1b[0m", order.order_id, reason);
+            return;
+        }
+
+        let collect = match self.phase {
+            MarketPhase::ContinuousTrading => {
+                let now = Instant::now();
+                for name in self.circuit_breaker.poll(now) {
+                    self.uncross_book(&name, "REOPENING AUCTION");
+                }
+                match self.circuit_breaker.check_order(&order) {
+                    OrderCheck::Accept => false,
+                    OrderCheck::Collect => true,
+                    OrderCheck::Reject(reason) => {
+                        println!("\x1b[31m  REJECTED order {}: {}\x1b[0m", order.order_id, reason);
+                        return;
+                    }
+                }
+            }
+            MarketPhase::ClosingAuction if self.circuit_breaker.halted_for_day() => {
+                println!("\x1b[31m  REJECTED order {}: market halted for the day\x1b[0m", order.order_id);
+                return;
+            }
+            phase => phase.is_call(),
+        };
+
+        // Orders can be directed to a venue, but only the listing exchange runs auctions
+        if let Some(venue) = &order.venue {
+            let rejection = if self.venues.get(venue).is_none() {
+                Some(format!("unknown venue {}", venue))
+            } else if collect && venue != PRIMARY_VENUE {
+                Some(format!("{} only trades continuously", venue))
+            } else if !collect {
+                self.trade_through(&order, venue)
+            } else {
+                None
+            };
+            if let Some(reason) = rejection {
+                println!("\x1b[31m  REJECTED order {}: {}\x1b[0m", order.order_id, reason);
+                return;
+            }
+        }
+
+        // Selling more than the trader holds is a short sale and needs borrow located. This comes last
+        // so a rejected order never ties up borrow it will not use.
+        if order.side == Side::Sell {
+            if let Err(reason) = self.locate(&order) {
+                println!("\x1b[31m  REJECTED order {}: {}\x1b[0m", order.order_id, reason);
+                return;
+            }
+        }
+
+        let reference_price = self.last_price(&order.stock_name);
+        if collect {
+            // Orders only accumulate during a call; disseminate where the book would uncross now
+            order.venue = Some(PRIMARY_VENUE.to_string());
+            let stock_name = order.stock_name.clone();
+            let book = self.venues.primary.books.get_mut(&stock_name).unwrap();
+            book.collect(order);
+            match book.indicative_uncross(reference_price.unwrap_or(Price::ZERO)) {
+                Some(indicative) => println!("\x1b[36m  INDICATIVE {}: ${:.2} for {} shares (surplus {})\x1b[0m",
+                book.stock_name, indicative.price, indicative.volume, indicative.surplus),
+                None => println!("\x1b[36m  INDICATIVE {}: no crossing orders\x1b[0m", book.stock_name),
+            }
+            self.publish_depth(&stock_name);
+        } else {
+            let children = if order.venue.is_some() {
+                vec![order]
+            } else {
+                let monthly_volume = self.market.ledger.lock().unwrap().accounts.get(&order.trader_id).map_or(0, |a| a.monthly_volume(self.date));
+                let children = self.router.route(&order, &self.venues, monthly_volume);
+                let split: Vec<String> = children.iter()
+                    .map(|child| format!("{} {}", child.quantity, child.venue.as_deref().unwrap_or(PRIMARY_VENUE)))
+                    .collect();
+                println!("  ROUTED order {}: {}", order.order_id, split.join(", "));
+                for child in &children {
+                    self.tickets.route(order.order_id, child.order_id);
+                }
+                children
+            };
+            let now = Instant::now();
+            for child in children {
+                self.venues.send(child, now);
+            }
+            self.release_orders(now);
+        }
+    }
+
+    // A directed order whose limit is beyond the consolidated best price may only take
+    // liquidity at that price on its venue, and must not rest crossing another venue
+    fn trade_through(&self, order: &Order, venue: &str) -> Option<String> {
+        let consolidated = self.venues.consolidated(&order.stock_name);
+        let (best, venues, opposite, label) = match order.side {
+            Side::Buy => (consolidated.best_ask?, consolidated.ask_venues, Side::Sell, "offer"),
+            Side::Sell => (consolidated.best_bid?, consolidated.bid_venues, Side::Buy, "bid"),
+        };
+        // Whether `a` is a better price than `b` for this order
+        let better = |a: Price, b: Price| match order.side {
+            Side::Buy => a < b,
+            Side::Sell => a > b,
+        };
+        if !better(best, order.price) {
+            return None;
+        }
+        let mut remaining = order.quantity;
+        if let Some(book) = self.venues.get(venue).and_then(|v| v.books.get(&order.stock_name)) {
+            for level in book.depth(opposite) {
+                if remaining.is_zero() || better(order.price, level.price) {
+                    break;
+                }
+                if level.price != best {
+                    remaining = order.quantity;
+                    break;
+                }
+                remaining -= remaining.min(level.quantity);
+            }
+        }
+        (!remaining.is_zero()).then(|| format!("${:.2} on {} trades through the consolidated best {} ${:.2} on {}", order.price, venue, label, best,
+        venues.join(", ")))
+    }
+
+    // Child orders reach their venue once its latency has passed
+    fn release_orders(&mut self, now: Instant) {
+        for order in self.venues.arrived(now) {
+            self.execute(order);
+        }
+    }
+
+    // Alternative venues only match while the stock trades continuously; an order
+    // arriving there after a halt or the end of continuous trading is cancelled
+    fn execute(&mut self, order: Order) {
+        let venue = order.venue.clone().unwrap_or_else(|| PRIMARY_VENUE.to_string());
+        if venue != PRIMARY_VENUE && (self.phase != MarketPhase::ContinuousTrading || self.circuit_breaker.state(&order.stock_name) != TradingState::Trading) {
+            println!("\x1b[31m  CANCELLED order {}: {} is not trading on {}\x1b[0m", order.order_id, order.stock_name, venue);
+            return;
+        }
+        let stock_name = order.stock_name.clone();
+        let Some(book) = self.venues.get_mut(&venue).and_then(|v| v.books.get_mut(&stock_name)) else {
+            return;
+        };
+        let trades = book.submit(order);
+        self.apply_trades(&trades);
+        self.check_band(&trades);
+        self.publish_book(&stock_name);
+        self.check_market_wide();
+        if !trades.is_empty() {
+            self.check_margin(&trades);
+        }
+    }
+
+    // Primary market: baskets and ETF shares change hands with the issuer, not on the book
+    fn handle_creation(&mut self, request: CreationRequest) {
+        println!("* Received {} request: Participant {} {} unit(s) of {}", request.action.as_str(), request.participant_id + 1, request.units,
+        request.etf);
+        let rejection = if !self.market.ledger.lock().unwrap().accounts.contains_key(&request.participant_id) {
+            Some(format!("no account for participant {}", request.participant_id + 1))
+        } else if let Some(limit) = request.etf_limit {
+            self.arbitrage(&request, limit).err()
+        } else {
+            self.create_or_redeem(&request).err()
+        };
+        if let Some(reason) = rejection {
+            println!("\x1b[31m  REJECTED {} request: {}\x1b[0m", request.action.as_str(), reason);
+        }
+    }
+
+    // The swap settles like a trade, against the issuer
+    fn create_or_redeem(&mut self, request: &CreationRequest) -> Result<(), String> {
+        let deliveries = {
+            let mut ledger = self.market.ledger.lock().unwrap();
+            let account = ledger.accounts.get_mut(&request.participant_id).ok_or_else(|| format!("no account for participant {}", request.participant_id + 1))?;
+            self.etfs.process(request, account)?
+        };
+        for (symbol, side, quantity) in deliveries {
+            self.settlement.record_delivery(request.participant_id, &symbol, side, quantity, Price::ZERO, self.date);
+        }
+        let label = match request.action {
+            CreationAction::Create => "CREATION",
+            CreationAction::Redeem => "REDEMPTION",
+        };
+        let outstanding = self.etfs.get(&request.etf).map_or(0, |etf| etf.shares_outstanding);
+        println!("\x1b[36m{}: Participant {} {} {} unit(s) of {}, {} shares now outstanding\x1b[0m", label, request.participant_id + 1,
+        request.action.as_str(), request.units, request.etf, outstanding);
+        Ok(())
+    }
+
+    // The participant's legs and the swap as one operation. Nothing happens unless the
+    // listing exchange can fill every leg in full right now, inside the price band; each
+    // leg is then sent immediate-or-cancel, so the participant is never left half hedged.
+    fn arbitrage(&mut self, request: &CreationRequest, etf_limit: Price) -> Result<(), String> {
+        let etf = self.etfs.get(&request.etf).cloned().ok_or_else(|| format!("{} is not an ETF", request.etf))?;
+        let (basket_side, etf_side) = match request.action {
+            CreationAction::Create => (Side::Buy, Side::Sell),
+            CreationAction::Redeem => (Side::Sell, Side::Buy),
+        };
+        let mut basket = Vec::new();
+        for component in &etf.basket {
+            let quantity = Quantity::new(component.shares * request.units);
+            let price = self.fillable_price(&component.symbol, basket_side, quantity, None)?;
+            basket.push(Order::new(request.participant_id, &component.symbol, basket_side, price, quantity, TimeInForce::Day));
+        }
+        let etf_shares = Quantity::new(etf.creation_unit * request.units);
+        let price = self.fillable_price(&etf.symbol, etf_side, etf_shares, Some(etf_limit))?;
+        let etf_order = Order::new(request.participant_id, &etf.symbol, etf_side, price, etf_shares, TimeInForce::Day);
+        let (opening, closing) = match request.action {
+            CreationAction::Create => (basket, vec![etf_order]),
+            CreationAction::Redeem => (vec![etf_order], basket),
+        };
+
+        let mut trades = Vec::new();
+        for order in opening {
+            trades.extend(self.fill_or_cancel(order));
+        }
+        self.create_or_redeem(request)?;
+        for order in closing {
+            trades.extend(self.fill_or_cancel(order));
+        }
+        self.check_margin(&trades);
+        Ok(())
+    }
+
+    // The limit that fills `quantity` at once on the listing exchange: the last price
+    // level needed, which has to be inside the band and no worse than `limit`
+    fn fillable_price(&self, symbol: &str, side: Side, quantity: Quantity, limit: Option<Price>) -> Result<Price, String> {
+        if self.phase != MarketPhase::ContinuousTrading || self.circuit_breaker.state(symbol) != TradingState::Trading {
+            return Err(format!("{} is not trading continuously", symbol));
+        }
+        let book = self.venues.primary.books.get(symbol).ok_or_else(|| format!("{} does not trade here", symbol))?;
+        let band = self.circuit_breaker.band(symbol);
+        let opposite = match side {
+            Side::Buy => Side::Sell,
+            Side::Sell => Side::Buy,
+        };
+        let mut remaining = quantity;
+        for level in book.depth(opposite) {
+            let acceptable = match side {
+                Side::Buy => band.is_none_or(|(_, upper)| level.price < upper) && limit.is_none_or(|limit| level.price <= limit),
+                Side::Sell => band.is_none_or(|(lower, _)| level.price > lower) && limit.is_none_or(|limit| level.price >= limit),
+            };
+            if !acceptable {
+                break;
+            }
+            if level.quantity >= remaining {
+                return Ok(level.price);
+            }
+            remaining -= level.quantity;
+        }
+        Err(format!("not enough {} liquidity to {} {} shares", symbol, side.as_str(), quantity))
+    }
+
+    // Immediate-or-cancel on the listing exchange
+    fn fill_or_cancel(&mut self, order: Order) -> Vec<Trade> {
+        let stock_name = order.stock_name.clone();
+        let order_id = order.order_id;
+        let book = self.venues.primary.books.get_mut(&stock_name).unwrap();
+        let trades = book.submit(order);
+        book.cancel(order_id);
+        self.apply_trades(&trades);
+        self.check_band(&trades);
+        self.publish_book(&stock_name);
+        trades
+    }
+
+    // Options trade continuously only, and not while their underlying is halted
+    fn handle_option_order(&mut self, order: Order, contract: &OptionContract) {
+        println!("* Received order: {} {} {} contracts at ${:.2}", order.side.as_str(), order.quantity, order.stock_name, order.price);
+
+        let rejection = if self.phase != MarketPhase::ContinuousTrading || self.circuit_breaker.state(&contract.underlying) != TradingState::Trading {
+            Some(format!("options on {} only trade while it trades continuously", contract.underlying))
+        } else if order.price <= Price::ZERO || !order.price.is_multiple_of(self.options.tick_size) {
+            Some(format!("premium ${:.2} is not a multiple of the ${:.2} option tick", order.price, self.options.tick_size))
+        } else {
+            None
+        };
+        if let Some(reason) = rejection {
+            println!("\x1b[31m  REJECTED order {}: {}\x1b[0m", order.order_id, reason);
+            return;
+        }
+
+        // Premiums are paid in full, out of the same buying power as stock
+        if order.side == Side::Buy {
+            if let Err(reason) = self.check_buying_power(&order) {
+                println!("\x1b[31m  REJECTED order {}: {}\x1b[0m", order.order_id, reason);
+                return;
+            }
+        }
+
+        let book = self.options.book_mut(&order.stock_name).unwrap();
+        // Cash accounts can buy options and sell the ones they hold, but not write them
+        if let Some(account) = self.market.ledger.lock().unwrap().accounts.get(&order.trader_id) {
+            let offered = book.open_quantity(order.trader_id, Side::Sell).value() as i64;
+            if account.account_type == AccountType::Cash && order.side == Side::Sell
+                && order.quantity.value() as i64 > (account.position(&order.stock_name) - offered).max(0) {
+                println!("\x1b[31m  REJECTED order {}: writing options needs a margin account\x1b[0m", order.order_id);
+                return;
+            }
+        }
+        // A new quote from the market maker replaces its last one on that side
+        if order.trader_id == self.population.market_maker_id() {
+            book.cancel_all(order.trader_id, order.side);
+        }
+        let symbol = order.stock_name.clone();
+        let trades = book.submit(order);
+        self.apply_option_trades(contract, &trades);
+        self.publish_derivative_quote(&symbol);
+        if !trades.is_empty() {
+            self.check_margin(&trades);
+        }
+    }
+
+    // Futures need a margin account with initial margin for any new contracts. Like
+    // options they trade only while their underlying trades continuously.
+    fn handle_future_order(&mut self, order: Order, contract: &FutureContract) {
+        println!("* Received order: {} {} {} contracts at ${:.2}", order.side.as_str(), order.quantity, order.stock_name, order.price);
+
+        let rejection = if self.phase != MarketPhase::ContinuousTrading || self.future_halted(contract) {
+            Some(format!("futures on {} only trade while it trades continuously", contract.underlying))
+        } else if order.price <= Price::ZERO || !order.price.is_multiple_of(contract.tick_size) {
+            Some(format!("price ${:.2} is not a multiple of the ${:.2} tick", order.price, contract.tick_size))
+        } else {
+            self.check_future_margin(&order, contract).err()
+        };
+        if let Some(reason) = rejection {
+            println!("\x1b[31m  REJECTED order {}: {}\x1b[0m", order.order_id, reason);
+            return;
+        }
+
+        let symbol = order.stock_name.clone();
+        let trades = self.futures.book_mut(&symbol).unwrap().submit(order);
+        self.apply_future_trades(contract, &trades);
+        self.publish_derivative_quote(&symbol);
+        if !trades.is_empty() {
+            self.check_margin(&trades);
+        }
+    }
+
+    // Index futures stop with the market-wide breaker, stock futures with their stock
+    fn future_halted(&self, contract: &FutureContract) -> bool {
+        if contract.is_index {
+            self.circuit_breaker.halted_for_day()
+        } else {
+            self.circuit_breaker.state(&contract.underlying) != TradingState::Trading
+        }
+    }
+
+    fn check_future_margin(&self, order: &Order, contract: &FutureContract) -> Result<(), String> {
+        let prices = self.marks();
+        let ledger = self.market.ledger.lock().unwrap();
+        let Some(account) = ledger.accounts.get(&order.trader_id) else {
+            return Ok(());
+        };
+        if account.account_type == AccountType::Cash {
+            return Err("futures need a margin account".to_string());
+        }
+        let position = account.futures_position(&order.stock_name);
+        let quantity = order.quantity.value() as i64;
+        let new_contracts = match order.side {
+            Side::Buy => quantity - (-position).max(0),
+            Side::Sell => quantity - position.max(0),
+        };
+        if new_contracts <= 0 {
+            return Ok(());
+        }
+        let fx = self.fx.rates();
+        let notional = fx.to_base(order.price.notional(Quantity::new(new_contracts as u64)) * contract.multiplier as i64, &contract.currency);
+        let buying_power = self.margin.buying_power(account, &prices, fx, &order.stock_name);
+        if notional > buying_power {
+            return Err(format!("{} {:.2} notional exceeds margin buying power {} {:.2}", BASE_CURRENCY, notional, BASE_CURRENCY, buying_power));
+        }
+        Ok(())
+    }
+
+    // Stocks at their last price, options at their theoretical value per contract and
+    // futures at their notional per contract, which only margin looks at
+    fn mark_prices(&self, mut prices: HashMap<String, Price>) -> HashMap<String, Price> {
+        let marks = self.options.marks(&prices, self.date);
+        prices.extend(marks);
+        prices.extend(self.futures.notionals());
+        prices
+    }
+
+    // Marks for everything an account can hold: this shard's own, which it leaves in the
+    // ledger, and the other shards' as they last left them
+    fn marks(&self) -> HashMap<String, Price> {
+        let marks = self.mark_prices(self.stocks.prices());
+        let mut ledger = self.market.ledger.lock().unwrap();
+        ledger.marks.extend(marks);
+        ledger.marks.clone()
+    }
+
+    // Trades re-price their symbol and, for a stock, the options on it. Only the accounts
+    // that traded or hold one of those are re-checked; those in a call are liquidated.
+    fn check_margin(&mut self, trades: &[Trade]) {
+        let Some(symbol) = trades.first().map(|trade| trade.stock_name.clone()) else {
+            return;
+        };
+        let mut symbols = vec![symbol.clone()];
+        symbols.extend(self.options.contracts().iter().filter(|c| c.underlying == symbol).map(|c| c.symbol.clone()));
+        let prices = self.marks();
+        let mut ids: Vec<usize> = {
+            let ledger = self.market.ledger.lock().unwrap();
+            ledger.accounts.values()
+                .filter(|account| symbols.iter().any(|s| account.holds(s)))
+                .map(|account| account.trader_id)
+                .chain(trades.iter().flat_map(|trade| [trade.buyer_id, trade.seller_id]))
+                .filter(|id| ledger.accounts.contains_key(id))
+                .collect()
+        };
+        ids.sort();
+        ids.dedup();
+        for id in ids {
+            let event = self.margin.check(&self.market.ledger.lock().unwrap().accounts[&id], &prices, self.fx.rates());
+            match event {
+                Some(MarginEvent::Call { equity, requirement, .. }) => println!("\x1b[41mMARGIN CALL: Trader {} equity ${:.2} below maintenance requirement ${:.2}\x1b[0m",
+                id + 1, equity, requirement),
+                Some(MarginEvent::Restored { equity, requirement, .. }) => println!("\x1b[32mMARGIN RESTORED: Trader {} equity ${:.2} covers maintenance requirement ${:.2}\x1b[0m",
+                id + 1, equity, requirement),
+                _ => {}
+            }
+            if self.margin.in_call(id) {
+                self.liquidate(id, &prices);
+            }
+        }
+    }
+
+    // Close enough of the account to cover its call, taking whatever liquidity the book
+    // has (for stock, inside the price band); anything that does not fill is cancelled
+    // rather than left resting. Options and futures are closed on their own books, and
+    // what another shard trades is left to that shard.
+    fn liquidate(&mut self, trader_id: usize, prices: &HashMap<String, Price>) {
+        let orders = self.margin.liquidation_orders(&self.market.ledger.lock().unwrap().accounts[&trader_id], prices, self.fx.rates());
+        for (symbol, side, quantity) in orders {
+            let option = self.options.get(&symbol).cloned();
+            let future = self.futures.get(&symbol).cloned();
+            if option.is_none() && future.is_none() && !self.venues.primary.books.contains_key(&symbol) {
+                continue;
+            }
+            let (book, trading, units) = match (&option, &future) {
+                (Some(contract), _) => (self.options.book(&symbol), self.phase == MarketPhase::ContinuousTrading
+                    && self.circuit_breaker.state(&contract.underlying) == TradingState::Trading, "contracts"),
+                (_, Some(contract)) => (self.futures.book(&symbol), self.phase == MarketPhase::ContinuousTrading && !self.future_halted(contract), "contracts"),
+                _ => (self.venues.primary.books.get(&symbol), self.circuit_breaker.state(&symbol) == TradingState::Trading, "shares"),
+            };
+            let opposite = match side {
+                Side::Buy => Side::Sell,
+                Side::Sell => Side::Buy,
+            };
+            let worst = book.and_then(|book| book.depth(opposite).last().map(|level| level.price));
+            let price = match (worst, trading) {
+                (Some(price), true) if option.is_none() && future.is_none() => self.within_band(&symbol, side, price),
+                (Some(price), true) => price,
+                _ => {
+                    println!("\x1b[33mLIQUIDATION DEFERRED: Trader {} {} {} {} {}, {} cannot trade now\x1b[0m", trader_id + 1, side.as_str(),
+                    quantity, symbol, units, symbol);
+                    continue;
+                }
+            };
+            let order = Order::new(trader_id, &symbol, side, price, quantity, TimeInForce::Day);
+            let order_id = order.order_id;
+            let book = match (&option, &future) {
+                (Some(_), _) => self.options.book_mut(&symbol),
+                (_, Some(_)) => self.futures.book_mut(&symbol),
+                _ => self.venues.primary.books.get_mut(&symbol),
+            }.unwrap();
+            let trades = book.submit(order);
+            book.cancel(order_id);
+            match (&option, &future) {
+                (Some(contract), _) => {
+                    self.apply_option_trades(contract, &trades);
+                    self.publish_derivative_quote(&symbol);
+                }
+                (_, Some(contract)) => {
+                    self.apply_future_trades(contract, &trades);
+                    self.publish_derivative_quote(&symbol);
+                }
+                _ => {
+                    self.apply_trades(&trades);
+                    self.check_band(&trades);
+                    self.publish_book(&symbol);
+                }
+            }
+
+            let filled: Quantity = trades.iter().map(|t| t.quantity).sum();
+            if filled.is_zero() {
+                continue;
+            }
+            let notional: Amount = trades.iter().map(|t| t.price.notional(t.quantity)).sum();
+            let average = Price::from_f64(notional.to_f64() / filled.value() as f64);
+            println!("\x1b[41mLIQUIDATION: Trader {} {} {} {} {} at an average ${:.2}\x1b[0m", trader_id + 1, side.as_str(), filled,
+            symbol, units, average);
+            self.margin.record(MarginEvent::Liquidation { trader_id, stock_name: symbol, side, quantity: filled, price: average });
+        }
+    }
+
+    // A buy limit no higher than the LIMIT UP price and a sell limit no lower than
+    // LIMIT DOWN, on the stock's tick grid
+    fn within_band(&self, stock_name: &str, side: Side, price: Price) -> Price {
+        let Some((lower, upper)) = self.circuit_breaker.band(stock_name) else {
+            return price;
+        };
+        let tick_size = self.stocks.lock(stock_name).map_or(Price::ZERO, |s| s.tick_size);
+        match side {
+            Side::Buy if price > upper => {
+                let limit = upper.round_to_tick(tick_size);
+                if limit > upper { limit - tick_size } else { limit }
+            }
+            Side::Sell if price < lower => {
+                let limit = lower.round_to_tick(tick_size);
+                if limit < lower { limit + tick_size } else { limit }
+            }
+            _ => price,
+        }
+    }
 
+    // Cash accounts pay in full and cannot short; margin accounts need initial margin on new exposure
+    fn check_buying_power(&self, order: &Order) -> Result<(), String> {
+        let prices = self.marks();
+        let ledger = self.market.ledger.lock().unwrap();
+        let Some(account) = ledger.accounts.get(&order.trader_id) else {
+            return Ok(());
+        };
+        let resting = match self.options.book(&order.stock_name) {
+            Some(book) => book.open_quantity(order.trader_id, order.side),
+            None => self.venues.open_quantity(&order.stock_name, order.trader_id, order.side),
+        };
+        let new_shares = opening_shares(account.account_type, order.side, account.position(&order.stock_name), resting.value() as i64,
+        order.quantity.value() as i64)?;
+        if new_shares <= 0 {
+            return Ok(());
+        }
+        // An option costs its premium on every share the contract covers
+        let multiplier = self.options.get(&order.stock_name).map_or(1, |contract| contract.multiplier as i64);
+        let fx = self.fx.rates();
+        let notional = fx.to_base(order.price.notional(Quantity::new(new_shares as u64)) * multiplier, account.currency(&order.stock_name));
+        let buying_power = self.margin.buying_power(account, &prices, fx, &order.stock_name);
+        if notional > buying_power {
+            return Err(format!("{} {:.2} exceeds {} buying power {} {:.2}", BASE_CURRENCY, notional, account.account_type.as_str(), BASE_CURRENCY,
+            buying_power));
+        }
+        Ok(())
+    }
 
+    // Continuous trades that reach a stock's price band halt it
+    fn check_band(&mut self, trades: &[Trade]) {
+        let now = Instant::now();
+        for trade in trades {
+            self.circuit_breaker.check_trade(&trade.stock_name, trade.price, now);
+        }
+    }
+
+    // The index is what market-wide circuit breakers watch. There is one for the whole
+    // market, so whichever shard sees a level breached, every shard halts.
+    fn check_market_wide(&mut self) {
+        let index = self.index();
+        let decline = -index.change();
+        let mut breaker = self.market.breaker.lock().unwrap();
+        if let Some(level) = breaker.check_market_wide(decline, Instant::now()) {
+            if breaker.halted_for_day() {
+                println!("\x1b[41m!!! MARKET-WIDE CIRCUIT BREAKER: {} down {:.1}% (level {:.0}%), trading halted for the day\x1b[0m",
+                index.name, decline * 100.0, level * 100.0);
+            } else {
+                println!("\x1b[41m!!! MARKET-WIDE CIRCUIT BREAKER: {} down {:.1}% (level {:.0}%), all trading paused for {}s\x1b[0m",
+                index.name, decline * 100.0, level * 100.0, breaker.halt_duration.as_secs());
+            }
+        }
+        self.circuit_breaker.follow(&breaker);
+    }
+
+    // Leaving a call phase uncrosses every book before the next phase starts
+    fn enter_phase(&mut self, next: MarketPhase) {
+        if next == self.phase {
+            return;
+        }
+
+        // Orders still on their way to a venue arrive before continuous trading ends
+        if self.phase == MarketPhase::ContinuousTrading {
+            for order in self.venues.flush() {
+                self.execute(order);
+            }
+        }
+        let auction_prices = if self.phase.is_call() {
+            self.run_auction()
+        } else {
+            Vec::new()
+        };
+        if next == MarketPhase::ClosingAuction {
+            self.circuit_breaker.resume_all();
+        }
+        if next == MarketPhase::PostClose {
+            self.set_closing_prices(&auction_prices);
+        }
+
+        self.phase = next;
+        println!("\x1b[35m=== {} ===\x1b[0m", self.phase.name());
+        self.market_data.publish(SESSION_SYMBOL, MarketDataEvent::Phase(next));
+    }
+
+    fn run_auction(&mut self) -> Vec<(String, Price)> {
+        let mut names: Vec<String> = self.venues.primary.books.keys().cloned().collect();
+        names.sort();
+
+        let mut prices = Vec::new();
+        for name in names {
+            if let Some(price) = self.uncross_book(&name, self.phase.name()) {
+                prices.push((name, price));
+            }
+        }
+        prices
+    }
+
+    // Uncross one book; the auction price becomes the stock's new band reference
+    fn uncross_book(&mut self, name: &str, label: &str) -> Option<Price> {
+        let reference_price = self.last_price(name).unwrap_or(Price::ZERO);
+        let book = self.venues.primary.books.get_mut(name).unwrap();
+        match book.uncross(reference_price) {
+            Some((uncross, trades)) => {
+                println!("\x1b[35m{}: {} uncrossed at ${:.2}, {} shares executed\x1b[0m", label,
+                name, uncross.price, uncross.volume);
+                self.apply_trades(&trades);
+                self.circuit_breaker.set_reference(name, uncross.price);
+                self.publish_book(name);
+                Some(uncross.price)
+            }
+            None => {
+                println!("\x1b[35m{}: {} did not uncross\x1b[0m", label, name);
+                self.publish_book(name);
+                None
+            }
+        }
+    }
+
+    // The closing auction price is the official close; without one the last trade stands
+    fn set_closing_prices(&mut self, auction_prices: &[(String, Price)]) {
+        for symbol in self.stocks.symbols() {
+            let mut stock = self.stocks.lock(symbol).unwrap();
+            let closing_price = auction_prices.iter()
+                .find(|(name, _)| *name == stock.stock_name)
+                .map(|(_, price)| *price)
+                .unwrap_or(stock.current_price);
+            stock.closing_price = Some(closing_price);
+            println!("OFFICIAL CLOSE: {} ${:.2}", stock.stock_name, closing_price);
+            self.market_data.publish(symbol, MarketDataEvent::Close { closing_price });
+            self.market.index.lock().unwrap().update(symbol, closing_price);
+        }
+        self.publish_index();
+    }
+
+    // Shares resting on other sell orders are already spoken for
+    fn locate(&mut self, order: &Order) -> Result<(), String> {
+        let Some(position) = self.market.ledger.lock().unwrap().accounts.get(&order.trader_id).map(|account| account.position(&order.stock_name)) else {
+            return Ok(());
+        };
+        let offered = self.venues.open_quantity(&order.stock_name, order.trader_id, Side::Sell).value() as i64;
+        let available = (position - offered).max(0);
+        let short = order.quantity.value() as i64 - available;
+        if short <= 0 {
+            return Ok(());
+        }
+        self.borrow_desk.locate(order.trader_id, &order.stock_name, short as u64)?;
+        println!("  LOCATE: Trader {} located {} {} shares to short", order.trader_id + 1, short, order.stock_name);
+        Ok(())
+    }
+
+    // Expire DAY orders, mark every account at the official close and finish the day's bars
+    fn end_of_day(&mut self) {
+        self.market.wait_for_close();
+        let mut names: Vec<String> = self.venues.primary.books.keys().cloned().collect();
+        names.sort();
+
+        for name in &names {
+            let expired: usize = self.venues.iter_mut()
+                .filter_map(|venue| venue.books.get_mut(name))
+                .map(|book| book.expire_day_orders())
+                .sum();
+            if expired > 0 {
+                println!("EXPIRED: {} DAY orders for {}", expired, name);
+                self.publish_book(name);
+            }
+        }
+        for (symbol, expired) in self.options.expire_day_orders().into_iter().chain(self.futures.expire_day_orders()) {
+            println!("EXPIRED: {} DAY orders for {}", expired, symbol);
+            self.publish_derivative_quote(&symbol);
+        }
+
+        let closing_prices: HashMap<String, Price> = self.stocks.snapshot().iter()
+            .map(|s| (s.stock_name.clone(), s.closing_price.unwrap_or(s.current_price)))
+            .collect();
+        self.expire_options(&closing_prices);
+        self.report_options(&closing_prices);
+        self.settle_futures(&closing_prices);
+        let closing_prices = self.mark_prices(closing_prices);
+
+        // Borrow follows the short positions left at the close; fees and buy-ins hit cash before marking
+        {
+            let mut ledger = self.market.ledger.lock().unwrap();
+            self.borrow_desk.reconcile(&mut ledger.accounts);
+            // Shares stay on loan over weekends and holidays, so the fee runs to the next session
+            let days = (self.calendar.next_trading_day(self.date) - self.date).num_days() as u32;
+            for fee in self.borrow_desk.accrue_fees(&mut ledger.accounts, &closing_prices, days) {
+                println!("BORROW FEE {}: Trader {} {:.2} {} on {} borrowed {} shares for {} days", self.date, fee.trader_id + 1, fee.amount, fee.currency,
+                fee.quantity, fee.stock_name, fee.days);
+            }
+            for buy_in in self.borrow_desk.recall(&mut ledger.accounts, &closing_prices) {
+                println!("\x1b[33mBUY-IN {}: Trader {} bought in {} {} shares at ${:.2}\x1b[0m", self.date, buy_in.trader_id + 1, buy_in.quantity,
+                buy_in.stock_name, buy_in.price);
+            }
+            ledger.marks.extend(closing_prices.clone());
+        }
+
+        // Each venue's share of the day's stock volume
+        let total_volume: Quantity = self.venues.iter().map(|v| v.volume).sum();
+        for venue in self.venues.iter_mut() {
+            let share = if total_volume.is_zero() { 0.0 } else { venue.volume.value() as f64 / total_volume.value() as f64 * 100.0 };
+            let (maker, taker) = venue.fee_schedule.tier(0).map_or((0.0, 0.0), |tier| (tier.maker_per_share, tier.taker_per_share));
+            println!("VENUE {} {}: {} shares ({:.1}% of volume), maker {:+.4} taker {:+.4} per share, latency {}ms", self.date, venue.name,
+            venue.volume, share, maker, taker, venue.latency.as_millis());
+            venue.volume = Quantity::ZERO;
+        }
+
+        for etf in self.etfs.etfs() {
+            let (Some(nav), Some(&close)) = (self.etfs.nav(&etf.symbol, &closing_prices, self.fx.rates()), closing_prices.get(&etf.symbol)) else {
+                continue;
+            };
+            println!("NAV {} {}: close ${:.2}, NAV ${:.2}, premium {:+.2}%, {} shares outstanding", self.date, etf.symbol, close, nav,
+            (close.to_f64() / nav.to_f64() - 1.0) * 100.0, etf.shares_outstanding);
+        }
+        // Accounts and the index span every shard, so the last one to close reports them
+        if self.market.close_shard() {
+            self.report_market();
+        }
+
+        for name in &names {
+            let finished = self.bars.close(name);
+            match finished.iter().find(|bar| bar.interval == BarInterval::Daily) {
+                Some(bar) => println!("OHLC {} {}: O ${:.2} H ${:.2} L ${:.2} C ${:.2} V {} ({} trades)", self.date, name,
+                bar.open, bar.high, bar.low, bar.close, bar.volume, bar.trades),
+                None => println!("OHLC {} {}: no trades", self.date, name),
+            }
+        }
+    }
+
+    // Each trader's return is reported against the index as a benchmark, with everything
+    // marked in the base currency at the closing FX rates
+    fn report_market(&self) {
+        let mut index = self.market.index.lock().unwrap();
+        let index_change = index.change();
+        println!("INDEX {} {}: {:.2} ({:+.2}%)", self.date, index.name, index.value(), index_change * 100.0);
+        let fx = self.fx.rates().clone();
+        let rates: Vec<String> = fx.currencies().iter()
+            .map(|currency| format!("{} {:.4}", currency, fx.rate(currency).unwrap_or(0.0)))
+            .collect();
+        println!("FX {} ({} per unit): {}", self.date, fx.base, rates.join(", "));
+        let mut ledger = self.market.ledger.lock().unwrap();
+        let Ledger { accounts, marks: closing_prices } = &mut *ledger;
+        let mut ids: Vec<usize> = accounts.keys().copied().collect();
+        ids.sort();
+        for id in ids {
+            let account = accounts.get_mut(&id).unwrap();
+            let (equity, pnl) = account.mark_to_market(closing_prices, &fx);
+            let previous_equity = equity - pnl;
+            let return_pct = if previous_equity != Amount::ZERO { pnl.to_f64() / previous_equity.to_f64() * 100.0 } else { 0.0 };
+            let cash: Vec<String> = account.balance_currencies().iter()
+                .map(|currency| format!("{} {:.2} (unsettled {:+.2})", currency, account.cash.get(currency).copied().unwrap_or_default(),
+                account.unsettled_cash.get(currency).copied().unwrap_or_default()))
+                .collect();
+            println!("MARK {}: Trader {} ({}) cash {}, positions {} {:.2}, equity {} {:.2}, P&L {:+.2} ({:+.2}%, {:+.2}% vs {})", self.date, id + 1,
+            account.account_type.as_str(), cash.join(", "), fx.base, account.market_value(closing_prices, &fx), fx.base, equity, pnl, return_pct,
+            return_pct - index_change * 100.0, index.name);
+            println!("STATEMENT {}: Trader {}", self.date, id + 1);
+            for line in account.statement(self.date) {
+                println!("{}", line);
+            }
+        }
+        index.close_session();
+    }
+
+    // Contracts expiring today are exercised automatically if in the money at the close,
+    // delivering stock at the strike on the normal settlement cycle; the rest expire worthless
+    fn expire_options(&mut self, closing_prices: &HashMap<String, Price>) {
+        let mut ledger = self.market.ledger.lock().unwrap();
+        let mut ids: Vec<usize> = ledger.accounts.keys().copied().collect();
+        ids.sort();
+        for contract in self.options.expire(self.date) {
+            let Some(&spot) = closing_prices.get(&contract.underlying) else {
+                continue;
+            };
+            let intrinsic = contract.intrinsic(spot);
+            for &id in &ids {
+                let account = ledger.accounts.get_mut(&id).unwrap();
+                let contracts = account.close_option(&contract.symbol);
+                if contracts == 0 {
+                    continue;
+                }
+                if intrinsic <= 0.0 {
+                    println!("OPTION EXPIRED {}: Trader {} {:+} {} worthless ({} closed ${:.2}, strike ${:.2})", self.date, id + 1, contracts,
+                    contract.symbol, contract.underlying, spot, contract.strike);
+                    continue;
+                }
+                // Long calls and short puts take delivery; long puts and short calls make it
+                let side = match (contract.kind, contracts > 0) {
+                    (OptionKind::Call, true) | (OptionKind::Put, false) => Side::Buy,
+                    _ => Side::Sell,
+                };
+                let shares = Quantity::new(contracts.unsigned_abs() * contract.multiplier);
+                account.apply_fill(&contract.underlying, side, contract.strike, shares);
+                self.settlement.record_delivery(id, &contract.underlying, side, shares, contract.strike, self.date);
+                println!("\x1b[33mOPTION {} {}: Trader {} {:+} {}, {} {} {} shares at ${:.2}\x1b[0m", if contracts > 0 { "EXERCISED" } else { "ASSIGNED" },
+                self.date, id + 1, contracts, contract.symbol, side.as_str(), shares, contract.underlying, contract.strike);
+            }
+        }
+    }
+
+    // Daily mark-to-market: every open futures position is paid or charged the move
+    // in its settlement price as variation margin. Expiring contracts settle finally
+    // against the underlying's close and are closed out.
+    fn settle_futures(&mut self, closing_prices: &HashMap<String, Price>) {
+        let mut underlying_prices = closing_prices.clone();
+        let index = self.index();
+        underlying_prices.insert(index.name, Price::from_f64(index.value));
+        let mut ledger = self.market.ledger.lock().unwrap();
+        let mut ids: Vec<usize> = ledger.accounts.keys().copied().collect();
+        ids.sort();
+        for settlement in self.futures.settle(self.date, &underlying_prices) {
+            let contract = &settlement.contract;
+            let source = if contract.expiry <= self.date { "final, underlying close" } else if settlement.traded { "last trade" } else { "fair value" };
+            println!("FUTURES SETTLEMENT {}: {} ${:.2} ({}), previous ${:.2}", self.date, contract.symbol, settlement.price, source, settlement.previous);
+            for &id in &ids {
+                let account = ledger.accounts.get_mut(&id).unwrap();
+                let contracts = account.futures_position(&contract.symbol);
+                if contracts == 0 {
+                    continue;
+                }
+                let variation = account.settle_variation(&contract.symbol, settlement.price, settlement.previous, contract.multiplier);
+                println!("VARIATION MARGIN {}: Trader {} {:+.2} {} on {:+} {}", self.date, id + 1, variation, contract.currency, contracts, contract.symbol);
+            }
+        }
+        for contract in self.futures.expire(self.date) {
+            for &id in &ids {
+                let contracts = ledger.accounts.get_mut(&id).unwrap().close_future(&contract.symbol);
+                if contracts != 0 {
+                    println!("\x1b[33mFUTURE EXPIRED {}: Trader {} {:+} {} cash-settled\x1b[0m", self.date, id + 1, contracts, contract.symbol);
+                }
+            }
+        }
+    }
+
+    // Theoretical values and Greeks of every listed contract at the close
+    fn report_options(&self, closing_prices: &HashMap<String, Price>) {
+        for contract in self.options.contracts() {
+            let Some(&spot) = closing_prices.get(&contract.underlying) else {
+                continue;
+            };
+            let time = contract.time_to_expiry(self.date);
+            let volatility = self.options.surface.volatility(&contract.underlying, contract.strike, spot, time);
+            let value = self.options.surface.value(contract, spot, self.date);
+            println!("OPTION {} {}: {} {} strike ${:.2} expiring {}, vol {:.1}%, theo ${:.2}, delta {:+.3}, gamma {:.5}, vega {:.2}, theta {:+.2}, rho {:+.2}",
+            self.date, contract.symbol, contract.underlying, contract.kind.as_str(), contract.strike, contract.expiry, volatility * 100.0, value.price,
+            value.delta, value.gamma, value.vega, value.theta, value.rho);
+        }
+    }
+
+    // Premium moves straight between the accounts; options are not charged fees
+    fn apply_option_trades(&mut self, contract: &OptionContract, trades: &[Trade]) {
+        for trade in trades {
+            for (trader_id, side) in [(trade.buyer_id, Side::Buy), (trade.seller_id, Side::Sell)] {
+                if let Some(account) = self.market.ledger.lock().unwrap().accounts.get_mut(&trader_id) {
+                    account.apply_option_fill(&trade.stock_name, side, trade.price, trade.quantity, contract.multiplier);
+                }
+                self.fund_in(trader_id, &trade.stock_name);
+            }
+            let time = self.date.and_time(Local::now().time());
+            self.tape.record(trade, time);
+
+            let current_time = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+            println!("{}, Order processing... Trader {} bought {} {} contracts from Trader {} at ${:.2}", current_time, trade.buyer_id + 1,
+            trade.quantity, trade.stock_name, trade.seller_id + 1, trade.price);
+        }
+    }
+
+    // Futures trades move only the difference to the last settlement price in cash
+    fn apply_future_trades(&mut self, contract: &FutureContract, trades: &[Trade]) {
+        let settlement_price = self.futures.settlement_price(&contract.symbol).unwrap_or(Price::ZERO);
+        for trade in trades {
+            for (trader_id, side) in [(trade.buyer_id, Side::Buy), (trade.seller_id, Side::Sell)] {
+                if let Some(account) = self.market.ledger.lock().unwrap().accounts.get_mut(&trader_id) {
+                    account.apply_future_fill(&trade.stock_name, side, trade.price, trade.quantity, contract.multiplier, settlement_price);
+                }
+            }
+            let time = self.date.and_time(Local::now().time());
+            self.tape.record(trade, time);
+            self.futures.on_trade(&trade.stock_name, trade.price);
+
+            let current_time = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+            println!("{}, Order processing... Trader {} bought {} {} contracts from Trader {} at ${:.2}", current_time, trade.buyer_id + 1,
+            trade.quantity, trade.stock_name, trade.seller_id + 1, trade.price);
+        }
+    }
+
+    fn apply_trades(&mut self, trades: &[Trade]) {
+        for trade in trades {
+            let buyer_fees = self.fill_account(trade.buyer_id, Side::Buy, trade);
+            let seller_fees = self.fill_account(trade.seller_id, Side::Sell, trade);
+            // Trades are stamped with the simulated trading day and the wall-clock time
+            let time = self.date.and_time(Local::now().time());
+            self.tape.record(trade, time);
+            self.settlement.record(trade, self.date, buyer_fees, seller_fees);
+            let venue = trade.venue.as_deref().unwrap_or(PRIMARY_VENUE);
+            if let Some(venue) = self.venues.get_mut(venue) {
+                venue.volume += trade.quantity;
+            }
+            self.analytics.on_trade(&trade.stock_name, trade.price, trade.quantity, trade.aggressor, time);
+            self.bars.on_trade(&trade.stock_name, trade.price, trade.quantity, time);
+            let volume = self.bars.latest(&trade.stock_name, BarInterval::Daily).map_or(trade.quantity, |bar| bar.volume);
+            self.market_data.publish(&trade.stock_name, MarketDataEvent::Trade { price: trade.price, quantity: trade.quantity, volume,
+            aggressor: trade.aggressor, time });
+            if self.market.index.lock().unwrap().update(&trade.stock_name, trade.price).is_some() {
+                self.publish_index();
+            }
+            for etf in self.etfs.containing(&trade.stock_name) {
+                self.publish_nav(&etf);
+            }
+
+            if let Some(mut existing_stock) = self.stocks.lock(&trade.stock_name) {
+                existing_stock.current_price = trade.price;
+
+                let current_time = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+                println!("{}, Order processing... Trader {} bought {} {} shares from Trader {} at ${:.2} on {}", current_time,
+                trade.buyer_id + 1, trade.quantity, existing_stock.stock_name, trade.seller_id + 1, existing_stock.current_price, venue);
+            }
+        }
+    }
+
+    // Move cash and shares for one side of a trade and charge its fees
+    // Returns the fees charged, which are paid when the trade settles
+    fn fill_account(&mut self, trader_id: usize, side: Side, trade: &Trade) -> Amount {
+        let fees = {
+            let mut ledger = self.market.ledger.lock().unwrap();
+            let Some(account) = ledger.accounts.get_mut(&trader_id) else {
+                return Amount::ZERO;
+            };
+            account.apply_fill(&trade.stock_name, side, trade.price, trade.quantity);
+            let liquidity = Liquidity::for_side(side, trade.aggressor);
+            let order_id = match side {
+                Side::Buy => trade.buy_order_id,
+                Side::Sell => trade.sell_order_id,
+            };
+            let fees = self.venues.fee_schedule(trade.venue.as_deref()).calculate(trade.price, trade.quantity, liquidity, account.monthly_volume(self.date),
+            self.tickets.commission_charged(order_id));
+            self.tickets.charge(order_id, &fees);
+            account.charge_fees(FeeCharge { date: self.date, stock_name: trade.stock_name.clone(), side, price: trade.price,
+                quantity: trade.quantity, liquidity, fees });
+            fees.total()
+        };
+        self.fund_in(trader_id, &trade.stock_name);
+        fees
+    }
+
+    // The FX leg for a cash account that has spent more of a foreign currency than it holds
+    fn fund_in(&self, trader_id: usize, symbol: &str) {
+        let mut ledger = self.market.ledger.lock().unwrap();
+        let Some(account) = ledger.accounts.get_mut(&trader_id) else {
+            return;
+        };
+        let currency = account.currency(symbol).to_string();
+        if let Some((amount, cost)) = account.fund_in(&currency, self.fx.rates()) {
+            println!("\x1b[36m  FX: Trader {} bought {} {:.2} for {} {:.2}\x1b[0m", trader_id + 1, currency, amount, BASE_CURRENCY, cost);
+        }
+    }
+
+    fn last_price(&self, stock_name: &str) -> Option<Price> {
+        self.stocks.price(stock_name)
+    }
+}
@@ -0,0 +1,76 @@
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+
+pub const START_DATE: &str = "2024-12-23";
+pub const TRADING_DAYS: usize = 3;
+pub const HOLIDAYS: [&str; 2] = ["2024-12-25", "2025-01-01"];
+
+#[derive(Debug, Clone)]
+pub struct TradingCalendar {
+    pub start_date: NaiveDate,
+    pub holidays: Vec<NaiveDate>,
+}
+
+impl TradingCalendar {
+    pub fn new(start_date: NaiveDate, holidays: Vec<NaiveDate>) -> Self {
+        TradingCalendar { start_date, holidays }
+    }
+
+    // Calendar built from the START_DATE and HOLIDAYS constants
+    pub fn default_calendar() -> Self {
+        let start_date = NaiveDate::parse_from_str(START_DATE, "%Y-%m-%d").unwrap();
+        let holidays = HOLIDAYS.iter()
+            .map(|d| NaiveDate::parse_from_str(d, "%Y-%m-%d").unwrap())
+            .collect();
+        TradingCalendar::new(start_date, holidays)
+    }
+
+    pub fn is_trading_day(&self, date: NaiveDate) -> bool {
+        !matches!(date.weekday(), Weekday::Sat | Weekday::Sun) && !self.holidays.contains(&date)
+    }
+
+    pub fn next_trading_day(&self, date: NaiveDate) -> NaiveDate {
+        let mut next = date + Duration::days(1);
+        while !self.is_trading_day(next) {
+            next += Duration::days(1);
+        }
+        next
+    }
+
+    // The first `count` trading days on or after the start date
+    pub fn trading_days(&self, count: usize) -> Vec<NaiveDate> {
+        let mut days = Vec::with_capacity(count);
+        let mut date = self.start_date;
+        if !self.is_trading_day(date) {
+            date = self.next_trading_day(date);
+        }
+        while days.len() < count {
+            days.push(date);
+            date = self.next_trading_day(date);
+        }
+        days
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn sessions_skip_weekends_and_holidays() {
+        let calendar = TradingCalendar::default_calendar();
+        let days = calendar.trading_days(6);
+        assert_eq!(days, ["2024-12-23", "2024-12-24", "2024-12-26", "2024-12-27", "2024-12-30", "2024-12-31"].map(date));
+        assert_eq!(calendar.next_trading_day(date("2024-12-31")), date("2025-01-02"));
+        assert!(!calendar.is_trading_day(date("2024-12-28")));
+    }
+
+    #[test]
+    fn a_start_on_a_weekend_opens_the_next_monday() {
+        let calendar = TradingCalendar::new(date("2024-12-28"), Vec::new());
+        assert_eq!(calendar.trading_days(1), vec![date("2024-12-30")]);
+    }
+}
@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::order::Order;
+use crate::price::Price;
+
+// Limit-up/limit-down band around each stock's reference price
+pub const PRICE_BAND_PCT: f64 = 0.10;
+// Market-wide decline levels of the index; the last one halts trading for the rest of the session
+pub const MARKET_WIDE_LEVELS: [f64; 3] = [0.07, 0.13, 0.20];
+pub const HALT_DURATION: Duration = Duration::from_secs(3);
+pub const REOPENING_AUCTION_DURATION: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TradingState {
+    Trading,
+    Halted { until: Instant },
+    ReopeningAuction { until: Instant },
+    HaltedForDay,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OrderCheck {
+    // Goes to the book for continuous matching
+    Accept,
+    // Collected for the stock's reopening auction
+    Collect,
+    Reject(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    pub band_pct: f64,
+    pub market_wide_levels: Vec<f64>,
+    pub halt_duration: Duration,
+    pub reopening_duration: Duration,
+    reference_prices: HashMap<String, Price>,
+    states: HashMap<String, TradingState>,
+    levels_triggered: usize,
+    // What the last market-wide level breached put every stock into
+    market_wide_halt: Option<TradingState>,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        CircuitBreaker::new(PRICE_BAND_PCT, MARKET_WIDE_LEVELS.to_vec(), HALT_DURATION, REOPENING_AUCTION_DURATION)
+    }
+}
+
+impl CircuitBreaker {
+    pub fn new(band_pct: f64, market_wide_levels: Vec<f64>, halt_duration: Duration, reopening_duration: Duration) -> Self {
+        CircuitBreaker {
+            band_pct,
+            market_wide_levels,
+            halt_duration,
+            reopening_duration,
+            reference_prices: HashMap::new(),
+            states: HashMap::new(),
+            levels_triggered: 0,
+            market_wide_halt: None,
+        }
+    }
+
+    // Bands start from the previous close and every stock starts the day trading
+    pub fn start_session(&mut self, previous_closes: HashMap<String, Price>) {
+        self.states = previous_closes.keys().map(|name| (name.clone(), TradingState::Trading)).collect();
+        self.reference_prices = previous_closes;
+        self.levels_triggered = 0;
+        self.market_wide_halt = None;
+    }
+
+    pub fn set_reference(&mut self, stock_name: &str, price: Price) {
+        self.reference_prices.insert(stock_name.to_string(), price);
+    }
+
+    pub fn band(&self, stock_name: &str) -> Option<(Price, Price)> {
+        self.reference_prices.get(stock_name)
+            .map(|reference| (reference.scale(1.0 - self.band_pct), reference.scale(1.0 + self.band_pct)))
+    }
+
+    pub fn state(&self, stock_name: &str) -> TradingState {
+        self.states.get(stock_name).copied().unwrap_or(TradingState::Trading)
+    }
+
+    pub fn halted_for_day(&self) -> bool {
+        self.levels_triggered > 0 && self.levels_triggered == self.market_wide_levels.len()
+    }
+
+    // An order priced outside the band is rejected; only trades reaching the band halt the stock
+    pub fn check_order(&self, order: &Order) -> OrderCheck {
+        match self.state(&order.stock_name) {
+            TradingState::Halted { .. } => OrderCheck::Reject(format!("{} is halted", order.stock_name)),
+            TradingState::HaltedForDay => OrderCheck::Reject("market halted for the day".to_string()),
+            TradingState::ReopeningAuction { .. } => OrderCheck::Collect,
+            TradingState::Trading => {
+                let Some((lower, upper)) = self.band(&order.stock_name) else {
+                    return OrderCheck::Accept;
+                };
+                if order.price > upper {
+                    OrderCheck::Reject(format!("${:.2} is above the LIMIT UP price ${:.2}", order.price, upper))
+                } else if order.price < lower {
+                    OrderCheck::Reject(format!("${:.2} is below the LIMIT DOWN price ${:.2}", order.price, lower))
+                } else {
+                    OrderCheck::Accept
+                }
+            }
+        }
+    }
+
+    // A trade at the edge of the band halts the stock. Returns the reason if it did.
+    pub fn check_trade(&mut self, stock_name: &str, price: Price, now: Instant) -> Option<String> {
+        if self.state(stock_name) != TradingState::Trading {
+            return None;
+        }
+        let (lower, upper) = self.band(stock_name)?;
+        let reason = if price >= upper {
+            format!("LIMIT UP at ${:.2}", upper)
+        } else if price <= lower {
+            format!("LIMIT DOWN at ${:.2}", lower)
+        } else {
+            return None;
+        };
+        self.states.insert(stock_name.to_string(), TradingState::Halted { until: now + self.halt_duration });
+        println!("\x1b[41m!!! HALT: {} {}, trading paused for {}s\x1b[0m", stock_name, reason, self.halt_duration.as_secs());
+        Some(reason)
+    }
+
+    // Move expired halts into their reopening auction and return the stocks whose
+    // reopening auction is over and must be uncrossed
+    pub fn poll(&mut self, now: Instant) -> Vec<String> {
+        let mut reopened = Vec::new();
+        for (name, state) in self.states.iter_mut() {
+            match *state {
+                TradingState::Halted { until } if now >= until => {
+                    *state = TradingState::ReopeningAuction { until: now + self.reopening_duration };
+                    println!("\x1b[35mREOPENING AUCTION: {} collecting orders\x1b[0m", name);
+                }
+                TradingState::ReopeningAuction { until } if now >= until => {
+                    *state = TradingState::Trading;
+                    reopened.push(name.clone());
+                }
+                _ => {}
+            }
+        }
+        reopened.sort();
+        reopened
+    }
+
+    // `decline` is how far the market index has fallen since the previous close.
+    // Returns the level that was breached, if a new one was.
+    pub fn check_market_wide(&mut self, decline: f64, now: Instant) -> Option<f64> {
+        let mut breached = None;
+        while self.levels_triggered < self.market_wide_levels.len() && decline >= self.market_wide_levels[self.levels_triggered] {
+            breached = Some(self.market_wide_levels[self.levels_triggered]);
+            self.levels_triggered += 1;
+        }
+        breached?;
+
+        let state = if self.halted_for_day() {
+            TradingState::HaltedForDay
+        } else {
+            TradingState::Halted { until: now + self.halt_duration }
+        };
+        for existing in self.states.values_mut() {
+            *existing = state;
+        }
+        self.market_wide_halt = Some(state);
+        breached
+    }
+
+    // A shard's breaker takes on the levels the market-wide one has triggered, halting
+    // its own stocks the same way. Returns whether there was a new level to follow.
+    pub fn follow(&mut self, market: &CircuitBreaker) -> bool {
+        if market.levels_triggered <= self.levels_triggered {
+            return false;
+        }
+        self.levels_triggered = market.levels_triggered;
+        if let Some(state) = market.market_wide_halt {
+            for existing in self.states.values_mut() {
+                *existing = state;
+            }
+        }
+        true
+    }
+
+    // The closing auction takes every stock that is not halted for the day
+    pub fn resume_all(&mut self) {
+        for state in self.states.values_mut() {
+            if *state != TradingState::HaltedForDay {
+                *state = TradingState::Trading;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order::{Side, TimeInForce};
+    use crate::price::Quantity;
+
+    fn breaker() -> CircuitBreaker {
+        let mut breaker = CircuitBreaker::default();
+        breaker.start_session(HashMap::from([("TEST".to_string(), Price::from_f64(100.0))]));
+        breaker
+    }
+
+    #[test]
+    fn order_outside_band_is_rejected_without_halting() {
+        let breaker = breaker();
+        let order = Order::new(0, "TEST", Side::Buy, Price::from_f64(111.0), Quantity::new(100), TimeInForce::Day);
+        assert!(matches!(breaker.check_order(&order), OrderCheck::Reject(_)));
+        assert_eq!(breaker.state("TEST"), TradingState::Trading);
+    }
+
+    #[test]
+    fn trade_at_band_halts() {
+        let mut breaker = breaker();
+        let now = Instant::now();
+        assert_eq!(breaker.check_trade("TEST", Price::from_f64(109.99), now), None);
+        assert!(breaker.check_trade("TEST", Price::from_f64(110.0), now).is_some());
+        assert_eq!(breaker.state("TEST"), TradingState::Halted { until: now + HALT_DURATION });
+    }
+
+    #[test]
+    fn shard_follows_market_wide_levels() {
+        let mut market = CircuitBreaker::default();
+        market.start_session(HashMap::new());
+        let mut shard = breaker();
+        let now = Instant::now();
+        assert_eq!(market.check_market_wide(0.08, now), Some(MARKET_WIDE_LEVELS[0]));
+        assert!(shard.follow(&market));
+        assert_eq!(shard.state("TEST"), TradingState::Halted { until: now + HALT_DURATION });
+        assert!(!shard.follow(&market));
+
+        market.check_market_wide(0.25, now);
+        assert!(shard.follow(&market));
+        assert!(shard.halted_for_day());
+        assert_eq!(shard.state("TEST"), TradingState::HaltedForDay);
+    }
+}
@@ -0,0 +1,149 @@
+use serde::{Serialize, Deserialize};
+use chrono::NaiveDate;
+use std::fs;
+
+use crate::instrument::normalize_symbol;
+use crate::price::{Amount, Price};
+
+// Relative to the working directory; `--scenario PATH` or the RTS_SCENARIO variable points elsewhere
+pub const SCENARIO_FILE: &str = "scenario.toml";
+pub const SCENARIO_ENV: &str = "RTS_SCENARIO";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum CorporateAction {
+    // `new_shares` for every `old_shares`: 2 for 1 is a split, 1 for 10 a reverse split
+    Split { symbol: String, date: NaiveDate, new_shares: u64, old_shares: u64 },
+    // Holders before the ex-date are paid `amount` per share on the pay date
+    Dividend { symbol: String, ex_date: NaiveDate, pay_date: NaiveDate, amount: Price },
+    Rename { symbol: String, date: NaiveDate, new_symbol: String },
+}
+
+impl CorporateAction {
+    pub fn symbol(&self) -> &str {
+        match self {
+            CorporateAction::Split { symbol, .. } | CorporateAction::Dividend { symbol, .. } | CorporateAction::Rename { symbol, .. } => symbol,
+        }
+    }
+
+    // The first session that trades with the action applied
+    pub fn effective_date(&self) -> NaiveDate {
+        match self {
+            CorporateAction::Split { date, .. } | CorporateAction::Rename { date, .. } => *date,
+            CorporateAction::Dividend { ex_date, .. } => *ex_date,
+        }
+    }
+}
+
+// A dividend owed to (or, for a short position, by) one trader
+#[derive(Debug, Clone, PartialEq)]
+pub struct DividendEntitlement {
+    pub trader_id: usize,
+    pub stock_name: String,
+    pub shares: i64,
+    pub amount: Amount,
+    pub currency: String,
+    pub pay_date: NaiveDate,
+}
+
+#[derive(Deserialize)]
+struct ScenarioFile {
+    #[serde(default)]
+    corporate_action: Vec<CorporateAction>,
+}
+
+// Corporate actions waiting for their date, and dividends waiting to be paid
+#[derive(Debug, Clone, Default)]
+pub struct CorporateActions {
+    scheduled: Vec<CorporateAction>,
+    entitlements: Vec<DividendEntitlement>,
+}
+
+impl CorporateActions {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let contents = fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path, e))?;
+        CorporateActions::from_toml(&contents)
+    }
+
+    pub fn from_toml(contents: &str) -> Result<Self, String> {
+        let file: ScenarioFile = toml::from_str(contents).map_err(|e| format!("invalid scenario file: {}", e))?;
+        CorporateActions::new(file.corporate_action)
+    }
+
+    pub fn new(mut actions: Vec<CorporateAction>) -> Result<Self, String> {
+        for action in &actions {
+            match action {
+                CorporateAction::Split { symbol, new_shares, old_shares, .. } if *new_shares == 0 || *old_shares == 0 => {
+                    return Err(format!("{}: split ratio must be positive", symbol));
+                }
+                CorporateAction::Dividend { symbol, ex_date, pay_date, amount } if *amount <= Price::ZERO || pay_date < ex_date => {
+                    return Err(format!("{}: dividend needs a positive amount and a pay date on or after the ex-date", symbol));
+                }
+                CorporateAction::Rename { symbol, new_symbol, .. } if new_symbol.trim().is_empty() => {
+                    return Err(format!("{}: rename needs a new symbol", symbol));
+                }
+                _ => {}
+            }
+        }
+        // Stable, so actions on the same day apply in file order
+        actions.sort_by_key(|a| a.effective_date());
+        Ok(CorporateActions { scheduled: actions, entitlements: Vec::new() })
+    }
+
+    // Just the actions on `symbols`, following them through any symbol changes, for a
+    // broker shard that trades only those
+    pub fn for_symbols(&self, symbols: &[String]) -> CorporateActions {
+        let mut symbols = symbols.to_vec();
+        let mut scheduled = Vec::new();
+        for action in &self.scheduled {
+            if !symbols.contains(&normalize_symbol(action.symbol())) {
+                continue;
+            }
+            if let CorporateAction::Rename { new_symbol, .. } = action {
+                symbols.push(normalize_symbol(new_symbol));
+            }
+            scheduled.push(action.clone());
+        }
+        let entitlements = self.entitlements.iter()
+            .filter(|e| symbols.contains(&e.stock_name))
+            .cloned()
+            .collect();
+        CorporateActions { scheduled, entitlements }
+    }
+
+    pub fn scheduled(&self) -> &[CorporateAction] {
+        &self.scheduled
+    }
+
+    // Actions that take effect on or before `date` and have not been applied yet
+    pub fn due(&mut self, date: NaiveDate) -> Vec<CorporateAction> {
+        let count = self.scheduled.iter().take_while(|a| a.effective_date() <= date).count();
+        self.scheduled.drain(..count).collect()
+    }
+
+    pub fn entitle(&mut self, entitlement: DividendEntitlement) {
+        self.entitlements.push(entitlement);
+    }
+
+    // Dividends payable on or before `date`
+    pub fn payable(&mut self, date: NaiveDate) -> Vec<DividendEntitlement> {
+        let (due, waiting) = self.entitlements.drain(..).partition(|e| e.pay_date <= date);
+        self.entitlements = waiting;
+        due
+    }
+
+    // Later actions and unpaid dividends follow a symbol change
+    pub fn rename(&mut self, old: &str, new: &str) {
+        for action in self.scheduled.iter_mut() {
+            match action {
+                CorporateAction::Split { symbol, .. } | CorporateAction::Dividend { symbol, .. } | CorporateAction::Rename { symbol, .. } if symbol == old => {
+                    *symbol = new.to_string();
+                }
+                _ => {}
+            }
+        }
+        for entitlement in self.entitlements.iter_mut().filter(|e| e.stock_name == old) {
+            entitlement.stock_name = new.to_string();
+        }
+    }
+}
@@ -0,0 +1,204 @@
+use serde::{Serialize, Deserialize};
+
+use crate::order::Side;
+use crate::order_book::{OrderBook, PriceLevel};
+use crate::price::{Price, Quantity};
+
+// A full snapshot goes out after this many incremental updates to a book,
+// so a subscriber that missed an update is never out of sync for long
+pub const DEPTH_SNAPSHOT_INTERVAL: u64 = 20;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LevelAction {
+    Add,
+    Modify,
+    Delete,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LevelChange {
+    pub side: Side,
+    pub action: LevelAction,
+    pub level: PriceLevel,
+}
+
+// Each book has its own sequence. An update moves it on by one; a snapshot
+// carries the sequence of the last update it includes.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum DepthMessage {
+    Snapshot { sequence: u64, bids: Vec<PriceLevel>, asks: Vec<PriceLevel> },
+    Update { sequence: u64, changes: Vec<LevelChange> },
+}
+
+// Sent on the order queue by a subscriber that lost sync, so the broker publishes
+// a snapshot straight away instead of the subscriber waiting for the next one
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SnapshotRequest {
+    pub symbol: String,
+}
+
+// Broker side: remembers what was last published for one book and sends the difference
+#[derive(Debug, Clone, Default)]
+pub struct DepthFeed {
+    sequence: u64,
+    bids: Vec<PriceLevel>,
+    asks: Vec<PriceLevel>,
+    updates_since_snapshot: u64,
+}
+
+impl DepthFeed {
+    pub fn snapshot(&mut self, book: &OrderBook) -> DepthMessage {
+        self.bids = book.depth(Side::Buy);
+        self.asks = book.depth(Side::Sell);
+        self.updates_since_snapshot = 0;
+        DepthMessage::Snapshot { sequence: self.sequence, bids: self.bids.clone(), asks: self.asks.clone() }
+    }
+
+    // Nothing when the book looks the same; an update, followed by a snapshot when one is due
+    pub fn update(&mut self, book: &OrderBook) -> Vec<DepthMessage> {
+        let bids = book.depth(Side::Buy);
+        let asks = book.depth(Side::Sell);
+        let mut changes = diff_levels(Side::Buy, &self.bids, &bids);
+        changes.extend(diff_levels(Side::Sell, &self.asks, &asks));
+        if changes.is_empty() {
+            return Vec::new();
+        }
+
+        self.sequence += 1;
+        self.bids = bids;
+        self.asks = asks;
+        self.updates_since_snapshot += 1;
+        let mut messages = vec![DepthMessage::Update { sequence: self.sequence, changes }];
+        if self.updates_since_snapshot >= DEPTH_SNAPSHOT_INTERVAL {
+            messages.push(self.snapshot(book));
+        }
+        messages
+    }
+}
+
+fn diff_levels(side: Side, old: &[PriceLevel], new: &[PriceLevel]) -> Vec<LevelChange> {
+    let mut changes = Vec::new();
+    for level in old {
+        if !new.iter().any(|l| l.price == level.price) {
+            changes.push(LevelChange { side, action: LevelAction::Delete, level: *level });
+        }
+    }
+    for level in new {
+        match old.iter().find(|l| l.price == level.price) {
+            None => changes.push(LevelChange { side, action: LevelAction::Add, level: *level }),
+            Some(previous) if previous != level => changes.push(LevelChange { side, action: LevelAction::Modify, level: *level }),
+            Some(_) => {}
+        }
+    }
+    changes
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DepthStatus {
+    Applied,
+    // Already seen, or received while waiting for a snapshot
+    Ignored,
+    // An update went missing; the book is cleared until the next snapshot
+    Gap { expected: u64, received: u64 },
+    Recovered,
+}
+
+// Subscriber side: a book rebuilt from the feed. It starts out of sync and
+// only trusts updates that follow on from the last snapshot it applied.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct DepthBook {
+    pub sequence: u64,
+    pub bids: Vec<PriceLevel>,
+    pub asks: Vec<PriceLevel>,
+    pub synced: bool,
+}
+
+impl DepthBook {
+    pub fn apply(&mut self, message: &DepthMessage) -> DepthStatus {
+        match message {
+            DepthMessage::Snapshot { sequence, bids, asks } => {
+                if self.synced && *sequence < self.sequence {
+                    return DepthStatus::Ignored;
+                }
+                let recovered = !self.synced;
+                self.sequence = *sequence;
+                self.bids = bids.clone();
+                self.asks = asks.clone();
+                self.synced = true;
+                if recovered { DepthStatus::Recovered } else { DepthStatus::Applied }
+            }
+            DepthMessage::Update { sequence, changes } => {
+                if !self.synced || *sequence <= self.sequence {
+                    return DepthStatus::Ignored;
+                }
+                if *sequence != self.sequence + 1 {
+                    let expected = self.sequence + 1;
+                    self.bids.clear();
+                    self.asks.clear();
+                    self.synced = false;
+                    return DepthStatus::Gap { expected, received: *sequence };
+                }
+                for change in changes {
+                    self.apply_change(change);
+                }
+                self.sequence = *sequence;
+                DepthStatus::Applied
+            }
+        }
+    }
+
+    fn apply_change(&mut self, change: &LevelChange) {
+        let levels = match change.side {
+            Side::Buy => &mut self.bids,
+            Side::Sell => &mut self.asks,
+        };
+        let existing = levels.iter().position(|l| l.price == change.level.price);
+        match (change.action, existing) {
+            (LevelAction::Delete, Some(index)) => {
+                levels.remove(index);
+            }
+            (LevelAction::Add | LevelAction::Modify, Some(index)) => levels[index] = change.level,
+            (LevelAction::Add | LevelAction::Modify, None) => {
+                let better = |l: &PriceLevel| match change.side {
+                    Side::Buy => l.price > change.level.price,
+                    Side::Sell => l.price < change.level.price,
+                };
+                let index = levels.iter().take_while(|l| better(l)).count();
+                levels.insert(index, change.level);
+            }
+            (LevelAction::Delete, None) => {}
+        }
+    }
+
+    pub fn best_bid(&self) -> Option<PriceLevel> {
+        self.bids.first().copied()
+    }
+
+    pub fn best_ask(&self) -> Option<PriceLevel> {
+        self.asks.first().copied()
+    }
+
+    pub fn level(&self, side: Side, price: Price) -> Option<PriceLevel> {
+        let levels = match side {
+            Side::Buy => &self.bids,
+            Side::Sell => &self.asks,
+        };
+        levels.iter().find(|l| l.price == price).copied()
+    }
+
+    // Shares that would trade before a new order joining `side` at `price`:
+    // everything at better prices plus the queue already at that price
+    pub fn quantity_ahead(&self, side: Side, price: Price) -> Quantity {
+        let levels = match side {
+            Side::Buy => &self.bids,
+            Side::Sell => &self.asks,
+        };
+        levels.iter()
+            .filter(|l| match side {
+                Side::Buy => l.price >= price,
+                Side::Sell => l.price <= price,
+            })
+            .map(|l| l.quantity)
+            .sum()
+    }
+}
@@ -0,0 +1,217 @@
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+
+use crate::account::Account;
+use crate::fx::FxRates;
+use crate::instrument::InstrumentRegistry;
+use crate::order::Side;
+use crate::price::{Amount, Price, Quantity};
+
+// Charged to the participant for every creation or redemption request, in the ETF's currency
+pub const CREATION_FEE: Amount = Amount::from_units(500);
+
+// Shares of one stock in a creation unit
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BasketComponent {
+    pub symbol: String,
+    pub shares: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CreationAction {
+    // Basket in, ETF shares out
+    Create,
+    // ETF shares in, basket out
+    Redeem,
+}
+
+impl CreationAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CreationAction::Create => "create",
+            CreationAction::Redeem => "redeem",
+        }
+    }
+}
+
+// What an authorized participant sends the broker, on the same queue as orders. With
+// an `etf_limit` the broker also trades around the swap, all or nothing: a creation
+// buys the basket first and sells the new ETF shares at no less than the limit, a
+// redemption buys the ETF shares at no more than the limit and sells the basket after.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CreationRequest {
+    pub participant_id: usize,
+    pub etf: String,
+    pub action: CreationAction,
+    pub units: u64,
+    #[serde(default)]
+    pub etf_limit: Option<Price>,
+}
+
+// An exchange-traded fund listed as an instrument. Each creation unit of
+// `creation_unit` ETF shares is backed by one basket.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Etf {
+    pub symbol: String,
+    pub currency: String,
+    pub creation_unit: u64,
+    pub basket: Vec<BasketComponent>,
+    pub shares_outstanding: u64,
+}
+
+impl Etf {
+    // Net asset value per ETF share at `prices`, in the ETF's currency; none until
+    // every component has a price and an FX rate
+    pub fn nav(&self, prices: &HashMap<String, Price>, currencies: &HashMap<String, String>, fx: &FxRates) -> Option<Price> {
+        let mut value = Amount::ZERO;
+        for component in &self.basket {
+            let price = prices.get(&component.symbol)?;
+            let currency = currencies.get(&component.symbol).map_or(self.currency.as_str(), |c| c.as_str());
+            value += fx.convert(price.notional(Quantity::new(component.shares)), currency, &self.currency)?;
+        }
+        Some(Price::from_f64(value.to_f64() / self.creation_unit as f64))
+    }
+
+    pub fn contains(&self, symbol: &str) -> bool {
+        self.basket.iter().any(|c| c.symbol == symbol)
+    }
+}
+
+// The ETF issuer's side of the primary market: baskets and shares outstanding
+#[derive(Debug, Clone, Default)]
+pub struct EtfDesk {
+    etfs: Vec<Etf>,
+    // Currency each basket component trades in
+    currencies: HashMap<String, String>,
+}
+
+impl EtfDesk {
+    pub fn new(registry: &InstrumentRegistry) -> Self {
+        let etfs = registry.instruments().iter()
+            .filter(|i| i.is_etf())
+            .map(|i| Etf { symbol: i.symbol.clone(), currency: i.currency.clone(), creation_unit: i.creation_unit, basket: i.basket.clone(),
+                shares_outstanding: i.shares_outstanding })
+            .collect();
+        let currencies = registry.instruments().iter()
+            .map(|i| (i.symbol.clone(), i.currency.clone()))
+            .collect();
+        EtfDesk { etfs, currencies }
+    }
+
+    pub fn etfs(&self) -> &[Etf] {
+        &self.etfs
+    }
+
+    pub fn get(&self, symbol: &str) -> Option<&Etf> {
+        self.etfs.iter().find(|e| e.symbol == symbol)
+    }
+
+    // ETFs whose iNAV moves when `symbol` trades
+    pub fn containing(&self, symbol: &str) -> Vec<String> {
+        self.etfs.iter().filter(|e| e.contains(symbol)).map(|e| e.symbol.clone()).collect()
+    }
+
+    pub fn nav(&self, symbol: &str, prices: &HashMap<String, Price>, fx: &FxRates) -> Option<Price> {
+        self.get(symbol)?.nav(prices, &self.currencies, fx)
+    }
+
+    // Swap baskets for ETF shares or back. Creations need the basket on hand and
+    // redemptions the ETF shares, settled or not; both pay CREATION_FEE. The shares
+    // move like a fill, so returns what has to settle: (symbol, Buy for received or
+    // Sell for delivered, quantity).
+    pub fn process(&mut self, request: &CreationRequest, account: &mut Account) -> Result<Vec<(String, Side, Quantity)>, String> {
+        let etf = self.etfs.iter_mut().find(|e| e.symbol == request.etf).ok_or_else(|| format!("{} is not an ETF", request.etf))?;
+        if request.units == 0 {
+            return Err("at least one creation unit is needed".to_string());
+        }
+        let etf_shares = etf.creation_unit * request.units;
+        let (basket_side, etf_side, shortfall) = match request.action {
+            CreationAction::Create => (Side::Sell, Side::Buy, etf.basket.iter()
+                .find(|c| account.position(&c.symbol) < (c.shares * request.units) as i64)
+                .map(|c| format!("not enough {} shares for the basket", c.symbol))),
+            CreationAction::Redeem => (Side::Buy, Side::Sell, (account.position(&etf.symbol) < etf_shares as i64)
+                .then(|| format!("not enough {} shares to redeem", etf.symbol))),
+        };
+        if let Some(reason) = shortfall {
+            return Err(reason);
+        }
+        let mut transfers: Vec<(String, Side, Quantity)> = etf.basket.iter()
+            .map(|c| (c.symbol.clone(), basket_side, Quantity::new(c.shares * request.units)))
+            .collect();
+        transfers.push((etf.symbol.clone(), etf_side, Quantity::new(etf_shares)));
+        for (symbol, side, quantity) in &transfers {
+            account.apply_transfer(symbol, *side, *quantity);
+        }
+        account.credit(&etf.currency, -CREATION_FEE);
+        etf.shares_outstanding = match request.action {
+            CreationAction::Create => etf.shares_outstanding + etf_shares,
+            CreationAction::Redeem => etf.shares_outstanding - etf_shares,
+        };
+        Ok(transfers)
+    }
+
+    pub fn rename(&mut self, old: &str, new: &str) {
+        for etf in self.etfs.iter_mut() {
+            if etf.symbol == old {
+                etf.symbol = new.to_string();
+            }
+            for component in etf.basket.iter_mut().filter(|c| c.symbol == old) {
+                component.symbol = new.to_string();
+            }
+        }
+        if let Some(currency) = self.currencies.remove(old) {
+            self.currencies.insert(new.to_string(), currency);
+        }
+    }
+
+    // Baskets hold post-split shares so a creation unit keeps its value
+    pub fn split(&mut self, symbol: &str, new_shares: u64, old_shares: u64) {
+        for etf in self.etfs.iter_mut() {
+            if etf.symbol == symbol {
+                etf.shares_outstanding = etf.shares_outstanding * new_shares / old_shares;
+                etf.creation_unit = etf.creation_unit * new_shares / old_shares;
+            }
+            for component in etf.basket.iter_mut().filter(|c| c.symbol == symbol) {
+                component.shares = component.shares * new_shares / old_shares;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::AccountType;
+    use crate::calendar::TradingCalendar;
+    use crate::instrument::INSTRUMENTS_FILE;
+    use crate::settlement::{SettlementEngine, SETTLEMENT_DAYS};
+
+    #[test]
+    fn creation_moves_unsettled_shares_until_settlement() {
+        let registry = InstrumentRegistry::load(INSTRUMENTS_FILE).unwrap();
+        let mut desk = EtfDesk::new(&registry);
+        let etf = desk.etfs()[0].clone();
+        let mut accounts = HashMap::from([(0, Account::new(0, &registry.stocks(), AccountType::Margin, &FxRates::default()))]);
+        let before: HashMap<String, i64> = accounts[&0].positions.clone();
+        let calendar = TradingCalendar::default_calendar();
+        let days = calendar.trading_days(SETTLEMENT_DAYS + 1);
+        let mut settlement = SettlementEngine::new(SETTLEMENT_DAYS, calendar);
+
+        let request = CreationRequest { participant_id: 0, etf: etf.symbol.clone(), action: CreationAction::Create, units: 1, etf_limit: None };
+        for (symbol, side, quantity) in desk.process(&request, accounts.get_mut(&0).unwrap()).unwrap() {
+            settlement.record_delivery(0, &symbol, side, quantity, Price::ZERO, days[0]);
+        }
+        assert_eq!(desk.get(&etf.symbol).unwrap().shares_outstanding, etf.shares_outstanding + etf.creation_unit);
+        let account = &accounts[&0];
+        assert_eq!(account.positions, before);
+        assert_eq!(account.position(&etf.symbol), before[&etf.symbol] + etf.creation_unit as i64);
+
+        settlement.settle(days[SETTLEMENT_DAYS], &mut accounts);
+        let account = &accounts[&0];
+        assert_eq!(account.positions[&etf.symbol], before[&etf.symbol] + etf.creation_unit as i64);
+        for component in &etf.basket {
+            assert_eq!(account.positions[&component.symbol], before[&component.symbol] - component.shares as i64);
+        }
+    }
+}
@@ -0,0 +1,227 @@
+use chrono::NaiveDate;
+use std::collections::HashMap;
+
+use crate::order::Side;
+use crate::price::{Amount, Price, Quantity};
+
+pub const COMMISSION_PER_SHARE: f64 = 0.005;
+pub const COMMISSION_PERCENTAGE: f64 = 0.0005;
+pub const MINIMUM_TICKET_FEE: Amount = Amount::from_units(1);
+
+// Exchange fees per share by shares traded so far this month. A negative
+// maker rate is a rebate for adding liquidity.
+pub fn default_tiers() -> Vec<VolumeTier> {
+    vec![
+        VolumeTier { min_monthly_volume: 0, maker_per_share: -0.0020, taker_per_share: 0.0030 },
+        VolumeTier { min_monthly_volume: 5_000, maker_per_share: -0.0025, taker_per_share: 0.0028 },
+        VolumeTier { min_monthly_volume: 20_000, maker_per_share: -0.0030, taker_per_share: 0.0025 },
+    ]
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VolumeTier {
+    pub min_monthly_volume: u64,
+    pub maker_per_share: f64,
+    pub taker_per_share: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Liquidity {
+    // The resting order in a continuous match
+    Maker,
+    // The incoming order that crossed the spread
+    Taker,
+    // Auction fills neither add nor take liquidity and pay no exchange fee
+    Auction,
+}
+
+impl Liquidity {
+    // Which side of a trade `side` was on, given the trade's aggressor
+    pub fn for_side(side: Side, aggressor: Option<Side>) -> Liquidity {
+        match aggressor {
+            None => Liquidity::Auction,
+            Some(aggressor) if aggressor == side => Liquidity::Taker,
+            Some(_) => Liquidity::Maker,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Liquidity::Maker => "maker",
+            Liquidity::Taker => "taker",
+            Liquidity::Auction => "auction",
+        }
+    }
+}
+
+// Fees on one fill, itemized. Only the exchange fee can be negative (a rebate).
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct FeeBreakdown {
+    pub per_share: Amount,
+    pub percentage: Amount,
+    // Added when the commission falls short of the minimum ticket fee
+    pub minimum_top_up: Amount,
+    pub exchange_fee: Amount,
+}
+
+impl FeeBreakdown {
+    pub fn commission(&self) -> Amount {
+        self.per_share + self.percentage + self.minimum_top_up
+    }
+
+    pub fn total(&self) -> Amount {
+        self.commission() + self.exchange_fee
+    }
+}
+
+impl std::ops::AddAssign for FeeBreakdown {
+    fn add_assign(&mut self, other: FeeBreakdown) {
+        self.per_share += other.per_share;
+        self.percentage += other.percentage;
+        self.minimum_top_up += other.minimum_top_up;
+        self.exchange_fee += other.exchange_fee;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeeSchedule {
+    pub per_share: f64,
+    pub percentage: f64,
+    // Charged once per order, however many fills it takes
+    pub minimum_ticket: Amount,
+    // Sorted by min_monthly_volume
+    pub tiers: Vec<VolumeTier>,
+}
+
+impl Default for FeeSchedule {
+    fn default() -> Self {
+        FeeSchedule::new(COMMISSION_PER_SHARE, COMMISSION_PERCENTAGE, MINIMUM_TICKET_FEE, default_tiers())
+    }
+}
+
+impl FeeSchedule {
+    pub fn new(per_share: f64, percentage: f64, minimum_ticket: Amount, mut tiers: Vec<VolumeTier>) -> Self {
+        tiers.sort_by_key(|t| t.min_monthly_volume);
+        FeeSchedule { per_share, percentage, minimum_ticket, tiers }
+    }
+
+    // Trading is free when nothing is configured
+    pub fn free() -> Self {
+        FeeSchedule::new(0.0, 0.0, Amount::ZERO, Vec::new())
+    }
+
+    pub fn tier(&self, monthly_volume: u64) -> Option<&VolumeTier> {
+        self.tiers.iter().rev().find(|t| monthly_volume >= t.min_monthly_volume)
+    }
+
+    // Fees on one fill of an order that has already paid `commission_charged` on earlier fills
+    pub fn calculate(&self, price: Price, quantity: Quantity, liquidity: Liquidity, monthly_volume: u64, commission_charged: Amount) -> FeeBreakdown {
+        let shares = quantity.value() as f64;
+        let per_share = Amount::from_f64(self.per_share * shares);
+        let percentage = price.notional(quantity).scale(self.percentage);
+        let minimum_top_up = (self.minimum_ticket - commission_charged - per_share - percentage).max(Amount::ZERO);
+        let exchange_fee = match (liquidity, self.tier(monthly_volume)) {
+            (Liquidity::Maker, Some(tier)) => Amount::from_f64(tier.maker_per_share * shares),
+            (Liquidity::Taker, Some(tier)) => Amount::from_f64(tier.taker_per_share * shares),
+            _ => Amount::ZERO,
+        };
+        FeeBreakdown { per_share, percentage, minimum_top_up, exchange_fee }
+    }
+}
+
+// Commission charged so far on each order a trader sent. Child orders the router
+// split off are charged to their parent.
+#[derive(Debug, Clone, Default)]
+pub struct OrderTickets {
+    parents: HashMap<usize, usize>,
+    commissions: HashMap<usize, Amount>,
+}
+
+impl OrderTickets {
+    pub fn route(&mut self, parent_id: usize, child_id: usize) {
+        self.parents.insert(child_id, parent_id);
+    }
+
+    fn parent(&self, order_id: usize) -> usize {
+        self.parents.get(&order_id).copied().unwrap_or(order_id)
+    }
+
+    pub fn commission_charged(&self, order_id: usize) -> Amount {
+        self.commissions.get(&self.parent(order_id)).copied().unwrap_or_default()
+    }
+
+    pub fn charge(&mut self, order_id: usize, fees: &FeeBreakdown) {
+        *self.commissions.entry(self.parent(order_id)).or_default() += fees.commission();
+    }
+
+    // A GTC order filling on a later day is a new ticket
+    pub fn clear(&mut self) {
+        self.parents.clear();
+        self.commissions.clear();
+    }
+}
+
+// One line on an account statement
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeeCharge {
+    pub date: NaiveDate,
+    pub stock_name: String,
+    pub side: Side,
+    pub price: Price,
+    pub quantity: Quantity,
+    pub liquidity: Liquidity,
+    pub fees: FeeBreakdown,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn monthly_volume_picks_the_tier() {
+        let schedule = FeeSchedule::default();
+        assert_eq!(schedule.tier(4_999).unwrap().min_monthly_volume, 0);
+        assert_eq!(schedule.tier(5_000).unwrap().min_monthly_volume, 5_000);
+        assert_eq!(schedule.tier(1_000_000).unwrap().min_monthly_volume, 20_000);
+        assert!(FeeSchedule::free().tier(1_000_000).is_none());
+    }
+
+    #[test]
+    fn makers_earn_a_rebate_and_auctions_pay_no_exchange_fee() {
+        assert_eq!(Liquidity::for_side(Side::Buy, Some(Side::Buy)), Liquidity::Taker);
+        assert_eq!(Liquidity::for_side(Side::Sell, Some(Side::Buy)), Liquidity::Maker);
+        assert_eq!(Liquidity::for_side(Side::Sell, None), Liquidity::Auction);
+
+        // 1000 shares at $100: $5 per share plus $50 percentage, well over the minimum ticket
+        let schedule = FeeSchedule::default();
+        let price = Price::from_f64(100.0);
+        let quantity = Quantity::new(1_000);
+        let maker = schedule.calculate(price, quantity, Liquidity::Maker, 0, Amount::ZERO);
+        assert_eq!(maker.commission(), Amount::from_f64(55.0));
+        assert_eq!(maker.exchange_fee, Amount::from_f64(-2.0));
+        assert_eq!(maker.total(), Amount::from_f64(53.0));
+        let taker = schedule.calculate(price, quantity, Liquidity::Taker, 20_000, Amount::ZERO);
+        assert_eq!(taker.exchange_fee, Amount::from_f64(2.5));
+        let auction = schedule.calculate(price, quantity, Liquidity::Auction, 20_000, Amount::ZERO);
+        assert_eq!(auction.exchange_fee, Amount::ZERO);
+        assert_eq!(FeeSchedule::free().calculate(price, quantity, Liquidity::Taker, 0, Amount::ZERO).total(), Amount::ZERO);
+    }
+
+    #[test]
+    fn minimum_ticket_is_charged_once_per_order() {
+        let schedule = FeeSchedule::default();
+        let mut tickets = OrderTickets::default();
+        // The router split order 1 into children 2 and 3; each fills 10 shares at $10
+        tickets.route(1, 2);
+        tickets.route(1, 3);
+        let first = schedule.calculate(Price::from_f64(10.0), Quantity::new(10), Liquidity::Taker, 0, tickets.commission_charged(2));
+        tickets.charge(2, &first);
+        assert_eq!(first.commission(), MINIMUM_TICKET_FEE);
+
+        let second = schedule.calculate(Price::from_f64(10.0), Quantity::new(10), Liquidity::Taker, 0, tickets.commission_charged(3));
+        tickets.charge(3, &second);
+        assert_eq!(second.minimum_top_up, Amount::ZERO);
+        assert_eq!(second.commission(), Amount::from_f64(0.10));
+        assert_eq!(tickets.commission_charged(1), Amount::from_f64(1.10));
+    }
+}
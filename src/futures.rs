@@ -0,0 +1,235 @@
+use chrono::NaiveDate;
+use std::collections::HashMap;
+
+use crate::calendar::TradingCalendar;
+use crate::fx::BASE_CURRENCY;
+use crate::index::MarketIndex;
+use crate::instrument::InstrumentRegistry;
+use crate::options::{DAYS_PER_YEAR, RISK_FREE_RATE};
+use crate::order_book::OrderBook;
+use crate::price::Price;
+
+// Expiries in trading days after the first session: the same week and about a month out
+pub const FUTURE_EXPIRY_OFFSETS: [usize; 2] = [2, 20];
+// Index points or shares per contract
+pub const INDEX_FUTURE_MULTIPLIER: u64 = 50;
+pub const STOCK_FUTURE_MULTIPLIER: u64 = 100;
+pub const INDEX_FUTURE_TICK_SIZE: f64 = 0.25;
+// Performance bond per contract as a fraction of its notional at the last settlement price
+pub const FUTURE_INITIAL_MARGIN: f64 = 0.10;
+pub const FUTURE_MAINTENANCE_MARGIN: f64 = 0.08;
+
+// A cash-settled future on the market index or on one stock, e.g. RTSXF241226 or NIKEF241226
+#[derive(Debug, Clone, PartialEq)]
+pub struct FutureContract {
+    pub symbol: String,
+    pub underlying: String,
+    pub is_index: bool,
+    pub expiry: NaiveDate,
+    pub multiplier: u64,
+    pub tick_size: Price,
+    pub currency: String,
+}
+
+impl FutureContract {
+    pub fn new(underlying: &str, is_index: bool, expiry: NaiveDate, multiplier: u64, tick_size: Price, currency: &str) -> Self {
+        FutureContract { symbol: format!("{}F{}", underlying, expiry.format("%y%m%d")), underlying: underlying.to_string(), is_index, expiry,
+            multiplier, tick_size, currency: currency.to_string() }
+    }
+
+    // Cost of carry: the underlying grown at the risk-free rate until expiry, on the tick grid
+    pub fn fair_value(&self, spot: Price, date: NaiveDate) -> Price {
+        let time = (self.expiry - date).num_days().max(0) as f64 / DAYS_PER_YEAR;
+        spot.scale((RISK_FREE_RATE * time).exp()).round_to_tick(self.tick_size).max(self.tick_size)
+    }
+}
+
+// One contract's daily settlement
+#[derive(Debug, Clone, PartialEq)]
+pub struct FutureSettlement {
+    pub contract: FutureContract,
+    pub price: Price,
+    pub previous: Price,
+    // Whether the day's last trade set the price rather than fair value or, at expiry, the underlying
+    pub traded: bool,
+}
+
+// Every listed future with its order book and last settlement price
+#[derive(Debug, Clone)]
+pub struct FuturesMarket {
+    contracts: Vec<FutureContract>,
+    books: HashMap<String, OrderBook>,
+    settlement_prices: HashMap<String, Price>,
+    last_trades: HashMap<String, Price>,
+}
+
+impl FuturesMarket {
+    // Index futures, when given the index, and stock futures on every instrument with futures
+    // listed. The first settlement price is fair value off the index level and reference prices.
+    pub fn new(registry: &InstrumentRegistry, calendar: &TradingCalendar, index: Option<&MarketIndex>) -> Self {
+        let days = calendar.trading_days(FUTURE_EXPIRY_OFFSETS.iter().max().map_or(0, |offset| offset + 1));
+        let mut contracts = Vec::new();
+        let mut settlement_prices = HashMap::new();
+        if let Some(index) = index {
+            let index_level = Price::from_f64(index.value());
+            for offset in FUTURE_EXPIRY_OFFSETS {
+                let contract = FutureContract::new(&index.name, true, days[offset], INDEX_FUTURE_MULTIPLIER, Price::from_f64(INDEX_FUTURE_TICK_SIZE),
+                    BASE_CURRENCY);
+                settlement_prices.insert(contract.symbol.clone(), contract.fair_value(index_level, days[0]));
+                contracts.push(contract);
+            }
+        }
+        for instrument in registry.instruments().iter().filter(|i| i.futures_listed && i.is_tradable()) {
+            for offset in FUTURE_EXPIRY_OFFSETS {
+                let contract = FutureContract::new(&instrument.symbol, false, days[offset], STOCK_FUTURE_MULTIPLIER, instrument.tick_size,
+                    &instrument.currency);
+                settlement_prices.insert(contract.symbol.clone(), contract.fair_value(instrument.reference_price, days[0]));
+                contracts.push(contract);
+            }
+        }
+        let books = contracts.iter()
+            .map(|c| (c.symbol.clone(), OrderBook::new(&c.symbol)))
+            .collect();
+        FuturesMarket { contracts, books, settlement_prices, last_trades: HashMap::new() }
+    }
+
+    pub fn contracts(&self) -> &[FutureContract] {
+        &self.contracts
+    }
+
+    pub fn get(&self, symbol: &str) -> Option<&FutureContract> {
+        self.contracts.iter().find(|c| c.symbol == symbol)
+    }
+
+    pub fn book(&self, symbol: &str) -> Option<&OrderBook> {
+        self.books.get(symbol)
+    }
+
+    pub fn book_mut(&mut self, symbol: &str) -> Option<&mut OrderBook> {
+        self.books.get_mut(symbol)
+    }
+
+    pub fn settlement_price(&self, symbol: &str) -> Option<Price> {
+        self.settlement_prices.get(symbol).copied()
+    }
+
+    pub fn on_trade(&mut self, symbol: &str, price: Price) {
+        self.last_trades.insert(symbol.to_string(), price);
+    }
+
+    // What margin is charged on: one contract's notional at its last settlement price
+    pub fn notionals(&self) -> HashMap<String, Price> {
+        self.contracts.iter()
+            .filter_map(|c| Some((c.symbol.clone(), self.settlement_price(&c.symbol)?.scale(c.multiplier as f64))))
+            .collect()
+    }
+
+    // End of day: returns (symbol, expired) for every book that lost DAY orders
+    pub fn expire_day_orders(&mut self) -> Vec<(String, usize)> {
+        let mut expired: Vec<(String, usize)> = self.books.iter_mut()
+            .map(|(symbol, book)| (symbol.clone(), book.expire_day_orders()))
+            .filter(|(_, expired)| *expired > 0)
+            .collect();
+        expired.sort();
+        expired
+    }
+
+    // Settle every contract at the close. A contract settles at its last trade of the
+    // day, or at fair value off `underlying_prices` if it did not trade; on its expiry
+    // date it converges to the underlying's close.
+    pub fn settle(&mut self, date: NaiveDate, underlying_prices: &HashMap<String, Price>) -> Vec<FutureSettlement> {
+        let mut settlements = Vec::new();
+        for contract in &self.contracts {
+            let Some(&spot) = underlying_prices.get(&contract.underlying) else {
+                continue;
+            };
+            let last_trade = self.last_trades.get(&contract.symbol).copied();
+            let (price, traded) = match last_trade {
+                _ if contract.expiry <= date => (spot, false),
+                Some(price) => (price, true),
+                None => (contract.fair_value(spot, date), false),
+            };
+            let previous = self.settlement_prices.insert(contract.symbol.clone(), price).unwrap_or(price);
+            settlements.push(FutureSettlement { contract: contract.clone(), price, previous, traded });
+        }
+        self.last_trades.clear();
+        settlements
+    }
+
+    // Delist everything expiring on or before `date`; their resting orders go with them
+    pub fn expire(&mut self, date: NaiveDate) -> Vec<FutureContract> {
+        let (expired, listed): (Vec<FutureContract>, Vec<FutureContract>) = self.contracts.drain(..).partition(|c| c.expiry <= date);
+        self.contracts = listed;
+        for contract in &expired {
+            self.books.remove(&contract.symbol);
+            self.settlement_prices.remove(&contract.symbol);
+        }
+        expired
+    }
+
+    pub fn rename(&mut self, old: &str, new: &str) {
+        for contract in self.contracts.iter_mut().filter(|c| c.underlying == old) {
+            contract.underlying = new.to_string();
+        }
+    }
+
+    // A `new_shares` for `old_shares` split raises the multiplier and lowers the
+    // settlement price so open positions keep their value. Resting orders are
+    // cancelled; returns how many were.
+    pub fn split(&mut self, underlying: &str, new_shares: u64, old_shares: u64) -> usize {
+        let mut cancelled = 0;
+        for contract in self.contracts.iter_mut().filter(|c| c.underlying == underlying && !c.is_index) {
+            contract.multiplier = contract.multiplier * new_shares / old_shares;
+            if let Some(price) = self.settlement_prices.get_mut(&contract.symbol) {
+                *price = price.scale(old_shares as f64 / new_shares as f64);
+            }
+            if let Some(book) = self.books.get_mut(&contract.symbol) {
+                cancelled += book.clear();
+            }
+        }
+        cancelled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::{Account, AccountType};
+    use crate::fx::FxRates;
+    use crate::index::IndexConfig;
+    use crate::instrument::INSTRUMENTS_FILE;
+    use crate::order::Side;
+    use crate::price::{Amount, Quantity};
+
+    #[test]
+    fn settles_at_last_trade_then_fair_value_then_the_underlying_at_expiry() {
+        let registry = InstrumentRegistry::load(INSTRUMENTS_FILE).unwrap();
+        let calendar = TradingCalendar::default_calendar();
+        let days = calendar.trading_days(FUTURE_EXPIRY_OFFSETS[0] + 1);
+        let config = IndexConfig::default();
+        let index = MarketIndex::new(&config.name, config.weighting, &registry, &FxRates::default());
+        let mut market = FuturesMarket::new(&registry, &calendar, Some(&index));
+        let contract = market.contracts().iter().find(|c| !c.is_index && c.expiry == days[FUTURE_EXPIRY_OFFSETS[0]]).unwrap().clone();
+        let spot = HashMap::from([(contract.underlying.clone(), Price::from_f64(100.0))]);
+        let settle = |market: &mut FuturesMarket, date| market.settle(date, &spot).into_iter().find(|s| s.contract == contract).unwrap();
+
+        market.on_trade(&contract.symbol, Price::from_f64(101.0));
+        let first = settle(&mut market, days[0]);
+        assert_eq!((first.price, first.traded), (Price::from_f64(101.0), true));
+        let second = settle(&mut market, days[1]);
+        assert_eq!((second.price, second.previous, second.traded), (contract.fair_value(Price::from_f64(100.0), days[1]), Price::from_f64(101.0), false));
+        let last = settle(&mut market, contract.expiry);
+        assert_eq!(last.price, Price::from_f64(100.0));
+    }
+
+    #[test]
+    fn variation_margin_adds_up_to_the_move_from_the_trade_price() {
+        let mut account = Account::new(0, &[], AccountType::Margin, &FxRates::default());
+        let cash = account.balance(BASE_CURRENCY);
+        // Two contracts bought at 101 against a 100 settlement, then settled at 103 and 99
+        account.apply_future_fill("TESTF", Side::Buy, Price::from_f64(101.0), Quantity::new(2), 100, Price::from_f64(100.0));
+        assert_eq!(account.settle_variation("TESTF", Price::from_f64(103.0), Price::from_f64(100.0), 100), Amount::from_units(600));
+        assert_eq!(account.settle_variation("TESTF", Price::from_f64(99.0), Price::from_f64(103.0), 100), -Amount::from_units(800));
+        assert_eq!(account.balance(BASE_CURRENCY) - cash, -Amount::from_units(400));
+    }
+}
@@ -0,0 +1,130 @@
+use serde::{Serialize, Deserialize};
+use rand::Rng;
+use std::collections::HashMap;
+
+use crate::price::Amount;
+
+// Accounts report P&L in this currency, and every rate is quoted against it
+pub const BASE_CURRENCY: &str = "USD";
+// Largest relative move of a rate in one step of the FX process
+pub const FX_VOLATILITY: f64 = 0.001;
+// The FX process steps once for this many orders the broker receives
+pub const FX_STEP_ORDERS: usize = 10;
+// Market data symbol the rates are published under
+pub const FX_SYMBOL: &str = "FX";
+
+// Units of the base currency per unit of each currency
+pub fn default_rates() -> Vec<(&'static str, f64)> {
+    vec![("EUR", 1.08), ("HKD", 0.128), ("JPY", 0.0067)]
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FxRates {
+    pub base: String,
+    rates: HashMap<String, f64>,
+}
+
+impl FxRates {
+    pub fn new(base: &str, rates: &[(&str, f64)]) -> Self {
+        let mut rates: HashMap<String, f64> = rates.iter().map(|(currency, rate)| (currency.to_string(), *rate)).collect();
+        rates.insert(base.to_string(), 1.0);
+        FxRates { base: base.to_string(), rates }
+    }
+
+    pub fn rate(&self, currency: &str) -> Option<f64> {
+        self.rates.get(currency).copied()
+    }
+
+    // An unknown currency cannot be valued, so it counts for nothing
+    pub fn to_base(&self, amount: Amount, currency: &str) -> Amount {
+        amount.scale(self.rate(currency).unwrap_or(0.0))
+    }
+
+    pub fn convert(&self, amount: Amount, from: &str, to: &str) -> Option<Amount> {
+        Some(amount.scale(self.rate(from)? / self.rate(to)?))
+    }
+
+    // Every non-base currency, sorted
+    pub fn currencies(&self) -> Vec<&str> {
+        let mut currencies: Vec<&str> = self.rates.keys().map(|c| c.as_str()).filter(|c| *c != self.base).collect();
+        currencies.sort();
+        currencies
+    }
+}
+
+impl Default for FxRates {
+    fn default() -> Self {
+        FxRates::new(BASE_CURRENCY, &default_rates())
+    }
+}
+
+// Random walk of every rate against the base currency
+#[derive(Debug, Clone)]
+pub struct FxProcess {
+    pub volatility: f64,
+    rates: FxRates,
+}
+
+impl FxProcess {
+    pub fn new(rates: FxRates, volatility: f64) -> Self {
+        FxProcess { volatility, rates }
+    }
+
+    pub fn rates(&self) -> &FxRates {
+        &self.rates
+    }
+
+    pub fn set_rates(&mut self, rates: FxRates) {
+        self.rates = rates;
+    }
+
+    pub fn step(&mut self) -> &FxRates {
+        let mut rng = rand::thread_rng();
+        let base = self.rates.base.clone();
+        for (currency, rate) in self.rates.rates.iter_mut() {
+            if *currency != base {
+                *rate *= 1.0 + rng.gen_range(-self.volatility..=self.volatility);
+            }
+        }
+        &self.rates
+    }
+}
+
+impl Default for FxProcess {
+    fn default() -> Self {
+        FxProcess::new(FxRates::default(), FX_VOLATILITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn amounts_convert_through_the_base_currency() {
+        let fx = FxRates::default();
+        assert_eq!(fx.rate(BASE_CURRENCY), Some(1.0));
+        assert_eq!(fx.currencies(), vec!["EUR", "HKD", "JPY"]);
+        assert_eq!(fx.to_base(Amount::from_units(100), "EUR"), Amount::from_units(108));
+        assert_eq!(fx.convert(Amount::from_units(108), "USD", "EUR"), Some(Amount::from_units(100)));
+        assert_eq!(fx.convert(Amount::from_units(1_000), "EUR", "HKD"), Some(Amount::from_f64(1_000.0 * 1.08 / 0.128)));
+        // Unknown currencies cannot be valued or converted
+        assert_eq!(fx.to_base(Amount::from_units(100), "GBP"), Amount::ZERO);
+        assert_eq!(fx.convert(Amount::from_units(100), "GBP", "USD"), None);
+    }
+
+    #[test]
+    fn steps_move_each_rate_within_the_volatility() {
+        let mut process = FxProcess::new(FxRates::default(), 0.01);
+        for _ in 0..1_000 {
+            let before = process.rates().clone();
+            let after = process.step();
+            assert_eq!(after.rate(BASE_CURRENCY), Some(1.0));
+            for currency in before.currencies() {
+                let change = after.rate(currency).unwrap() / before.rate(currency).unwrap() - 1.0;
+                assert!(change.abs() <= 0.01 + 1e-12);
+            }
+        }
+        assert!(process.rates().currencies().iter().all(|c| process.rates().rate(c).unwrap() > 0.0));
+    }
+}
@@ -0,0 +1,215 @@
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+
+use crate::fx::FxRates;
+use crate::instrument::InstrumentRegistry;
+use crate::price::Price;
+
+pub const INDEX_NAME: &str = "RTSX";
+pub const INDEX_BASE_VALUE: f64 = 1_000.0;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexWeighting {
+    // Each stock counts by its market capitalisation (price x shares outstanding)
+    MarketCap,
+    // Each stock counts by its price alone, like the Dow
+    Price,
+}
+
+// The [index] table of the instrument file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct IndexConfig {
+    #[serde(default = "default_index_name")]
+    pub name: String,
+    #[serde(default = "default_weighting")]
+    pub weighting: IndexWeighting,
+}
+
+fn default_index_name() -> String {
+    INDEX_NAME.to_string()
+}
+
+fn default_weighting() -> IndexWeighting {
+    IndexWeighting::MarketCap
+}
+
+impl Default for IndexConfig {
+    fn default() -> Self {
+        IndexConfig { name: default_index_name(), weighting: default_weighting() }
+    }
+}
+
+// What gets published: the index level and where it closed the previous session
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct IndexLevel {
+    pub name: String,
+    pub value: f64,
+    pub previous_close: f64,
+}
+
+impl IndexLevel {
+    // Fractional change since the previous close, negative when the market is down
+    pub fn change(&self) -> f64 {
+        if self.previous_close == 0.0 {
+            return 0.0;
+        }
+        self.value / self.previous_close - 1.0
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MarketIndex {
+    pub name: String,
+    pub weighting: IndexWeighting,
+    // Constituent weight factors: shares outstanding converted at the listing FX rate,
+    // or 1 for price weighting
+    weights: HashMap<String, f64>,
+    prices: HashMap<String, Price>,
+    // Chosen so the index starts at INDEX_BASE_VALUE
+    divisor: f64,
+    value: f64,
+    previous_close: f64,
+}
+
+impl MarketIndex {
+    // Every stock listed for trading is a constituent, starting at its reference price; ETFs are not
+    pub fn new(name: &str, weighting: IndexWeighting, registry: &InstrumentRegistry, fx: &FxRates) -> Self {
+        let mut weights = HashMap::new();
+        let mut prices = HashMap::new();
+        for instrument in registry.tradable().into_iter().filter(|i| !i.is_etf()) {
+            let weight = match weighting {
+                // Shares outstanding at the FX rate, so capitalizations compare in the base currency
+                IndexWeighting::MarketCap => instrument.shares_outstanding as f64 * fx.rate(&instrument.currency).unwrap_or(0.0),
+                IndexWeighting::Price => 1.0,
+            };
+            weights.insert(instrument.symbol.clone(), weight);
+            prices.insert(instrument.symbol.clone(), instrument.reference_price);
+        }
+        let mut index = MarketIndex { name: name.to_string(), weighting, weights, prices, divisor: 1.0, value: 0.0, previous_close: 0.0 };
+        let total = index.total();
+        index.divisor = if total > 0.0 { total / INDEX_BASE_VALUE } else { 1.0 };
+        index.value = index.total() / index.divisor;
+        index.previous_close = index.value;
+        index
+    }
+
+    fn total(&self) -> f64 {
+        self.weights.iter()
+            .map(|(symbol, weight)| weight * self.prices.get(symbol).map(|p| p.to_f64()).unwrap_or(0.0))
+            .sum()
+    }
+
+    pub fn is_constituent(&self, symbol: &str) -> bool {
+        self.weights.contains_key(symbol)
+    }
+
+    // Returns the new level, or None if the symbol is not in the index
+    pub fn update(&mut self, symbol: &str, price: Price) -> Option<f64> {
+        if !self.is_constituent(symbol) {
+            return None;
+        }
+        self.prices.insert(symbol.to_string(), price);
+        self.value = self.total() / self.divisor;
+        Some(self.value)
+    }
+
+    // Change a constituent's weight factor without moving the index level
+    pub fn set_weight(&mut self, symbol: &str, weight: f64) {
+        if !self.is_constituent(symbol) {
+            return;
+        }
+        self.weights.insert(symbol.to_string(), weight);
+        self.divisor = self.total() / self.value;
+    }
+
+    // Reprice a constituent for a corporate action; the divisor absorbs the change
+    // so the index level does not move
+    pub fn adjust(&mut self, symbol: &str, price: Price, weight: f64) {
+        if !self.is_constituent(symbol) {
+            return;
+        }
+        self.prices.insert(symbol.to_string(), price);
+        self.set_weight(symbol, weight);
+    }
+
+    pub fn weight(&self, symbol: &str) -> Option<f64> {
+        self.weights.get(symbol).copied()
+    }
+
+    pub fn rename(&mut self, old: &str, new: &str) {
+        if let Some(weight) = self.weights.remove(old) {
+            self.weights.insert(new.to_string(), weight);
+        }
+        if let Some(price) = self.prices.remove(old) {
+            self.prices.insert(new.to_string(), price);
+        }
+    }
+
+    pub fn value(&self) -> f64 {
+        self.value
+    }
+
+    // The level at the end of the session becomes the base for the next day's change
+    pub fn close_session(&mut self) {
+        self.previous_close = self.value;
+    }
+
+    pub fn level(&self) -> IndexLevel {
+        IndexLevel { name: self.name.clone(), value: self.value, previous_close: self.previous_close }
+    }
+
+    pub fn change(&self) -> f64 {
+        self.level().change()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instrument::INSTRUMENTS_FILE;
+
+    fn index(weighting: IndexWeighting) -> MarketIndex {
+        let registry = InstrumentRegistry::load(INSTRUMENTS_FILE).unwrap();
+        let registry = registry.subset(&["NIKE".to_string(), "PUMA".to_string()]).unwrap();
+        MarketIndex::new(INDEX_NAME, weighting, &registry, &FxRates::default())
+    }
+
+    #[test]
+    fn only_listed_stocks_are_constituents() {
+        let registry = InstrumentRegistry::load(INSTRUMENTS_FILE).unwrap();
+        let index = MarketIndex::new(INDEX_NAME, IndexWeighting::MarketCap, &registry, &FxRates::default());
+        assert!(index.is_constituent("NIKE") && index.is_constituent("ADIDAS"));
+        assert!(!index.is_constituent("SPRT") && !index.is_constituent("ASICS"));
+        assert_eq!(index.value(), INDEX_BASE_VALUE);
+        // The EUR listing is weighted at the FX rate
+        assert!((index.weight("ADIDAS").unwrap() - 180_000_000.0 * 1.08).abs() < 1e-3);
+    }
+
+    #[test]
+    fn weighting_decides_how_far_a_move_carries() {
+        // NIKE: 1,500,000,000 shares at 1500, PUMA: 150,000,000 shares at 3300
+        let mut cap = index(IndexWeighting::MarketCap);
+        let value = cap.update("NIKE", Price::from_f64(1650.0)).unwrap();
+        assert!((value - INDEX_BASE_VALUE * (1.5e9 * 1650.0 + 1.5e8 * 3300.0) / (1.5e9 * 1500.0 + 1.5e8 * 3300.0)).abs() < 1e-9);
+
+        let mut price = index(IndexWeighting::Price);
+        assert_eq!(price.update("NIKE", Price::from_f64(1650.0)), Some(INDEX_BASE_VALUE * 4950.0 / 4800.0));
+        assert_eq!(price.update("SPRT", Price::from_f64(1.0)), None);
+    }
+
+    #[test]
+    fn change_is_measured_from_the_previous_close() {
+        let mut index = index(IndexWeighting::Price);
+        index.update("PUMA", Price::from_f64(3780.0));
+        assert!((index.change() - 0.1).abs() < 1e-12);
+        index.close_session();
+        assert_eq!(index.change(), 0.0);
+
+        // A 2-for-1 split reprices NIKE without moving the level
+        let level = index.value();
+        index.adjust("NIKE", Price::from_f64(750.0), 1.0);
+        assert!((index.value() - level).abs() < 1e-9);
+        let value = index.update("NIKE", Price::from_f64(825.0)).unwrap();
+        assert!(value > level);
+    }
+}
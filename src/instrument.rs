@@ -0,0 +1,310 @@
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::fs;
+
+use crate::etf::BasketComponent;
+use crate::index::IndexConfig;
+use crate::margin::{DEFAULT_INITIAL_MARGIN, DEFAULT_MAINTENANCE_MARGIN};
+use crate::options::DEFAULT_VOLATILITY;
+use crate::price::{Price, Quantity};
+use crate::stock_object::Stock;
+
+// Relative to the working directory; `--instruments PATH` or the RTS_INSTRUMENTS variable points elsewhere
+pub const INSTRUMENTS_FILE: &str = "instruments.toml";
+pub const INSTRUMENTS_ENV: &str = "RTS_INSTRUMENTS";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListingStatus {
+    Listed,
+    Suspended,
+    Delisted,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Instrument {
+    pub symbol: String,
+    pub isin: String,
+    pub company_name: String,
+    pub sector: String,
+    pub currency: String,
+    pub tick_size: Price,
+    pub lot_size: Quantity,
+    pub shares_outstanding: u64,
+    pub listing_status: ListingStatus,
+    // Price the simulation starts trading from
+    pub reference_price: Price,
+    // Shares lenders make available for short selling, and the annual borrow fee rate
+    #[serde(default)]
+    pub borrow_pool: u64,
+    #[serde(default)]
+    pub borrow_rate: f64,
+    // Fractions of position value a margin account must hold to open and to keep a position
+    #[serde(default = "default_initial_margin")]
+    pub initial_margin: f64,
+    #[serde(default = "default_maintenance_margin")]
+    pub maintenance_margin: f64,
+    // Whether calls and puts are listed on the stock, and its at-the-money implied volatility
+    #[serde(default)]
+    pub options_listed: bool,
+    #[serde(default = "default_volatility")]
+    pub volatility: f64,
+    // Whether single-stock futures are listed on it
+    #[serde(default)]
+    pub futures_listed: bool,
+    // ETFs only: ETF shares per creation unit and the stocks that back one unit
+    #[serde(default)]
+    pub creation_unit: u64,
+    #[serde(default)]
+    pub basket: Vec<BasketComponent>,
+}
+
+fn default_initial_margin() -> f64 {
+    DEFAULT_INITIAL_MARGIN
+}
+
+fn default_maintenance_margin() -> f64 {
+    DEFAULT_MAINTENANCE_MARGIN
+}
+
+fn default_volatility() -> f64 {
+    DEFAULT_VOLATILITY
+}
+
+impl Instrument {
+    pub fn is_tradable(&self) -> bool {
+        self.listing_status == ListingStatus::Listed
+    }
+
+    pub fn is_etf(&self) -> bool {
+        !self.basket.is_empty()
+    }
+
+    pub fn to_stock(&self) -> Stock {
+        Stock {
+            stock_name: self.symbol.clone(),
+            current_price: self.reference_price,
+            closing_price: None,
+            tick_size: self.tick_size,
+            lot_size: self.lot_size,
+            currency: self.currency.clone(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct InstrumentFile {
+    instrument: Vec<Instrument>,
+    #[serde(default)]
+    index: IndexConfig,
+}
+
+#[derive(Debug, Clone)]
+pub struct InstrumentRegistry {
+    // Kept in file order so listings print the same way every run
+    instruments: Vec<Instrument>,
+    by_symbol: HashMap<String, usize>,
+    // How the market index over these instruments is built
+    index: IndexConfig,
+}
+
+// Symbols are compared trimmed and upper-cased
+pub fn normalize_symbol(symbol: &str) -> String {
+    symbol.trim().to_uppercase()
+}
+
+impl InstrumentRegistry {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let contents = fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path, e))?;
+        InstrumentRegistry::from_toml(&contents)
+    }
+
+    pub fn from_toml(contents: &str) -> Result<Self, String> {
+        let file: InstrumentFile = toml::from_str(contents).map_err(|e| format!("invalid instrument file: {}", e))?;
+        let mut registry = InstrumentRegistry::new(file.instrument)?;
+        registry.index = file.index;
+        Ok(registry)
+    }
+
+    pub fn new(instruments: Vec<Instrument>) -> Result<Self, String> {
+        let mut registry = InstrumentRegistry { instruments: Vec::new(), by_symbol: HashMap::new(), index: IndexConfig::default() };
+        for mut instrument in instruments {
+            instrument.symbol = normalize_symbol(&instrument.symbol);
+            validate(&instrument)?;
+            if registry.by_symbol.contains_key(&instrument.symbol) {
+                return Err(format!("duplicate symbol {}", instrument.symbol));
+            }
+            if registry.instruments.iter().any(|i| i.isin == instrument.isin) {
+                return Err(format!("duplicate ISIN {}", instrument.isin));
+            }
+            registry.by_symbol.insert(instrument.symbol.clone(), registry.instruments.len());
+            registry.instruments.push(instrument);
+        }
+        // Baskets can only hold stocks from the same file
+        for etf in registry.instruments.iter().filter(|i| i.is_etf()) {
+            for component in &etf.basket {
+                match registry.get(&component.symbol) {
+                    Some(stock) if !stock.is_etf() && component.shares > 0 => {}
+                    _ => return Err(format!("{}: basket component {} must be a listed stock with a positive share count", etf.symbol,
+                    component.symbol)),
+                }
+            }
+        }
+        Ok(registry)
+    }
+
+    // The instruments a single broker shard trades
+    pub fn subset(&self, symbols: &[String]) -> Result<Self, String> {
+        let mut subset = InstrumentRegistry::new(self.instruments.iter().filter(|i| symbols.contains(&i.symbol)).cloned().collect())?;
+        subset.index = self.index.clone();
+        Ok(subset)
+    }
+
+    pub fn index_config(&self) -> &IndexConfig {
+        &self.index
+    }
+
+    pub fn set_index_config(&mut self, index: IndexConfig) {
+        self.index = index;
+    }
+
+    pub fn get(&self, symbol: &str) -> Option<&Instrument> {
+        self.by_symbol.get(&normalize_symbol(symbol)).map(|&index| &self.instruments[index])
+    }
+
+    // Symbols cannot be changed through here; use rename
+    pub fn get_mut(&mut self, symbol: &str) -> Option<&mut Instrument> {
+        let index = *self.by_symbol.get(&normalize_symbol(symbol))?;
+        Some(&mut self.instruments[index])
+    }
+
+    // The instrument keeps its ISIN and everything else under the new symbol
+    pub fn rename(&mut self, old: &str, new: &str) -> Result<(), String> {
+        let new = normalize_symbol(new);
+        if self.by_symbol.contains_key(&new) {
+            return Err(format!("symbol {} is already taken", new));
+        }
+        let index = self.by_symbol.remove(&normalize_symbol(old)).ok_or_else(|| format!("unknown symbol {}", old.trim()))?;
+        self.instruments[index].symbol = new.clone();
+        for component in self.instruments.iter_mut().flat_map(|i| i.basket.iter_mut()).filter(|c| c.symbol == normalize_symbol(old)) {
+            component.symbol = new.clone();
+        }
+        self.by_symbol.insert(new, index);
+        Ok(())
+    }
+
+    pub fn get_by_isin(&self, isin: &str) -> Option<&Instrument> {
+        self.instruments.iter().find(|i| i.isin == isin)
+    }
+
+    // The instrument an order refers to, if it exists and is open for trading
+    pub fn validate_symbol(&self, symbol: &str) -> Result<&Instrument, String> {
+        match self.get(symbol) {
+            None => Err(format!("unknown symbol {}", symbol.trim())),
+            Some(instrument) if !instrument.is_tradable() => {
+                Err(format!("{} is {:?}", instrument.symbol, instrument.listing_status))
+            }
+            Some(instrument) => Ok(instrument),
+        }
+    }
+
+    pub fn instruments(&self) -> &[Instrument] {
+        &self.instruments
+    }
+
+    pub fn tradable(&self) -> Vec<&Instrument> {
+        self.instruments.iter().filter(|i| i.is_tradable()).collect()
+    }
+
+    // Every instrument that has not been delisted gets a Stock for price tracking
+    pub fn stocks(&self) -> Vec<Stock> {
+        self.instruments.iter()
+            .filter(|i| i.listing_status != ListingStatus::Delisted)
+            .map(|i| i.to_stock())
+            .collect()
+    }
+}
+
+fn validate(instrument: &Instrument) -> Result<(), String> {
+    let isin = instrument.isin.as_bytes();
+    let isin_like = isin.len() == 12
+        && isin[..2].iter().all(|c| c.is_ascii_uppercase())
+        && isin[2..11].iter().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+        && isin[11].is_ascii_digit();
+    if !isin_like {
+        return Err(format!("{}: {} is not an ISIN-like id", instrument.symbol, instrument.isin));
+    }
+    if instrument.symbol.is_empty() {
+        return Err("instrument with an empty symbol".to_string());
+    }
+    if instrument.tick_size <= Price::ZERO || instrument.lot_size.is_zero() {
+        return Err(format!("{}: tick size and lot size must be positive", instrument.symbol));
+    }
+    if !instrument.reference_price.is_multiple_of(instrument.tick_size) {
+        return Err(format!("{}: reference price is off the tick grid", instrument.symbol));
+    }
+    if instrument.borrow_rate < 0.0 {
+        return Err(format!("{}: borrow rate cannot be negative", instrument.symbol));
+    }
+    if !(instrument.maintenance_margin > 0.0 && instrument.maintenance_margin <= instrument.initial_margin && instrument.initial_margin <= 1.0) {
+        return Err(format!("{}: margins must satisfy 0 < maintenance <= initial <= 1", instrument.symbol));
+    }
+    if instrument.volatility <= 0.0 {
+        return Err(format!("{}: volatility must be positive", instrument.symbol));
+    }
+    if instrument.is_etf() && instrument.creation_unit == 0 {
+        return Err(format!("{}: an ETF needs a creation unit", instrument.symbol));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn toml(symbol: &str, isin: &str, reference_price: &str) -> String {
+        format!("[[instrument]]\nsymbol = \"{}\"\nisin = \"{}\"\ncompany_name = \"Test\"\nsector = \"Test\"\ncurrency = \"USD\"\ntick_size = \"0.05\"\n\
+            lot_size = 10\nshares_outstanding = 1000\nlisting_status = \"Listed\"\nreference_price = \"{}\"\n", symbol, isin, reference_price)
+    }
+
+    #[test]
+    fn symbols_are_looked_up_normalized_and_validated() {
+        let registry = InstrumentRegistry::load(INSTRUMENTS_FILE).unwrap();
+        assert_eq!(registry.get(" nike ").unwrap().symbol, "NIKE");
+        assert_eq!(registry.get_by_isin("US0SIMNIKE01").unwrap().symbol, "NIKE");
+        assert!(registry.validate_symbol("nike").is_ok());
+        assert_eq!(registry.validate_symbol("REEBOK").unwrap_err(), "unknown symbol REEBOK");
+        assert_eq!(registry.validate_symbol("ASICS").unwrap_err(), "ASICS is Suspended");
+        assert!(registry.tradable().iter().all(|i| i.symbol != "ASICS"));
+    }
+
+    #[test]
+    fn invalid_files_are_rejected() {
+        assert!(InstrumentRegistry::from_toml(&toml("test", "US0SIMTEST01", "10.00")).is_ok());
+        let duplicate_symbol = toml("TEST", "US0SIMTEST01", "10.00") + &toml("test", "US0SIMTEST02", "10.00");
+        assert_eq!(InstrumentRegistry::from_toml(&duplicate_symbol).unwrap_err(), "duplicate symbol TEST");
+        let duplicate_isin = toml("TEST", "US0SIMTEST01", "10.00") + &toml("OTHER", "US0SIMTEST01", "10.00");
+        assert_eq!(InstrumentRegistry::from_toml(&duplicate_isin).unwrap_err(), "duplicate ISIN US0SIMTEST01");
+        assert!(InstrumentRegistry::from_toml(&toml("TEST", "not-an-isin", "10.00")).unwrap_err().contains("not an ISIN-like id"));
+        assert!(InstrumentRegistry::from_toml(&toml("TEST", "US0SIMTEST01", "10.03")).unwrap_err().contains("off the tick grid"));
+    }
+
+    #[test]
+    fn subset_keeps_baskets_whole() {
+        let registry = InstrumentRegistry::load(INSTRUMENTS_FILE).unwrap();
+        let subset = registry.subset(&["NIKE".to_string(), "PUMA".to_string()]).unwrap();
+        assert_eq!(subset.instruments().len(), 2);
+        assert_eq!(subset.index_config().name, registry.index_config().name);
+        // An ETF without the stocks in its basket is not a valid registry
+        assert!(registry.subset(&["SPRT".to_string()]).is_err());
+    }
+
+    #[test]
+    fn rename_moves_the_symbol_and_basket_components() {
+        let mut registry = InstrumentRegistry::load(INSTRUMENTS_FILE).unwrap();
+        assert!(registry.rename("NIKE", "puma").is_err());
+        registry.rename("nike", "nke").unwrap();
+        assert!(registry.get("NIKE").is_none());
+        assert_eq!(registry.get("NKE").unwrap().isin, "US0SIMNIKE01");
+        assert!(registry.get("SPRT").unwrap().basket.iter().any(|c| c.symbol == "NKE"));
+    }
+}
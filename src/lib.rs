@@ -1,4 +1,34 @@
+pub mod account;
+pub mod analytics;
+pub mod async_rmq;
+pub mod authorized_participant;
+pub mod bars;
+pub mod borrow;
 pub mod broker;
+pub mod calendar;
+pub mod circuit_breaker;
+pub mod corporate_actions;
+pub mod depth;
+pub mod etf;
+pub mod fees;
+pub mod futures;
+pub mod fx;
+pub mod index;
+pub mod instrument;
+pub mod margin;
+pub mod market;
+pub mod market_data;
+pub mod market_maker;
+pub mod order;
+pub mod order_book;
+pub mod options;
+pub mod price;
+pub mod router;
+pub mod session;
+pub mod settlement;
+pub mod shard;
 pub mod stock_object;
+pub mod tape;
 pub mod trader;
+pub mod venue;
 pub mod rmq;
@@ -1,52 +1,318 @@
 
-use std::sync::{atomic::{AtomicBool, AtomicUsize}, Arc, Mutex, RwLock};
-use std::sync::mpsc::channel;
-use rts_stockv3::stock_object::{Stock, MarketFactors};
-use rts_stockv3::trader::start_traders;
+use std::sync::{atomic::{AtomicBool, AtomicUsize, Ordering}, Arc, RwLock};
+use std::sync::mpsc::{channel, Receiver};
+use rts_stockv3::stock_object::MarketFactors;
+use rts_stockv3::trader::{run_traders, start_traders, Population, TradingDay, ASYNC_ORDERS_PER_TRADER, ASYNC_TRADERS, ASYNC_WORKER_THREADS, NUM_TRADERS};
+use rts_stockv3::async_rmq;
 use rts_stockv3::broker::Broker;
+use rts_stockv3::market_data::{compare_views, MarketDataSubscriber};
+use rts_stockv3::calendar::{TradingCalendar, TRADING_DAYS};
+use rts_stockv3::corporate_actions::{CorporateActions, SCENARIO_ENV, SCENARIO_FILE};
+use rts_stockv3::instrument::{InstrumentRegistry, INSTRUMENTS_ENV, INSTRUMENTS_FILE};
+use rts_stockv3::market_maker::{run_market_makers, start_market_maker, MarketMaker};
+use rts_stockv3::authorized_participant::{authorized_participants, run_authorized_participants, start_authorized_participants};
+use rts_stockv3::bars::BarInterval;
+use rts_stockv3::tape::{TradeTape, TAPE_CSV_FILE};
+use rts_stockv3::shard::{ShardMap, SharedMarket};
+use rts_stockv3::stock_object::Stock;
+use std::str::FromStr;
 use std::thread;
+use std::time::Duration;
 
 fn main() {
-    // Initialize stocks
-    let stocks = vec![
-        Stock::new("NIKE", 1500.0),
-        Stock::new("ADIDAS", 2500.0),
-        Stock::new("PUMA", 3300.0),
-        Stock::new("YONEX", 3000.0),
-        Stock::new("LINING", 4500.0),
-    ];
-    
-    let stocks = Arc::new(RwLock::new(stocks));
-    
+    // Load the instrument master and initialize stocks from it
+    let instruments_file = option_value(std::env::args(), "--instruments")
+        .or_else(|| std::env::var(INSTRUMENTS_ENV).ok())
+        .unwrap_or_else(|| INSTRUMENTS_FILE.to_string());
+    let registry = match InstrumentRegistry::load(&instruments_file) {
+        Ok(registry) => Arc::new(registry),
+        Err(e) => {
+            eprintln!("Failed to load instruments: {}", e);
+            return;
+        }
+    };
+    for instrument in registry.instruments() {
+        println!("{} ({}) {} [{}] {} tick ${} lot {} - {:?}", instrument.symbol, instrument.isin, instrument.company_name,
+        instrument.sector, instrument.currency, instrument.tick_size, instrument.lot_size, instrument.listing_status);
+    }
+
+    // Splits, dividends and symbol changes scheduled for the run
+    let scenario_file = option_value(std::env::args(), "--scenario")
+        .or_else(|| std::env::var(SCENARIO_ENV).ok())
+        .unwrap_or_else(|| SCENARIO_FILE.to_string());
+    let corporate_actions = match CorporateActions::load(&scenario_file) {
+        Ok(corporate_actions) => corporate_actions,
+        Err(e) => {
+            eprintln!("Failed to load scenario: {}", e);
+            return;
+        }
+    };
+    for action in corporate_actions.scheduled() {
+        println!("Scheduled {} {:?}", action.effective_date(), action);
+    }
+
     // Initialize market factors
     let market_factors = Arc::new(RwLock::new(MarketFactors::new(6.0, 2.5))); // Example values for unemployment rate and GDP growth
-    
+
     // Initialize order counter
     let order_count = Arc::new(AtomicUsize::new(0));
-    
+
     // Initialize stop signal
     let stop_signal = Arc::new(AtomicBool::new(false));
-    
+
     // Create channel for market factors updates
     let (tx, rx) = channel();
 
-    println!("\nMARKET OPENS.....");
-
-    // Start RabbitMQ processing thread
-    let _broker_stocks = Arc::clone(&stocks);
-    let broker = Broker::new(Arc::new(Mutex::new((*stocks.read().unwrap()).clone())), 
-    Arc::clone(&order_count), Arc::clone(&stop_signal), rx);
-    let broker_handle = thread::spawn(move || {
-        broker.process_orders();
-    });
-    
-    // Start traders
-    start_traders(Arc::clone(&stocks), Arc::clone(&market_factors), 
-    Arc::clone(&order_count), Arc::clone(&stop_signal), tx);
-    
-    // Wait for broker to finish processing
-    broker_handle.join().unwrap();
-
-    println!("MARKET CLOSED...");
+    // With `--async` traders are tasks on a few worker threads instead of a thread each,
+    // and the brokers consume over one long-lived connection; `--traders N` sets how many trade
+    // and `--accounts cash|margin|mixed` what kind of account they open
+    let async_mode = std::env::args().any(|arg| arg == "--async");
+    let mut population = if async_mode {
+        Population::new(option_value(std::env::args(), "--traders").unwrap_or(ASYNC_TRADERS), ASYNC_ORDERS_PER_TRADER)
+    } else {
+        Population { traders: option_value(std::env::args(), "--traders").unwrap_or(NUM_TRADERS), ..Population::default() }
+    };
+    population.accounts = option_value(std::env::args(), "--accounts").unwrap_or_default();
+    let runtime = if async_mode {
+        let runtime = match tokio::runtime::Builder::new_multi_thread().worker_threads(ASYNC_WORKER_THREADS).enable_time().build() {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                eprintln!("Failed to start the async runtime: {}", e);
+                return;
+            }
+        };
+        let connection = match runtime.block_on(async_rmq::connect()) {
+            Ok(connection) => Arc::new(connection),
+            Err(e) => {
+                eprintln!("Failed to connect to RabbitMQ: {}", e);
+                return;
+            }
+        };
+        println!("ASYNC MODE: {} traders on {} worker threads", population.traders, ASYNC_WORKER_THREADS);
+        Some((runtime, connection))
+    } else {
+        None
+    };
+
+    // The brokers live across sessions so GTC orders and accounts carry over.
+    // They own the market state; traders only see it through the market data exchange.
+    // With `--shards N` each of N brokers trades its own group of symbols, and orders reach
+    // it straight from the order exchange. They share one set of accounts, one session clock
+    // and one index.
+    let calendar = TradingCalendar::default_calendar();
+    let shard_map = ShardMap::new(&registry, option_value(std::env::args(), "--shards").unwrap_or(1));
+    let market = SharedMarket::new(&registry, shard_map.len(), population, Arc::clone(&order_count), Arc::clone(&stop_signal));
+    let mut market_rx = Some(rx);
+    let mut brokers = Vec::new();
+    for (id, shard) in shard_map.shards().iter().enumerate() {
+        let symbols = shard_map.symbols(id);
+        let shard_registry = match registry.subset(symbols) {
+            Ok(shard_registry) => Arc::new(shard_registry),
+            Err(e) => {
+                eprintln!("Failed to shard instruments: {}", e);
+                return;
+            }
+        };
+        // Nothing reads market factor updates, so only one broker takes the channel
+        let rx: Receiver<MarketFactors> = market_rx.take().unwrap_or_else(|| channel().1);
+        brokers.push(Broker::for_shard(shard.clone(), shard_registry, calendar.clone(), corporate_actions.for_symbols(symbols), market.clone(), rx));
+        if shard.is_sharded() {
+            println!("SHARD {}: {} on queue {}", shard.id + 1, symbols.join(", "), shard.queue);
+        }
+    }
+    // In the async mode market data goes out over the shared connection too
+    if let Some((runtime, connection)) = &runtime {
+        for broker in brokers.iter_mut() {
+            if let Err(e) = runtime.block_on(broker.publish_market_data_on(connection)) {
+                eprintln!("Failed to open a market data channel: {}", e);
+                return;
+            }
+        }
+    }
+    let market_data = match MarketDataSubscriber::start(registry.stocks()) {
+        Ok(market_data) => market_data,
+        Err(e) => {
+            eprintln!("Failed to start market data: {}", e);
+            return;
+        }
+    };
+
+    for date in calendar.trading_days(TRADING_DAYS) {
+        println!("\nMARKET OPENS {}.....", date);
+        order_count.store(0, Ordering::SeqCst);
+        stop_signal.store(false, Ordering::SeqCst);
+
+        // Start RabbitMQ processing threads
+        for broker in brokers.iter_mut() {
+            broker.start_session(date);
+        }
+        // Traders start from the market as adjusted for the day's corporate actions
+        let registry = match market_registry(&brokers) {
+            Ok(registry) => registry,
+            Err(e) => {
+                eprintln!("Failed to combine the shards' instruments: {}", e);
+                return;
+            }
+        };
+        wait_for_market_data(&market_data, &brokers);
+        // The market maker quotes the option chains as listed for the day, one per broker listing any
+        let mut market_makers = Vec::new();
+        for options in brokers.iter().map(|broker| broker.options()).filter(|options| !options.contracts().is_empty()) {
+            for contract in options.contracts() {
+                println!("Listed {} {} {} strike ${:.2} expiring {} x{}", contract.symbol, contract.underlying, contract.kind.as_str(), contract.strike,
+                contract.expiry, contract.multiplier);
+            }
+            market_makers.push(MarketMaker::new(population.market_maker_id(), market_data.clone(), options.contracts().to_vec(), options.surface.clone(),
+            options.tick_size, date, Arc::clone(&stop_signal)));
+        }
+        let mut futures = Vec::new();
+        for broker in &brokers {
+            for contract in broker.futures().contracts() {
+                println!("Listed {} {} future x{} expiring {}, last settlement ${:.2}", contract.symbol, contract.underlying, contract.multiplier,
+                contract.expiry, broker.futures().settlement_price(&contract.symbol).unwrap_or_default());
+                futures.push(contract.clone());
+            }
+        }
+        let venues = brokers[0].venues().names();
+        // Authorized participants arbitrage each ETF against its basket
+        let etfs = brokers.iter().flat_map(|broker| broker.etfs().etfs().to_vec()).collect();
+        let participants = authorized_participants(market_data.clone(), etfs, population.first_participant_id(), Arc::clone(&stop_signal));
+        let day = TradingDay { date, registry, futures, venues, population };
+        if let Some((runtime, connection)) = &runtime {
+            // Brokers, traders, the market makers and the participants are all tasks
+            brokers = runtime.block_on(async {
+                let quoting = {
+                    let connection = Arc::clone(connection);
+                    tokio::spawn(async move { run_market_makers(&connection, market_makers).await })
+                };
+                let arbitraging = {
+                    let connection = Arc::clone(connection);
+                    tokio::spawn(async move { run_authorized_participants(&connection, participants).await })
+                };
+                let broker_tasks: Vec<_> = brokers.into_iter()
+                    .map(|mut broker| {
+                        let connection = Arc::clone(connection);
+                        tokio::spawn(async move {
+                            broker.process_orders_async(&connection).await;
+                            broker
+                        })
+                    })
+                    .collect();
+                run_traders(connection, market_data.clone(), day, Arc::clone(&market_factors), Arc::clone(&order_count),
+                Arc::clone(&stop_signal), tx.clone()).await;
+
+                let mut brokers = Vec::new();
+                for task in broker_tasks {
+                    brokers.push(task.await.unwrap());
+                }
+                quoting.await.unwrap();
+                arbitraging.await.unwrap();
+                brokers
+            });
+        } else {
+            let market_makers: Vec<_> = market_makers.into_iter().map(start_market_maker).collect();
+            let participants = start_authorized_participants(participants);
+            let broker_handles: Vec<_> = brokers.into_iter()
+                .map(|mut broker| thread::spawn(move || {
+                    broker.process_orders();
+                    broker
+                }))
+                .collect();
+
+            // Start traders
+            start_traders(market_data.clone(), day, Arc::clone(&market_factors),
+            Arc::clone(&order_count), Arc::clone(&stop_signal), tx.clone());
+
+            // Wait for the brokers to finish processing
+            brokers = broker_handles.into_iter().map(|handle| handle.join().unwrap()).collect();
+            for market_maker in market_makers {
+                market_maker.join().unwrap();
+            }
+            for participant in participants {
+                participant.join().unwrap();
+            }
+        }
+
+        // Both views of the market must agree once the day is over
+        wait_for_market_data(&market_data, &brokers);
+        let view = market_data.snapshot();
+        let stocks: Vec<Stock> = brokers.iter().flat_map(|broker| broker.market_state().snapshot()).collect();
+        let mismatches = compare_views(&stocks, &view);
+        if mismatches.is_empty() {
+            println!("CONSISTENCY CHECK: trader and broker views agree after {} market data messages", view.sequences.values().sum::<u64>());
+        } else {
+            for mismatch in mismatches {
+                eprintln!("CONSISTENCY CHECK FAILED: {}", mismatch);
+            }
+        }
+
+        println!("MARKET CLOSED {}...", date);
+    }
+
+    market_data.stop();
+
+    println!("\nDAILY CLOSES:");
+    for broker in &brokers {
+        for symbol in broker.market_state().symbols() {
+            let closes: Vec<String> = broker.bars(symbol, BarInterval::Daily).iter()
+                .map(|bar| format!("{} ${:.2}", bar.start.date(), bar.close))
+                .collect();
+            println!("{}: {}", symbol, closes.join(", "));
+        }
+    }
+    if let Some(broker) = brokers.first() {
+        let index = broker.index();
+        println!("{}: {:.2}", index.name, index.value);
+    }
+
+    println!("\nANALYTICS:");
+    for broker in &brokers {
+        for symbol in broker.market_state().symbols() {
+            println!("{}", broker.analytics().report(symbol));
+        }
+    }
+
+    // Time and sales for the whole run
+    let tapes: Vec<&TradeTape> = brokers.iter().map(|broker| broker.tape()).collect();
+    let tape = TradeTape::merge(&tapes);
+    for symbol in brokers.iter().flat_map(|broker| broker.market_state().symbols()) {
+        println!("TAPE {}: {} trades", symbol, tape.for_symbol(symbol).len());
+    }
+    match tape.export_csv(TAPE_CSV_FILE) {
+        Ok(()) => println!("Trade tape of {} trades written to {}", tape.len(), TAPE_CSV_FILE),
+        Err(e) => eprintln!("Failed to write trade tape: {}", e),
+    }
 }
 
+// The value after `name` on the command line, as in `--shards 4`
+fn option_value<T: FromStr>(mut args: impl Iterator<Item = String>, name: &str) -> Option<T> {
+    while let Some(arg) = args.next() {
+        if arg == name {
+            return args.next().and_then(|value| value.parse().ok());
+        }
+    }
+    None
+}
+
+// Each broker numbers its own market data, so the feed has to catch up with every one of them
+fn wait_for_market_data(market_data: &MarketDataSubscriber, brokers: &[Broker]) {
+    for broker in brokers {
+        let (publisher, sequence) = broker.market_data_sequence();
+        if !market_data.wait_for_sequence(publisher, sequence, Duration::from_secs(5)) {
+            eprintln!("Market data is behind broker {} at sequence {}", publisher + 1, sequence);
+        }
+    }
+}
+
+// Every broker's instruments as one registry, for the traders
+fn market_registry(brokers: &[Broker]) -> Result<Arc<InstrumentRegistry>, String> {
+    if let [broker] = brokers {
+        return Ok(broker.registry());
+    }
+    let instruments = brokers.iter().flat_map(|broker| broker.registry().instruments().to_vec()).collect();
+    let mut registry = InstrumentRegistry::new(instruments)?;
+    if let Some(broker) = brokers.first() {
+        registry.set_index_config(broker.registry().index_config().clone());
+    }
+    Ok(Arc::new(registry))
+}
@@ -0,0 +1,267 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::account::{Account, AccountType};
+use crate::fx::FxRates;
+use crate::instrument::InstrumentRegistry;
+use crate::order::Side;
+use crate::price::{Amount, Price, Quantity};
+
+// Used when an instrument does not set its own margin rates
+pub const DEFAULT_INITIAL_MARGIN: f64 = 0.50;
+pub const DEFAULT_MAINTENANCE_MARGIN: f64 = 0.25;
+
+// Fractions of a position's market value the account must cover with equity
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MarginRequirement {
+    // To open or add to a position
+    pub initial: f64,
+    // To keep holding it
+    pub maintenance: f64,
+}
+
+impl Default for MarginRequirement {
+    fn default() -> Self {
+        MarginRequirement { initial: DEFAULT_INITIAL_MARGIN, maintenance: DEFAULT_MAINTENANCE_MARGIN }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MarginEvent {
+    // Equity fell below the maintenance requirement
+    Call { trader_id: usize, equity: Amount, requirement: Amount },
+    // The broker closed part of a position to cover a call
+    Liquidation { trader_id: usize, stock_name: String, side: Side, quantity: Quantity, price: Price },
+    // Equity is back above the maintenance requirement
+    Restored { trader_id: usize, equity: Amount, requirement: Amount },
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MarginDesk {
+    requirements: HashMap<String, MarginRequirement>,
+    lot_sizes: HashMap<String, Quantity>,
+    // Traders with a margin call outstanding
+    calls: HashSet<usize>,
+    events: Vec<MarginEvent>,
+}
+
+impl MarginDesk {
+    pub fn new(registry: &InstrumentRegistry) -> Self {
+        let requirements = registry.instruments().iter()
+            .map(|i| (i.symbol.clone(), MarginRequirement { initial: i.initial_margin, maintenance: i.maintenance_margin }))
+            .collect();
+        let lot_sizes = registry.instruments().iter()
+            .map(|i| (i.symbol.clone(), i.lot_size))
+            .collect();
+        MarginDesk { requirements, lot_sizes, calls: HashSet::new(), events: Vec::new() }
+    }
+
+    // Instruments listed after the desk was set up, such as futures
+    pub fn list(&mut self, symbol: &str, requirement: MarginRequirement) {
+        self.requirements.insert(symbol.to_string(), requirement);
+    }
+
+    pub fn requirement(&self, stock_name: &str) -> MarginRequirement {
+        self.requirements.get(stock_name).copied().unwrap_or_default()
+    }
+
+    // Long and short positions both need margin on their absolute market value;
+    // futures on their notional, which `prices` holds per contract.
+    // Requirements are in the base currency.
+    fn required(&self, account: &Account, prices: &HashMap<String, Price>, fx: &FxRates, rate: impl Fn(MarginRequirement) -> f64) -> Amount {
+        prices.iter()
+            .map(|(name, price)| {
+                let value = price.position_value(account.position(name) + account.futures_position(name)).abs();
+                fx.to_base(value, account.currency(name)).scale(rate(self.requirement(name)))
+            })
+            .sum()
+    }
+
+    pub fn initial_requirement(&self, account: &Account, prices: &HashMap<String, Price>, fx: &FxRates) -> Amount {
+        self.required(account, prices, fx, |r| r.initial)
+    }
+
+    pub fn maintenance_requirement(&self, account: &Account, prices: &HashMap<String, Price>, fx: &FxRates) -> Amount {
+        self.required(account, prices, fx, |r| r.maintenance)
+    }
+
+    // Notional of `stock_name`, in the base currency, the account can still buy or
+    // short. Cash accounts can only spend their cash, which buys foreign currency as
+    // needed (see `Account::fund_in`); margin accounts can lever their excess equity.
+    pub fn buying_power(&self, account: &Account, prices: &HashMap<String, Price>, fx: &FxRates, stock_name: &str) -> Amount {
+        match account.account_type {
+            AccountType::Cash => account.cash_value(fx).max(Amount::ZERO),
+            AccountType::Margin => {
+                let excess = account.equity(prices, fx) - self.initial_requirement(account, prices, fx);
+                excess.scale(1.0 / self.requirement(stock_name).initial).max(Amount::ZERO)
+            }
+        }
+    }
+
+    // Compare a margin account against its maintenance requirement at `prices`.
+    // Only changes are reported: a new call, or a call that has been met.
+    pub fn check(&mut self, account: &Account, prices: &HashMap<String, Price>, fx: &FxRates) -> Option<MarginEvent> {
+        if account.account_type != AccountType::Margin {
+            return None;
+        }
+        let equity = account.equity(prices, fx);
+        let requirement = self.maintenance_requirement(account, prices, fx);
+        let trader_id = account.trader_id;
+        let event = if equity < requirement && self.calls.insert(trader_id) {
+            MarginEvent::Call { trader_id, equity, requirement }
+        } else if equity >= requirement && self.calls.remove(&trader_id) {
+            MarginEvent::Restored { trader_id, equity, requirement }
+        } else {
+            return None;
+        };
+        self.events.push(event.clone());
+        Some(event)
+    }
+
+    pub fn in_call(&self, trader_id: usize) -> bool {
+        self.calls.contains(&trader_id)
+    }
+
+    // Orders that would bring the account back to its maintenance requirement.
+    // Closing shares or contracts leaves equity unchanged but frees their margin, so
+    // the positions tying up the most margin are reduced first, in whole lots.
+    pub fn liquidation_orders(&self, account: &Account, prices: &HashMap<String, Price>, fx: &FxRates) -> Vec<(String, Side, Quantity)> {
+        let mut deficit = self.maintenance_requirement(account, prices, fx) - account.equity(prices, fx);
+        let mut positions: Vec<(String, i64, Amount)> = prices.iter()
+            .map(|(name, price)| {
+                let position = account.position(name) + account.futures_position(name);
+                let per_share = fx.to_base(price.notional(Quantity::new(1)), account.currency(name)).scale(self.requirement(name).maintenance);
+                (name.clone(), position, per_share)
+            })
+            .filter(|(_, position, per_share)| *position != 0 && *per_share > Amount::ZERO)
+            .collect();
+        positions.sort_by_key(|(name, position, per_share)| (std::cmp::Reverse(*per_share * position.abs()), name.clone()));
+
+        let mut orders = Vec::new();
+        for (name, position, per_share) in positions {
+            if deficit <= Amount::ZERO {
+                break;
+            }
+            let lot = self.lot_sizes.get(&name).map_or(1, |l| l.value().max(1));
+            let held = position.unsigned_abs() / lot * lot;
+            let wanted = (deficit.to_f64() / per_share.to_f64()).ceil() as u64;
+            let quantity = wanted.div_ceil(lot).saturating_mul(lot).min(held);
+            if quantity == 0 {
+                continue;
+            }
+            deficit -= per_share * quantity as i64;
+            let side = if position > 0 { Side::Sell } else { Side::Buy };
+            orders.push((name, side, Quantity::new(quantity)));
+        }
+        orders
+    }
+
+    pub fn rename(&mut self, old: &str, new: &str) {
+        if let Some(requirement) = self.requirements.remove(old) {
+            self.requirements.insert(new.to_string(), requirement);
+        }
+        if let Some(lot_size) = self.lot_sizes.remove(old) {
+            self.lot_sizes.insert(new.to_string(), lot_size);
+        }
+    }
+
+    pub fn record(&mut self, event: MarginEvent) {
+        self.events.push(event);
+    }
+
+    pub fn events(&self) -> &[MarginEvent] {
+        &self.events
+    }
+}
+
+// Shares of an order that open or add to a position. What the position covers is set
+// aside, less what the trader's orders resting on the same side already use of it;
+// cash accounts may not sell more than they hold.
+pub fn opening_shares(account_type: AccountType, side: Side, position: i64, resting: i64, quantity: i64) -> Result<i64, String> {
+    match (account_type, side) {
+        (AccountType::Cash, Side::Buy) => Ok(quantity),
+        (AccountType::Cash, Side::Sell) if quantity > (position - resting).max(0) => Err("short sales need a margin account".to_string()),
+        (AccountType::Cash, Side::Sell) => Ok(0),
+        (AccountType::Margin, Side::Buy) => Ok((quantity - (-position - resting).max(0)).max(0)),
+        (AccountType::Margin, Side::Sell) => Ok((quantity - (position - resting).max(0)).max(0)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two USD stocks in lots of 10 with the default margin rates
+    fn desk() -> (MarginDesk, HashMap<String, Price>, Account) {
+        let instrument = |symbol: &str, price: &str| format!("[[instrument]]\nsymbol = \"{}\"\nisin = \"US0SIM{}001\"\ncompany_name = \"Test\"\nsector = \"Test\"\n\
+            currency = \"USD\"\ntick_size = \"0.01\"\nlot_size = 10\nshares_outstanding = 1000000\nlisting_status = \"Listed\"\nreference_price = \"{}\"\n", symbol, symbol, price);
+        let registry = InstrumentRegistry::from_toml(&(instrument("AAA", "100.00") + &instrument("BBB", "50.00"))).unwrap();
+        let prices = HashMap::from([("AAA".to_string(), Price::from_f64(100.0)), ("BBB".to_string(), Price::from_f64(50.0))]);
+        let account = Account::new(0, &registry.stocks(), AccountType::Margin, &FxRates::default());
+        (MarginDesk::new(&registry), prices, account)
+    }
+
+    fn hold(account: &mut Account, cash: i64, positions: &[(&str, i64)]) {
+        account.cash = HashMap::from([("USD".to_string(), Amount::from_units(cash))]);
+        account.positions = positions.iter().map(|(name, position)| (name.to_string(), *position)).collect();
+    }
+
+    #[test]
+    fn margin_accounts_lever_their_excess_equity() {
+        let (desk, prices, mut account) = desk();
+        let fx = FxRates::default();
+        hold(&mut account, 10_000, &[]);
+        assert_eq!(desk.buying_power(&account, &prices, &fx, "AAA"), Amount::from_units(20_000));
+        account.account_type = AccountType::Cash;
+        assert_eq!(desk.buying_power(&account, &prices, &fx, "AAA"), Amount::from_units(10_000));
+
+        // 100,000 of AAA on 20,000 of equity is past the initial requirement
+        account.account_type = AccountType::Margin;
+        hold(&mut account, -80_000, &[("AAA", 1_000)]);
+        assert_eq!(desk.initial_requirement(&account, &prices, &fx), Amount::from_units(50_000));
+        assert_eq!(desk.buying_power(&account, &prices, &fx, "AAA"), Amount::ZERO);
+    }
+
+    #[test]
+    fn calls_are_reported_once_until_restored() {
+        let (mut desk, prices, mut account) = desk();
+        let fx = FxRates::default();
+        hold(&mut account, -80_000, &[("AAA", 1_000)]);
+        let call = MarginEvent::Call { trader_id: 0, equity: Amount::from_units(20_000), requirement: Amount::from_units(25_000) };
+        assert_eq!(desk.check(&account, &prices, &fx), Some(call));
+        assert_eq!(desk.check(&account, &prices, &fx), None);
+        assert!(desk.in_call(0));
+
+        hold(&mut account, -60_000, &[("AAA", 800)]);
+        let restored = MarginEvent::Restored { trader_id: 0, equity: Amount::from_units(20_000), requirement: Amount::from_units(20_000) };
+        assert_eq!(desk.check(&account, &prices, &fx), Some(restored));
+        assert!(!desk.in_call(0));
+        assert_eq!(desk.events().len(), 2);
+    }
+
+    #[test]
+    fn liquidation_closes_the_largest_requirement_first_in_whole_lots() {
+        let (desk, prices, mut account) = desk();
+        let fx = FxRates::default();
+        // Requirement 25,000 on 17,490 of equity: 301 shares of AAA would cover it
+        hold(&mut account, -82_510, &[("AAA", 1_000)]);
+        assert_eq!(desk.liquidation_orders(&account, &prices, &fx), vec![("AAA".to_string(), Side::Sell, Quantity::new(310))]);
+
+        // Short 1,000 BBB ties up 12,500 and long 100 AAA 2,500; a 17,000 deficit takes all of both
+        hold(&mut account, 38_000, &[("AAA", 100), ("BBB", -1_000)]);
+        assert_eq!(desk.liquidation_orders(&account, &prices, &fx), vec![
+            ("BBB".to_string(), Side::Buy, Quantity::new(1_000)),
+            ("AAA".to_string(), Side::Sell, Quantity::new(100)),
+        ]);
+    }
+
+    #[test]
+    fn resting_sells_use_up_the_long_position() {
+        // Long 100 with a sell of 100 already resting: another sell of 50 is all short
+        assert_eq!(opening_shares(AccountType::Margin, Side::Sell, 100, 0, 50), Ok(0));
+        assert_eq!(opening_shares(AccountType::Margin, Side::Sell, 100, 100, 50), Ok(50));
+        assert_eq!(opening_shares(AccountType::Margin, Side::Sell, 100, 80, 50), Ok(30));
+        assert_eq!(opening_shares(AccountType::Margin, Side::Buy, -100, 100, 50), Ok(50));
+        assert!(opening_shares(AccountType::Cash, Side::Sell, 100, 80, 50).is_err());
+        assert_eq!(opening_shares(AccountType::Cash, Side::Sell, 100, 50, 50), Ok(0));
+    }
+}
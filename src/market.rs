@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+
+use crate::price::Price;
+use crate::stock_object::Stock;
+
+// Shared market state indexed by symbol. The map itself never changes after
+// construction, so it needs no lock of its own: each stock has its own mutex
+// and orders for different symbols never wait on each other.
+#[derive(Debug)]
+pub struct MarketState {
+    stocks: HashMap<String, Mutex<Stock>>,
+    // Listing order, for output that reads the same on every run
+    symbols: Vec<String>,
+}
+
+impl MarketState {
+    pub fn new(stocks: Vec<Stock>) -> Self {
+        let symbols = stocks.iter().map(|s| s.stock_name.clone()).collect();
+        let stocks = stocks.into_iter()
+            .map(|s| (s.stock_name.clone(), Mutex::new(s)))
+            .collect();
+        MarketState { stocks, symbols }
+    }
+
+    pub fn symbols(&self) -> &[String] {
+        &self.symbols
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    // Locks only the requested symbol
+    pub fn lock(&self, symbol: &str) -> Option<MutexGuard<'_, Stock>> {
+        self.stocks.get(symbol).map(|stock| stock.lock().unwrap())
+    }
+
+    pub fn price(&self, symbol: &str) -> Option<Price> {
+        self.lock(symbol).map(|s| s.current_price)
+    }
+
+    // Copy of every stock in listing order, taking one symbol lock at a time
+    pub fn snapshot(&self) -> Vec<Stock> {
+        self.symbols.iter()
+            .filter_map(|symbol| self.lock(symbol).map(|s| s.clone()))
+            .collect()
+    }
+
+    pub fn prices(&self) -> HashMap<String, Price> {
+        self.symbols.iter()
+            .filter_map(|symbol| self.price(symbol).map(|p| (symbol.clone(), p)))
+            .collect()
+    }
+}
@@ -0,0 +1,492 @@
+use serde::{Serialize, Deserialize};
+use serde_json::to_string;
+use chrono::NaiveDateTime;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock, atomic::{AtomicBool, Ordering}};
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::{Duration, Instant};
+use lapin::Channel;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+use crate::analytics::{Analytics, SymbolStats};
+use crate::async_rmq;
+use crate::bars::{Bar, BarAggregator, BarInterval};
+use crate::depth::{DepthBook, DepthMessage, DepthStatus, SnapshotRequest};
+use crate::fx::FxRates;
+use crate::index::IndexLevel;
+use crate::order::Side;
+use crate::price::{Price, Quantity};
+use crate::rmq::{publish, subscribe, Publisher, MARKET_DATA_EXCHANGE, ORDER_EXCHANGE};
+use crate::session::MarketPhase;
+use crate::shard::routing_key;
+use crate::stock_object::Stock;
+use crate::venue::ConsolidatedQuote;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum MarketDataEvent {
+    // Last trade and the symbol's volume for the day so far
+    Trade { price: Price, quantity: Quantity, volume: Quantity, aggressor: Option<Side>, time: NaiveDateTime },
+    Quote { best_bid: Option<Price>, best_ask: Option<Price> },
+    Close { closing_price: Price },
+    Depth(DepthMessage),
+    // Published under the index name instead of a stock symbol
+    Index(IndexLevel),
+    // Published under FX_SYMBOL
+    Fx(FxRates),
+    // Prices restated for a split or dividend
+    Adjustment { current_price: Price, closing_price: Option<Price> },
+    // `new_shares` for every `old_shares`; bars and analytics are restated to match
+    Split { new_shares: u64, old_shares: u64 },
+    // Published under the old symbol; everything after uses the new one
+    SymbolChange { new_symbol: String },
+    // An ETF's indicative net asset value per share, whenever a basket stock trades
+    Nav { nav: Price },
+    // Best bid and offer across every venue
+    Bbo(ConsolidatedQuote),
+    // Published under SESSION_SYMBOL whenever the session moves to another phase
+    Phase(MarketPhase),
+}
+
+impl MarketDataEvent {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            MarketDataEvent::Trade { .. } => "trade",
+            MarketDataEvent::Quote { .. } => "quote",
+            MarketDataEvent::Close { .. } => "close",
+            MarketDataEvent::Depth(DepthMessage::Update { .. }) => "depth",
+            MarketDataEvent::Depth(DepthMessage::Snapshot { .. }) => "snapshot",
+            MarketDataEvent::Index(_) => "index",
+            MarketDataEvent::Fx(_) => "fx",
+            MarketDataEvent::Adjustment { .. } => "adjustment",
+            MarketDataEvent::Split { .. } => "split",
+            MarketDataEvent::SymbolChange { .. } => "symbol_change",
+            MarketDataEvent::Nav { .. } => "nav",
+            MarketDataEvent::Bbo(_) => "bbo",
+            MarketDataEvent::Phase(_) => "phase",
+        }
+    }
+}
+
+// What goes over the wire; each publisher numbers its messages in one sequence shared by every symbol
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MarketDataMessage {
+    pub sequence: u64,
+    #[serde(default)]
+    pub publisher: usize,
+    pub symbol: String,
+    pub event: MarketDataEvent,
+}
+
+impl MarketDataMessage {
+    // e.g. md.NIKE.trade, so subscribers can bind to md.NIKE.# or md.*.trade
+    pub fn routing_key(&self) -> String {
+        format!("md.{}.{}", self.symbol, self.event.kind())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub struct Quote {
+    pub best_bid: Option<Price>,
+    pub best_ask: Option<Price>,
+    pub volume: Quantity,
+}
+
+// Everything a trader is allowed to see about the market at one point in time
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MarketSnapshot {
+    // Last sequence applied from each publisher
+    #[serde(default)]
+    pub sequences: HashMap<usize, u64>,
+    pub stocks: Vec<Stock>,
+    pub quotes: HashMap<String, Quote>,
+    pub depth: HashMap<String, DepthBook>,
+    pub index: Option<IndexLevel>,
+    #[serde(default)]
+    pub fx: Option<FxRates>,
+    // Latest iNAV of each ETF
+    #[serde(default)]
+    pub navs: HashMap<String, Price>,
+    // Consolidated best bid and offer of each stock
+    #[serde(default)]
+    pub bbos: HashMap<String, ConsolidatedQuote>,
+    // Phase of the session, once one has been published
+    #[serde(default)]
+    pub phase: Option<MarketPhase>,
+}
+
+impl MarketSnapshot {
+    pub fn new(stocks: Vec<Stock>) -> Self {
+        MarketSnapshot { sequences: HashMap::new(), stocks, quotes: HashMap::new(), depth: HashMap::new(), index: None, fx: None, navs: HashMap::new(),
+            bbos: HashMap::new(), phase: None }
+    }
+
+    pub fn sequence(&self, publisher: usize) -> u64 {
+        self.sequences.get(&publisher).copied().unwrap_or_default()
+    }
+
+    pub fn get(&self, symbol: &str) -> Option<&Stock> {
+        self.stocks.iter().find(|s| s.stock_name == symbol)
+    }
+
+    pub fn quote(&self, symbol: &str) -> Quote {
+        self.quotes.get(symbol).copied().unwrap_or_default()
+    }
+
+    pub fn bbo(&self, symbol: &str) -> ConsolidatedQuote {
+        self.bbos.get(symbol).cloned().unwrap_or_default()
+    }
+
+    // Depth for a symbol, once a snapshot has been received for it
+    pub fn depth(&self, symbol: &str) -> Option<&DepthBook> {
+        self.depth.get(symbol).filter(|book| book.synced)
+    }
+
+    // Depth messages return how they applied to the symbol's book
+    pub fn apply(&mut self, message: &MarketDataMessage) -> Option<DepthStatus> {
+        let last = self.sequences.entry(message.publisher).or_default();
+        *last = (*last).max(message.sequence);
+        match &message.event {
+            MarketDataEvent::Index(level) => {
+                self.index = Some(level.clone());
+                return None;
+            }
+            MarketDataEvent::Fx(rates) => {
+                self.fx = Some(rates.clone());
+                return None;
+            }
+            MarketDataEvent::Nav { nav } => {
+                self.navs.insert(message.symbol.clone(), *nav);
+                return None;
+            }
+            MarketDataEvent::Bbo(consolidated) => {
+                self.bbos.insert(message.symbol.clone(), consolidated.clone());
+                return None;
+            }
+            MarketDataEvent::Phase(phase) => {
+                self.phase = Some(*phase);
+                return None;
+            }
+            // Option contracts are quoted too, though they have no stock
+            MarketDataEvent::Quote { best_bid, best_ask } => {
+                let quote = self.quotes.entry(message.symbol.clone()).or_default();
+                quote.best_bid = *best_bid;
+                quote.best_ask = *best_ask;
+                return None;
+            }
+            _ => {}
+        }
+        let stock = self.stocks.iter_mut().find(|s| s.stock_name == message.symbol)?;
+        let quote = self.quotes.entry(message.symbol.clone()).or_default();
+        match &message.event {
+            MarketDataEvent::Trade { price, volume, .. } => {
+                stock.current_price = *price;
+                quote.volume = *volume;
+            }
+            MarketDataEvent::Close { closing_price } => {
+                stock.closing_price = Some(*closing_price);
+            }
+            MarketDataEvent::Quote { .. } | MarketDataEvent::Index(_) | MarketDataEvent::Fx(_) | MarketDataEvent::Nav { .. } | MarketDataEvent::Bbo(_)
+            | MarketDataEvent::Phase(_) | MarketDataEvent::Split { .. } => {}
+            MarketDataEvent::Adjustment { current_price, closing_price } => {
+                stock.current_price = *current_price;
+                stock.closing_price = *closing_price;
+            }
+            MarketDataEvent::SymbolChange { new_symbol } => {
+                stock.stock_name = new_symbol.clone();
+                if let Some(quote) = self.quotes.remove(&message.symbol) {
+                    self.quotes.insert(new_symbol.clone(), quote);
+                }
+                if let Some(depth) = self.depth.remove(&message.symbol) {
+                    self.depth.insert(new_symbol.clone(), depth);
+                }
+                if let Some(bbo) = self.bbos.remove(&message.symbol) {
+                    self.bbos.insert(new_symbol.clone(), bbo);
+                }
+            }
+            MarketDataEvent::Depth(depth) => {
+                let status = self.depth.entry(message.symbol.clone()).or_default().apply(depth);
+                match status {
+                    DepthStatus::Gap { expected, received } => println!("\x1b[33mDEPTH GAP: {} expected update {}, got {}; requesting a snapshot\x1b[0m",
+                    message.symbol, expected, received),
+                    DepthStatus::Recovered => println!("\x1b[33mDEPTH: {} in sync\x1b[0m", message.symbol),
+                    DepthStatus::Applied | DepthStatus::Ignored => {}
+                }
+                return Some(status);
+            }
+        }
+        None
+    }
+}
+
+// Broker side of the feed: numbers every message and publishes it on the exchange.
+// The connection opens with the first message and stays open; after a failure the
+// next message reconnects. Each shard's broker publishes as its own `id`. In the async mode messages go instead, in order, to a task
+// publishing over the shared connection, so handling an order never waits on the network.
+pub struct MarketDataPublisher {
+    exchange: String,
+    id: usize,
+    sequence: u64,
+    publisher: Option<Publisher>,
+    forward: Option<UnboundedSender<(String, String)>>,
+}
+
+impl MarketDataPublisher {
+    pub fn new(exchange: &str, id: usize) -> Self {
+        MarketDataPublisher { exchange: exchange.to_string(), id, sequence: 0, publisher: None, forward: None }
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    // Every message from now on goes to a task publishing on `channel`; must be called
+    // inside the runtime. The task ends once this publisher is dropped and it has sent the rest.
+    pub fn publish_on(&mut self, channel: Channel) {
+        let mut messages = self.forward();
+        let exchange = self.exchange.clone();
+        tokio::spawn(async move {
+            while let Some((routing_key, body)) = messages.recv().await {
+                if let Err(e) = async_rmq::publish(&channel, body, &exchange, &routing_key).await {
+                    eprintln!("Failed to publish market data {}: {}", routing_key, e);
+                }
+            }
+        });
+    }
+
+    // Routing key and body of every message from now on, in sequence order
+    fn forward(&mut self) -> UnboundedReceiver<(String, String)> {
+        let (sender, receiver) = unbounded_channel();
+        self.forward = Some(sender);
+        if let Some(publisher) = self.publisher.take() {
+            let _ = publisher.close();
+        }
+        receiver
+    }
+
+    pub fn publish(&mut self, symbol: &str, event: MarketDataEvent) {
+        self.sequence += 1;
+        let message = MarketDataMessage { sequence: self.sequence, publisher: self.id, symbol: symbol.to_string(), event };
+        let body = match to_string(&message) {
+            Ok(body) => body,
+            Err(e) => {
+                eprintln!("Failed to serialize market data: {}", e);
+                return;
+            }
+        };
+        if let Some(forward) = &self.forward {
+            if forward.send((message.routing_key(), body)).is_err() {
+                eprintln!("Failed to publish market data {}: the publishing task has stopped", message.routing_key());
+            }
+            return;
+        }
+        let publisher = match self.publisher.take() {
+            Some(publisher) => publisher,
+            None => match Publisher::open(&self.exchange) {
+                Ok(publisher) => publisher,
+                Err(e) => {
+                    eprintln!("Failed to connect to market data exchange {}: {}", self.exchange, e);
+                    return;
+                }
+            },
+        };
+        match publisher.publish(&body, &message.routing_key()) {
+            Ok(()) => self.publisher = Some(publisher),
+            Err(e) => eprintln!("Failed to publish market data {}: {}", message.routing_key(), e),
+        }
+    }
+}
+
+impl Drop for MarketDataPublisher {
+    fn drop(&mut self) {
+        if let Some(publisher) = self.publisher.take() {
+            let _ = publisher.close();
+        }
+    }
+}
+
+// Trader side of the feed: a local price cache kept up to date from the exchange,
+// plus bars and analytics built from the trades and quotes it sees. It only needs the instrument list to
+// start, so it works in any process.
+#[derive(Clone)]
+pub struct MarketDataSubscriber {
+    latest: Arc<RwLock<Arc<MarketSnapshot>>>,
+    bars: Arc<RwLock<BarAggregator>>,
+    analytics: Arc<RwLock<Analytics>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl MarketDataSubscriber {
+    pub fn start(stocks: Vec<Stock>) -> Result<Self, String> {
+        let latest = Arc::new(RwLock::new(Arc::new(MarketSnapshot::new(stocks))));
+        let bars = Arc::new(RwLock::new(BarAggregator::default()));
+        let analytics = Arc::new(RwLock::new(Analytics::new()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let (ready_tx, ready_rx) = channel();
+
+        let cache = Arc::clone(&latest);
+        let bar_cache = Arc::clone(&bars);
+        let analytics_cache = Arc::clone(&analytics);
+        let stop_flag = Arc::clone(&stop);
+        thread::spawn(move || {
+            let result = subscribe(MARKET_DATA_EXCHANGE, "md.#", &stop_flag, ready_tx, |routing_key, body| {
+                match serde_json::from_str::<MarketDataMessage>(body) {
+                    Ok(message) => {
+                        match message.event {
+                            MarketDataEvent::Trade { price, quantity, aggressor, time, .. } => {
+                                bar_cache.write().unwrap().on_trade(&message.symbol, price, quantity, time);
+                                analytics_cache.write().unwrap().on_trade(&message.symbol, price, quantity, aggressor, time);
+                            }
+                            MarketDataEvent::Quote { best_bid, best_ask } => {
+                                analytics_cache.write().unwrap().on_quote(&message.symbol, best_bid, best_ask);
+                            }
+                            MarketDataEvent::Close { .. } => {
+                                bar_cache.write().unwrap().close(&message.symbol);
+                            }
+                            MarketDataEvent::Split { new_shares, old_shares } => {
+                                bar_cache.write().unwrap().split(&message.symbol, new_shares, old_shares);
+                                analytics_cache.write().unwrap().split(&message.symbol, new_shares, old_shares);
+                            }
+                            MarketDataEvent::SymbolChange { ref new_symbol } => {
+                                bar_cache.write().unwrap().rename(&message.symbol, new_symbol);
+                                analytics_cache.write().unwrap().rename(&message.symbol, new_symbol);
+                            }
+                            _ => {}
+                        }
+                        let status = Arc::make_mut(&mut cache.write().unwrap()).apply(&message);
+                        if let Some(DepthStatus::Gap { .. }) = status {
+                            request_snapshot(&message.symbol);
+                        }
+                    }
+                    Err(e) => eprintln!("Failed to deserialize market data {}: {}", routing_key, e),
+                }
+            });
+            if let Err(e) = result {
+                eprintln!("Market data subscriber stopped: {}", e);
+            }
+        });
+
+        // The sender is dropped without a signal if the subscription failed
+        ready_rx.recv().map_err(|_| "could not subscribe to market data".to_string())?;
+        Ok(MarketDataSubscriber { latest, bars, analytics, stop })
+    }
+
+    pub fn snapshot(&self) -> Arc<MarketSnapshot> {
+        Arc::clone(&self.latest.read().unwrap())
+    }
+
+    pub fn bars(&self, symbol: &str, interval: BarInterval) -> Vec<Bar> {
+        self.bars.read().unwrap().bars(symbol, interval)
+    }
+
+    pub fn last_completed_bar(&self, symbol: &str, interval: BarInterval) -> Option<Bar> {
+        self.bars.read().unwrap().last_completed(symbol, interval)
+    }
+
+    pub fn analytics(&self, symbol: &str) -> Option<SymbolStats> {
+        self.analytics.read().unwrap().stats(symbol)
+    }
+
+    // Messages are delivered asynchronously; wait until the cache has caught up with `publisher`
+    pub fn wait_for_sequence(&self, publisher: usize, sequence: u64, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        while self.snapshot().sequence(publisher) < sequence {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        true
+    }
+
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+// Ask the broker for a fresh depth snapshot of `symbol`, the same way orders are sent
+fn request_snapshot(symbol: &str) {
+    let request = SnapshotRequest { symbol: symbol.to_string() };
+    match to_string(&request) {
+        Ok(body) => {
+            if let Err(e) = publish(body, ORDER_EXCHANGE, &routing_key(symbol)) {
+                eprintln!("Failed to request a depth snapshot of {}: {}", symbol, e);
+            }
+        }
+        Err(e) => eprintln!("Failed to serialize snapshot request: {}", e),
+    }
+}
+
+// Differences between the broker's authoritative stocks and a market data view
+pub fn compare_views(authoritative: &[Stock], view: &MarketSnapshot) -> Vec<String> {
+    let mut mismatches = Vec::new();
+    for stock in authoritative {
+        match view.get(&stock.stock_name) {
+            None => mismatches.push(format!("{} missing from market data", stock.stock_name)),
+            Some(seen) if seen != stock => mismatches.push(format!(
+                "{}: broker ${} (close {:?}), market data ${} (close {:?})", stock.stock_name,
+                stock.current_price.to_decimal_string(), stock.closing_price.map(|p| p.to_decimal_string()),
+                seen.current_price.to_decimal_string(), seen.closing_price.map(|p| p.to_decimal_string()))),
+            Some(_) => {}
+        }
+    }
+    if view.stocks.len() != authoritative.len() {
+        mismatches.push(format!("market data has {} stocks, broker has {}", view.stocks.len(), authoritative.len()));
+    }
+    mismatches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forwarded_messages_keep_their_sequence_order() {
+        let mut publisher = MarketDataPublisher::new(MARKET_DATA_EXCHANGE, 0);
+        let mut messages = publisher.forward();
+        publisher.publish("NIKE", MarketDataEvent::Close { closing_price: Price::from_f64(101.0) });
+        publisher.publish("NIKE", MarketDataEvent::Quote { best_bid: Some(Price::from_f64(100.5)), best_ask: None });
+        assert_eq!(publisher.sequence(), 2);
+
+        let (routing_key, body) = messages.try_recv().unwrap();
+        assert_eq!(routing_key, "md.NIKE.close");
+        assert_eq!(serde_json::from_str::<MarketDataMessage>(&body).unwrap().sequence, 1);
+        let (routing_key, body) = messages.try_recv().unwrap();
+        assert_eq!(routing_key, "md.NIKE.quote");
+        assert_eq!(serde_json::from_str::<MarketDataMessage>(&body).unwrap().sequence, 2);
+        assert!(messages.try_recv().is_err());
+    }
+
+    #[test]
+    fn snapshot_tracks_each_publisher_sequence() {
+        let mut first = MarketDataPublisher::new(MARKET_DATA_EXCHANGE, 0);
+        let mut second = MarketDataPublisher::new(MARKET_DATA_EXCHANGE, 1);
+        let mut first_messages = first.forward();
+        let mut second_messages = second.forward();
+        for _ in 0..3 {
+            first.publish("NIKE", MarketDataEvent::Close { closing_price: Price::from_f64(101.0) });
+        }
+        second.publish("APPLE", MarketDataEvent::Close { closing_price: Price::from_f64(99.0) });
+        second.publish("APPLE", MarketDataEvent::Close { closing_price: Price::from_f64(98.0) });
+
+        fn apply(snapshot: &mut MarketSnapshot, messages: &mut UnboundedReceiver<(String, String)>) {
+            let (_, body) = messages.try_recv().unwrap();
+            snapshot.apply(&serde_json::from_str::<MarketDataMessage>(&body).unwrap());
+        }
+        let mut snapshot = MarketSnapshot::new(Vec::new());
+        for _ in 0..3 {
+            apply(&mut snapshot, &mut first_messages);
+        }
+        apply(&mut snapshot, &mut second_messages);
+        // The busier publisher being caught up says nothing about the other
+        assert_eq!(snapshot.sequence(0), 3);
+        assert_eq!(snapshot.sequence(1), 1);
+        assert!(snapshot.sequence(1) < second.sequence());
+        apply(&mut snapshot, &mut second_messages);
+        assert_eq!(snapshot.sequence(1), second.sequence());
+    }
+}
@@ -0,0 +1,141 @@
+use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use chrono::NaiveDate;
+use serde_json::to_string;
+use lapin::{Channel, Connection};
+
+use crate::async_rmq;
+use crate::market_data::MarketDataSubscriber;
+use crate::options::{OptionContract, VolSurface};
+use crate::order::{Order, Side, TimeInForce};
+use crate::price::{Price, Quantity};
+use crate::rmq::{publish, ORDER_EXCHANGE};
+use crate::session::MarketPhase;
+use crate::shard::routing_key;
+
+// Half the quoted spread as a fraction of theoretical value; never less than a tick
+pub const QUOTE_HALF_SPREAD: f64 = 0.05;
+pub const QUOTE_SIZE: u64 = 10;
+pub const QUOTE_INTERVAL: Duration = Duration::from_millis(1500);
+
+// Quotes every listed option around its Black-Scholes value off the underlying's
+// last price. Each new quote replaces the previous one on the same side.
+// It trades from its own account, after the traders'.
+pub struct MarketMaker {
+    id: usize,
+    market_data: MarketDataSubscriber,
+    contracts: Vec<OptionContract>,
+    surface: VolSurface,
+    tick_size: Price,
+    date: NaiveDate,
+    stop_signal: Arc<AtomicBool>,
+}
+
+impl MarketMaker {
+    pub fn new(id: usize, market_data: MarketDataSubscriber, contracts: Vec<OptionContract>, surface: VolSurface, tick_size: Price, date: NaiveDate,
+        stop_signal: Arc<AtomicBool>) -> Self {
+        MarketMaker { id, market_data, contracts, surface, tick_size, date, stop_signal }
+    }
+
+    // Bid and ask around the theoretical value; no bid when it would not be above zero
+    pub fn quote(&self, contract: &OptionContract, spot: Price) -> (Option<Price>, Price) {
+        let theoretical = self.surface.value(contract, spot, self.date).price;
+        let half_spread = (theoretical * QUOTE_HALF_SPREAD).max(self.tick_size.to_f64());
+        let bid = Price::from_f64(theoretical - half_spread).round_to_tick(self.tick_size);
+        let ask = Price::from_f64(theoretical + half_spread).round_to_tick(self.tick_size).max(self.tick_size);
+        let bid = if bid > Price::ZERO && bid < ask { Some(bid) } else { None };
+        (bid, ask)
+    }
+
+    // Quotes go out every interval while the feed says the market trades continuously;
+    // options do not trade in any other phase
+    fn run(&self) {
+        loop {
+            thread::sleep(QUOTE_INTERVAL);
+            if self.stop_signal.load(Ordering::SeqCst) {
+                break;
+            }
+            for (symbol, order) in self.quote_orders() {
+                if let Err(e) = publish(order, ORDER_EXCHANGE, &routing_key(&symbol)) {
+                    eprintln!("Market maker: Failed to send quote: {}", e);
+                }
+            }
+        }
+        println!("Market maker is now stopping.");
+    }
+
+    // The same as a task in the async mode, sending over `channel`
+    async fn run_async(&self, channel: &Channel) {
+        loop {
+            tokio::time::sleep(QUOTE_INTERVAL).await;
+            if self.stop_signal.load(Ordering::SeqCst) {
+                break;
+            }
+            for (symbol, order) in self.quote_orders() {
+                if let Err(e) = async_rmq::publish(channel, order, ORDER_EXCHANGE, &routing_key(&symbol)).await {
+                    eprintln!("Market maker: Failed to send quote: {}", e);
+                }
+            }
+        }
+        println!("Market maker is now stopping.");
+    }
+
+    // This round's quotes, serialized, with their symbols
+    fn quote_orders(&self) -> Vec<(String, String)> {
+        let snapshot = self.market_data.snapshot();
+        if snapshot.phase != Some(MarketPhase::ContinuousTrading) {
+            return Vec::new();
+        }
+        let mut orders = Vec::new();
+        let mut quoted = 0;
+        for contract in &self.contracts {
+            let Some(spot) = snapshot.get(&contract.underlying).map(|s| s.current_price) else {
+                continue;
+            };
+            let (bid, ask) = self.quote(contract, spot);
+            if let Some(bid) = bid {
+                orders.extend(self.quote_order(contract, Side::Buy, bid));
+            }
+            orders.extend(self.quote_order(contract, Side::Sell, ask));
+            quoted += 1;
+        }
+        println!("MARKET MAKER: quoted {} option contracts", quoted);
+        orders
+    }
+
+    fn quote_order(&self, contract: &OptionContract, side: Side, price: Price) -> Option<(String, String)> {
+        let order = Order::new(self.id, &contract.symbol, side, price, Quantity::new(QUOTE_SIZE), TimeInForce::Day);
+        match to_string(&order) {
+            Ok(order) => Some((contract.symbol.clone(), order)),
+            Err(e) => {
+                eprintln!("Market maker: Failed to serialize quote: {}", e);
+                None
+            }
+        }
+    }
+}
+
+pub fn start_market_maker(market_maker: MarketMaker) -> JoinHandle<()> {
+    thread::spawn(move || market_maker.run())
+}
+
+// The async mode's market makers: tasks sending over one channel on the shared connection
+pub async fn run_market_makers(connection: &Connection, market_makers: Vec<MarketMaker>) {
+    let channel = match async_rmq::open_exchange(connection, ORDER_EXCHANGE).await {
+        Ok(channel) => channel,
+        Err(e) => {
+            eprintln!("Market maker: Failed to open a channel: {}", e);
+            return;
+        }
+    };
+    let handles: Vec<_> = market_makers.into_iter()
+        .map(|market_maker| {
+            let channel = channel.clone();
+            tokio::spawn(async move { market_maker.run_async(&channel).await })
+        })
+        .collect();
+    for handle in handles {
+        handle.await.unwrap();
+    }
+}
@@ -0,0 +1,367 @@
+use chrono::NaiveDate;
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+use crate::calendar::TradingCalendar;
+use crate::instrument::InstrumentRegistry;
+use crate::order_book::OrderBook;
+use crate::price::Price;
+
+// Shares delivered per contract on exercise
+pub const OPTION_MULTIPLIER: u64 = 100;
+// Option premiums are quoted per share in steps of this much
+pub const OPTION_TICK_SIZE: f64 = 0.05;
+pub const RISK_FREE_RATE: f64 = 0.04;
+pub const DAYS_PER_YEAR: f64 = 365.0;
+// Strikes listed either side of the at-the-money strike, spaced as a fraction of the reference price
+pub const STRIKES_EACH_SIDE: i64 = 1;
+pub const STRIKE_SPACING: f64 = 0.05;
+// Expiries in trading days after the first session: the same week and about a month out
+pub const EXPIRY_OFFSETS: [usize; 2] = [2, 20];
+// At-the-money implied volatility for instruments that do not set one
+pub const DEFAULT_VOLATILITY: f64 = 0.30;
+// Shape of the volatility surface around each underlying's at-the-money volatility
+pub const VOL_SKEW: f64 = -0.10;
+pub const VOL_SMILE: f64 = 0.20;
+pub const VOL_TERM_SLOPE: f64 = -0.05;
+pub const MIN_VOLATILITY: f64 = 0.05;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OptionKind {
+    Call,
+    Put,
+}
+
+impl OptionKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OptionKind::Call => "call",
+            OptionKind::Put => "put",
+        }
+    }
+
+    fn code(&self) -> char {
+        match self {
+            OptionKind::Call => 'C',
+            OptionKind::Put => 'P',
+        }
+    }
+}
+
+// A listed European option. The symbol is fixed at listing, e.g. NIKE241226C1500;
+// strike and multiplier are adjusted for splits like OCC contracts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OptionContract {
+    pub symbol: String,
+    pub underlying: String,
+    pub kind: OptionKind,
+    pub strike: Price,
+    pub expiry: NaiveDate,
+    pub multiplier: u64,
+    pub currency: String,
+}
+
+impl OptionContract {
+    pub fn new(underlying: &str, kind: OptionKind, strike: Price, expiry: NaiveDate, multiplier: u64, currency: &str) -> Self {
+        let symbol = format!("{}{}{}{:.0}", underlying, expiry.format("%y%m%d"), kind.code(), strike.to_f64());
+        OptionContract { symbol, underlying: underlying.to_string(), kind, strike, expiry, multiplier, currency: currency.to_string() }
+    }
+
+    // Per share, what exercising now would be worth
+    pub fn intrinsic(&self, spot: Price) -> f64 {
+        match self.kind {
+            OptionKind::Call => (spot.to_f64() - self.strike.to_f64()).max(0.0),
+            OptionKind::Put => (self.strike.to_f64() - spot.to_f64()).max(0.0),
+        }
+    }
+
+    // Years left until the close on the expiry date
+    pub fn time_to_expiry(&self, date: NaiveDate) -> f64 {
+        (self.expiry - date).num_days().max(0) as f64 / DAYS_PER_YEAR
+    }
+}
+
+// Theoretical value per share with its Greeks. Vega and rho are per percentage
+// point of volatility and rate, theta is per calendar day.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct OptionValuation {
+    pub price: f64,
+    pub delta: f64,
+    pub gamma: f64,
+    pub vega: f64,
+    pub theta: f64,
+    pub rho: f64,
+}
+
+fn norm_pdf(x: f64) -> f64 {
+    (-0.5 * x * x).exp() / (2.0 * PI).sqrt()
+}
+
+// Abramowitz and Stegun 26.2.17, accurate to about 1e-7
+pub fn norm_cdf(x: f64) -> f64 {
+    let t = 1.0 / (1.0 + 0.2316419 * x.abs());
+    let poly = t * (0.319381530 + t * (-0.356563782 + t * (1.781477937 + t * (-1.821255978 + t * 1.330274429))));
+    let upper = norm_pdf(x) * poly;
+    if x >= 0.0 { 1.0 - upper } else { upper }
+}
+
+// Black-Scholes for a European option on a non-dividend-paying stock. At expiry,
+// or with no volatility, the option is worth its discounted intrinsic value.
+pub fn black_scholes(kind: OptionKind, spot: f64, strike: f64, time: f64, rate: f64, volatility: f64) -> OptionValuation {
+    let discount = (-rate * time).exp();
+    if time <= 0.0 || volatility <= 0.0 || spot <= 0.0 || strike <= 0.0 {
+        let forward_intrinsic = match kind {
+            OptionKind::Call => (spot - strike * discount).max(0.0),
+            OptionKind::Put => (strike * discount - spot).max(0.0),
+        };
+        let delta = match kind {
+            OptionKind::Call if forward_intrinsic > 0.0 => 1.0,
+            OptionKind::Put if forward_intrinsic > 0.0 => -1.0,
+            _ => 0.0,
+        };
+        return OptionValuation { price: forward_intrinsic, delta, ..OptionValuation::default() };
+    }
+
+    let sqrt_time = time.sqrt();
+    let d1 = ((spot / strike).ln() + (rate + 0.5 * volatility * volatility) * time) / (volatility * sqrt_time);
+    let d2 = d1 - volatility * sqrt_time;
+    let gamma = norm_pdf(d1) / (spot * volatility * sqrt_time);
+    let vega = spot * norm_pdf(d1) * sqrt_time / 100.0;
+    let decay = -spot * norm_pdf(d1) * volatility / (2.0 * sqrt_time);
+    let (price, delta, theta, rho) = match kind {
+        OptionKind::Call => (
+            spot * norm_cdf(d1) - strike * discount * norm_cdf(d2),
+            norm_cdf(d1),
+            decay - rate * strike * discount * norm_cdf(d2),
+            strike * time * discount * norm_cdf(d2),
+        ),
+        OptionKind::Put => (
+            strike * discount * norm_cdf(-d2) - spot * norm_cdf(-d1),
+            norm_cdf(d1) - 1.0,
+            decay + rate * strike * discount * norm_cdf(-d2),
+            -strike * time * discount * norm_cdf(-d2),
+        ),
+    };
+    OptionValuation { price, delta, gamma, vega, theta: theta / DAYS_PER_YEAR, rho: rho / 100.0 }
+}
+
+// Implied volatility by underlying, strike and expiry: each underlying's at-the-money
+// volatility bent by a skew and smile in log-moneyness and a slope in time
+#[derive(Debug, Clone)]
+pub struct VolSurface {
+    pub atm: HashMap<String, f64>,
+    pub skew: f64,
+    pub smile: f64,
+    pub term_slope: f64,
+}
+
+impl Default for VolSurface {
+    fn default() -> Self {
+        VolSurface { atm: HashMap::new(), skew: VOL_SKEW, smile: VOL_SMILE, term_slope: VOL_TERM_SLOPE }
+    }
+}
+
+impl VolSurface {
+    pub fn new(registry: &InstrumentRegistry) -> Self {
+        let atm = registry.instruments().iter()
+            .map(|i| (i.symbol.clone(), i.volatility))
+            .collect();
+        VolSurface { atm, ..VolSurface::default() }
+    }
+
+    pub fn volatility(&self, underlying: &str, strike: Price, spot: Price, time: f64) -> f64 {
+        let atm = self.atm.get(underlying).copied().unwrap_or(MIN_VOLATILITY);
+        let moneyness = (strike.to_f64() / spot.to_f64()).ln();
+        (atm + self.skew * moneyness + self.smile * moneyness * moneyness + self.term_slope * time).max(MIN_VOLATILITY)
+    }
+
+    // Theoretical value per share of `contract` with the underlying at `spot` on `date`
+    pub fn value(&self, contract: &OptionContract, spot: Price, date: NaiveDate) -> OptionValuation {
+        let time = contract.time_to_expiry(date);
+        let volatility = self.volatility(&contract.underlying, contract.strike, spot, time);
+        black_scholes(contract.kind, spot.to_f64(), contract.strike.to_f64(), time, RISK_FREE_RATE, volatility)
+    }
+
+    pub fn rename(&mut self, old: &str, new: &str) {
+        if let Some(atm) = self.atm.remove(old) {
+            self.atm.insert(new.to_string(), atm);
+        }
+    }
+}
+
+// Every listed contract with its order book
+#[derive(Debug, Clone)]
+pub struct OptionMarket {
+    pub surface: VolSurface,
+    pub tick_size: Price,
+    // In listing order: by underlying, then expiry, kind and strike
+    contracts: Vec<OptionContract>,
+    books: HashMap<String, OrderBook>,
+}
+
+impl OptionMarket {
+    // Calls and puts around the reference price of every instrument with options listed
+    pub fn new(registry: &InstrumentRegistry, calendar: &TradingCalendar) -> Self {
+        let days = calendar.trading_days(EXPIRY_OFFSETS.iter().max().map_or(0, |offset| offset + 1));
+        let mut contracts = Vec::new();
+        for instrument in registry.instruments().iter().filter(|i| i.options_listed && i.is_tradable()) {
+            let step = (instrument.reference_price.to_f64() * STRIKE_SPACING).round().max(1.0);
+            let at_the_money = (instrument.reference_price.to_f64() / step).round() * step;
+            for offset in EXPIRY_OFFSETS {
+                for kind in [OptionKind::Call, OptionKind::Put] {
+                    for k in -STRIKES_EACH_SIDE..=STRIKES_EACH_SIDE {
+                        let strike = Price::from_f64(at_the_money + k as f64 * step);
+                        contracts.push(OptionContract::new(&instrument.symbol, kind, strike, days[offset], OPTION_MULTIPLIER, &instrument.currency));
+                    }
+                }
+            }
+        }
+        let books = contracts.iter()
+            .map(|c| (c.symbol.clone(), OrderBook::new(&c.symbol)))
+            .collect();
+        OptionMarket { surface: VolSurface::new(registry), tick_size: Price::from_f64(OPTION_TICK_SIZE), contracts, books }
+    }
+
+    pub fn contracts(&self) -> &[OptionContract] {
+        &self.contracts
+    }
+
+    pub fn get(&self, symbol: &str) -> Option<&OptionContract> {
+        self.contracts.iter().find(|c| c.symbol == symbol)
+    }
+
+    pub fn expiries(&self, underlying: &str) -> Vec<NaiveDate> {
+        let mut expiries: Vec<NaiveDate> = self.contracts.iter().filter(|c| c.underlying == underlying).map(|c| c.expiry).collect();
+        expiries.dedup();
+        expiries
+    }
+
+    // One expiry's chain as (strike, call, put) rows, lowest strike first
+    pub fn chain(&self, underlying: &str, expiry: NaiveDate) -> Vec<(Price, Option<&OptionContract>, Option<&OptionContract>)> {
+        let listed: Vec<&OptionContract> = self.contracts.iter().filter(|c| c.underlying == underlying && c.expiry == expiry).collect();
+        let mut strikes: Vec<Price> = listed.iter().map(|c| c.strike).collect();
+        strikes.sort();
+        strikes.dedup();
+        strikes.into_iter()
+            .map(|strike| {
+                let find = |kind: OptionKind| listed.iter().find(|c| c.kind == kind && c.strike == strike).copied();
+                (strike, find(OptionKind::Call), find(OptionKind::Put))
+            })
+            .collect()
+    }
+
+    pub fn book(&self, symbol: &str) -> Option<&OrderBook> {
+        self.books.get(symbol)
+    }
+
+    pub fn book_mut(&mut self, symbol: &str) -> Option<&mut OrderBook> {
+        self.books.get_mut(symbol)
+    }
+
+    // End of day: returns (symbol, expired) for every book that lost DAY orders
+    pub fn expire_day_orders(&mut self) -> Vec<(String, usize)> {
+        let mut expired: Vec<(String, usize)> = self.books.iter_mut()
+            .map(|(symbol, book)| (symbol.clone(), book.expire_day_orders()))
+            .filter(|(_, expired)| *expired > 0)
+            .collect();
+        expired.sort();
+        expired
+    }
+
+    // Value of one contract (premium times multiplier) for every contract whose underlying has a price
+    pub fn marks(&self, prices: &HashMap<String, Price>, date: NaiveDate) -> HashMap<String, Price> {
+        self.contracts.iter()
+            .filter_map(|c| {
+                let spot = *prices.get(&c.underlying)?;
+                Some((c.symbol.clone(), Price::from_f64(self.surface.value(c, spot, date).price * c.multiplier as f64)))
+            })
+            .collect()
+    }
+
+    // Delist everything expiring on or before `date`; their resting orders go with them
+    pub fn expire(&mut self, date: NaiveDate) -> Vec<OptionContract> {
+        let (expired, listed): (Vec<OptionContract>, Vec<OptionContract>) = self.contracts.drain(..).partition(|c| c.expiry <= date);
+        self.contracts = listed;
+        for contract in &expired {
+            self.books.remove(&contract.symbol);
+        }
+        expired
+    }
+
+    pub fn rename(&mut self, old: &str, new: &str) {
+        for contract in self.contracts.iter_mut().filter(|c| c.underlying == old) {
+            contract.underlying = new.to_string();
+        }
+        self.surface.rename(old, new);
+    }
+
+    // A `new_shares` for `old_shares` split lowers strikes and raises multipliers so
+    // every contract still covers the same value. Resting premiums no longer make
+    // sense and are cancelled; returns how many orders were.
+    pub fn split(&mut self, underlying: &str, new_shares: u64, old_shares: u64) -> usize {
+        let mut cancelled = 0;
+        for contract in self.contracts.iter_mut().filter(|c| c.underlying == underlying) {
+            contract.strike = contract.strike.scale(old_shares as f64 / new_shares as f64);
+            contract.multiplier = contract.multiplier * new_shares / old_shares;
+            if let Some(book) = self.books.get_mut(&contract.symbol) {
+                cancelled += book.clear();
+            }
+        }
+        cancelled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instrument::INSTRUMENTS_FILE;
+    use crate::order::{Order, Side, TimeInForce};
+    use crate::price::Quantity;
+
+    fn close(actual: f64, expected: f64, tolerance: f64) {
+        assert!((actual - expected).abs() < tolerance, "{} is not within {} of {}", actual, tolerance, expected);
+    }
+
+    #[test]
+    fn black_scholes_matches_reference_values() {
+        let call = black_scholes(OptionKind::Call, 100.0, 100.0, 1.0, 0.05, 0.2);
+        close(call.price, 10.4506, 1e-4);
+        close(call.delta, 0.6368, 1e-4);
+        close(call.gamma, 0.018762, 1e-5);
+        close(call.vega, 0.37524, 1e-4);
+        let put = black_scholes(OptionKind::Put, 100.0, 100.0, 1.0, 0.05, 0.2);
+        close(put.price, 5.5735, 1e-4);
+        close(put.delta, -0.3632, 1e-4);
+        // Hull's example: six months, 42 spot, 40 strike
+        close(black_scholes(OptionKind::Call, 42.0, 40.0, 0.5, 0.1, 0.2).price, 4.7594, 1e-4);
+        close(black_scholes(OptionKind::Put, 42.0, 40.0, 0.5, 0.1, 0.2).price, 0.8086, 1e-4);
+    }
+
+    #[test]
+    fn black_scholes_at_expiry_is_intrinsic_value() {
+        let call = black_scholes(OptionKind::Call, 110.0, 100.0, 0.0, 0.05, 0.2);
+        assert_eq!((call.price, call.delta), (10.0, 1.0));
+        let put = black_scholes(OptionKind::Put, 110.0, 100.0, 0.0, 0.05, 0.2);
+        assert_eq!((put.price, put.delta), (0.0, 0.0));
+    }
+
+    #[test]
+    fn split_adjusts_strikes_and_multipliers_and_cancels_resting_premiums() {
+        let registry = InstrumentRegistry::load(INSTRUMENTS_FILE).unwrap();
+        let mut market = OptionMarket::new(&registry, &TradingCalendar::default_calendar());
+        let before = market.contracts().to_vec();
+        let symbol = before.iter().find(|c| c.underlying == "NIKE").unwrap().symbol.clone();
+        market.book_mut(&symbol).unwrap().submit(Order::new(1, &symbol, Side::Buy, Price::from_f64(1.0), Quantity::new(1), TimeInForce::Day));
+
+        assert_eq!(market.split("NIKE", 2, 1), 1);
+        for (old, new) in before.iter().zip(market.contracts()) {
+            if old.underlying == "NIKE" {
+                assert_eq!(new.strike, old.strike.scale(0.5));
+                assert_eq!(new.multiplier, OPTION_MULTIPLIER * 2);
+            } else {
+                assert_eq!(new, old);
+            }
+        }
+        assert!(market.book(&symbol).unwrap().depth(Side::Buy).is_empty());
+    }
+}
@@ -0,0 +1,78 @@
+use serde::{Serialize, Deserialize};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use crate::price::{Price, Quantity};
+
+static NEXT_ORDER_ID: AtomicUsize = AtomicUsize::new(1);
+
+pub fn next_order_id() -> usize {
+    NEXT_ORDER_ID.fetch_add(1, Ordering::SeqCst)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+impl Side {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Side::Buy => "buy",
+            Side::Sell => "sell",
+        }
+    }
+}
+
+// DAY orders expire at the end of the session, GTC orders carry over to the next one
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimeInForce {
+    #[default]
+    Day,
+    GoodTillCancel,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Order {
+    pub order_id: usize,
+    pub trader_id: usize,
+    pub stock_name: String,
+    pub side: Side,
+    pub price: Price,
+    pub quantity: Quantity,
+    #[serde(default)]
+    pub time_in_force: TimeInForce,
+    // Where the order should execute; none lets the broker's smart order router decide
+    #[serde(default)]
+    pub venue: Option<String>,
+}
+
+impl Order {
+    pub fn new(trader_id: usize, stock_name: &str, side: Side, price: Price, quantity: Quantity, time_in_force: TimeInForce) -> Self {
+        Order {
+            order_id: next_order_id(),
+            trader_id,
+            stock_name: stock_name.to_string(),
+            side,
+            price,
+            quantity,
+            time_in_force,
+            venue: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Trade {
+    pub stock_name: String,
+    pub price: Price,
+    pub quantity: Quantity,
+    pub buy_order_id: usize,
+    pub sell_order_id: usize,
+    pub buyer_id: usize,
+    pub seller_id: usize,
+    // None when the trade came out of an auction uncross
+    pub aggressor: Option<Side>,
+    // None for options and futures, which trade in a single book
+    #[serde(default)]
+    pub venue: Option<String>,
+}
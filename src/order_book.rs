@@ -0,0 +1,353 @@
+use serde::{Serialize, Deserialize};
+
+use crate::order::{Order, Side, TimeInForce, Trade};
+use crate::price::{Price, Quantity};
+
+// Result of an auction calculation: the uncrossing price and how much would trade there
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Uncross {
+    pub price: Price,
+    pub volume: Quantity,
+    pub surplus: Quantity,
+}
+
+// All resting orders at one price, aggregated for the depth feed
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PriceLevel {
+    pub price: Price,
+    pub quantity: Quantity,
+    pub orders: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct OrderBook {
+    pub stock_name: String,
+    // Bids are kept best (highest) price first, asks best (lowest) price first.
+    // Orders at the same price keep their arrival order.
+    bids: Vec<Order>,
+    asks: Vec<Order>,
+}
+
+impl OrderBook {
+    pub fn new(stock_name: &str) -> Self {
+        OrderBook {
+            stock_name: stock_name.to_string(),
+            bids: Vec::new(),
+            asks: Vec::new(),
+        }
+    }
+
+    pub fn best_bid(&self) -> Option<Price> {
+        self.bids.first().map(|o| o.price)
+    }
+
+    pub fn best_ask(&self) -> Option<Price> {
+        self.asks.first().map(|o| o.price)
+    }
+
+    // Price levels for one side, best first
+    pub fn depth(&self, side: Side) -> Vec<PriceLevel> {
+        let orders = match side {
+            Side::Buy => &self.bids,
+            Side::Sell => &self.asks,
+        };
+        let mut levels: Vec<PriceLevel> = Vec::new();
+        for order in orders {
+            match levels.last_mut() {
+                Some(level) if level.price == order.price => {
+                    level.quantity += order.quantity;
+                    level.orders += 1;
+                }
+                _ => levels.push(PriceLevel { price: order.price, quantity: order.quantity, orders: 1 }),
+            }
+        }
+        levels
+    }
+
+    // Quantity a trader still has resting on one side
+    pub fn open_quantity(&self, trader_id: usize, side: Side) -> Quantity {
+        let orders = match side {
+            Side::Buy => &self.bids,
+            Side::Sell => &self.asks,
+        };
+        orders.iter().filter(|o| o.trader_id == trader_id).map(|o| o.quantity).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bids.is_empty() && self.asks.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.bids.len() + self.asks.len()
+    }
+
+    // End of day: drop DAY orders, keep good-till-cancel ones. Returns how many expired.
+    pub fn expire_day_orders(&mut self) -> usize {
+        let before = self.len();
+        self.bids.retain(|o| o.time_in_force == TimeInForce::GoodTillCancel);
+        self.asks.retain(|o| o.time_in_force == TimeInForce::GoodTillCancel);
+        before - self.len()
+    }
+
+    // Continuous trading: match against the opposite side, rest whatever is left
+    pub fn submit(&mut self, mut order: Order) -> Vec<Trade> {
+        let mut trades = Vec::new();
+        let opposite = match order.side {
+            Side::Buy => &mut self.asks,
+            Side::Sell => &mut self.bids,
+        };
+
+        while !order.quantity.is_zero() {
+            let crosses = match (opposite.first(), order.side) {
+                (Some(resting), Side::Buy) => resting.price <= order.price,
+                (Some(resting), Side::Sell) => resting.price >= order.price,
+                (None, _) => false,
+            };
+            if !crosses {
+                break;
+            }
+
+            let resting = &mut opposite[0];
+            let quantity = order.quantity.min(resting.quantity);
+            trades.push(make_trade(&order, resting, resting.price, quantity, Some(order.side)));
+            order.quantity -= quantity;
+            resting.quantity -= quantity;
+            if resting.quantity.is_zero() {
+                opposite.remove(0);
+            }
+        }
+
+        if !order.quantity.is_zero() {
+            self.rest(order);
+        }
+        trades
+    }
+
+    // Pull a resting order off the book
+    pub fn cancel(&mut self, order_id: usize) -> Option<Order> {
+        for orders in [&mut self.bids, &mut self.asks] {
+            if let Some(index) = orders.iter().position(|o| o.order_id == order_id) {
+                return Some(orders.remove(index));
+            }
+        }
+        None
+    }
+
+    // Pull every order a trader has resting on one side; returns how many there were
+    pub fn cancel_all(&mut self, trader_id: usize, side: Side) -> usize {
+        let orders = match side {
+            Side::Buy => &mut self.bids,
+            Side::Sell => &mut self.asks,
+        };
+        let before = orders.len();
+        orders.retain(|o| o.trader_id != trader_id);
+        before - orders.len()
+    }
+
+    // Drop every resting order; returns how many there were
+    pub fn clear(&mut self) -> usize {
+        let before = self.len();
+        self.bids.clear();
+        self.asks.clear();
+        before
+    }
+
+    pub fn rename(&mut self, stock_name: &str) {
+        self.stock_name = stock_name.to_string();
+        for order in self.bids.iter_mut().chain(self.asks.iter_mut()) {
+            order.stock_name = stock_name.to_string();
+        }
+    }
+
+    // Resting orders after a `new_shares` for `old_shares` split: prices move back onto
+    // the tick grid, quantities down to whole lots. Returns how many orders were too
+    // small to survive.
+    pub fn split(&mut self, new_shares: u64, old_shares: u64, tick_size: Price, lot_size: Quantity) -> usize {
+        let before = self.len();
+        let lot = lot_size.value().max(1);
+        for orders in [&mut self.bids, &mut self.asks] {
+            for order in orders.iter_mut() {
+                order.price = order.price.scale(old_shares as f64 / new_shares as f64).round_to_tick(tick_size).max(tick_size);
+                order.quantity = Quantity::new(order.quantity.value() * new_shares / old_shares / lot * lot);
+            }
+            orders.retain(|o| !o.quantity.is_zero());
+        }
+        before - self.len()
+    }
+
+    // Call phase: orders are only collected, nothing executes until the uncross
+    pub fn collect(&mut self, order: Order) {
+        self.rest(order);
+    }
+
+    // Price that maximizes executed volume. Ties go to the smallest surplus and
+    // then to the price closest to the reference (usually the last traded price).
+    pub fn indicative_uncross(&self, reference_price: Price) -> Option<Uncross> {
+        let mut candidates: Vec<Price> = self.bids.iter().chain(self.asks.iter()).map(|o| o.price).collect();
+        candidates.sort();
+        candidates.dedup();
+
+        let mut best: Option<Uncross> = None;
+        for price in candidates {
+            let demand: Quantity = self.bids.iter().filter(|o| o.price >= price).map(|o| o.quantity).sum();
+            let supply: Quantity = self.asks.iter().filter(|o| o.price <= price).map(|o| o.quantity).sum();
+            let volume = demand.min(supply);
+            if volume.is_zero() {
+                continue;
+            }
+            let candidate = Uncross { price, volume, surplus: demand.max(supply) - volume };
+
+            let better = match best {
+                None => true,
+                Some(current) => {
+                    if candidate.volume != current.volume {
+                        candidate.volume > current.volume
+                    } else if candidate.surplus != current.surplus {
+                        candidate.surplus < current.surplus
+                    } else {
+                        (candidate.price - reference_price).abs() < (current.price - reference_price).abs()
+                    }
+                }
+            };
+            if better {
+                best = Some(candidate);
+            }
+        }
+        best
+    }
+
+    // Execute every crossing order at the single auction price
+    pub fn uncross(&mut self, reference_price: Price) -> Option<(Uncross, Vec<Trade>)> {
+        let uncross = self.indicative_uncross(reference_price)?;
+        let mut trades = Vec::new();
+
+        while let (Some(bid), Some(ask)) = (self.bids.first(), self.asks.first()) {
+            if bid.price < uncross.price || ask.price > uncross.price {
+                break;
+            }
+            let quantity = bid.quantity.min(ask.quantity);
+            trades.push(make_trade(bid, ask, uncross.price, quantity, None));
+
+            self.bids[0].quantity -= quantity;
+            if self.bids[0].quantity.is_zero() {
+                self.bids.remove(0);
+            }
+            self.asks[0].quantity -= quantity;
+            if self.asks[0].quantity.is_zero() {
+                self.asks.remove(0);
+            }
+        }
+        Some((uncross, trades))
+    }
+
+    fn rest(&mut self, order: Order) {
+        let (book, position) = match order.side {
+            Side::Buy => {
+                let position = self.bids.iter().position(|o| o.price < order.price);
+                (&mut self.bids, position)
+            }
+            Side::Sell => {
+                let position = self.asks.iter().position(|o| o.price > order.price);
+                (&mut self.asks, position)
+            }
+        };
+        match position {
+            Some(index) => book.insert(index, order),
+            None => book.push(order),
+        }
+    }
+}
+
+fn make_trade(incoming: &Order, resting: &Order, price: Price, quantity: Quantity, aggressor: Option<Side>) -> Trade {
+    let (buy, sell) = match incoming.side {
+        Side::Buy => (incoming, resting),
+        Side::Sell => (resting, incoming),
+    };
+    Trade {
+        stock_name: incoming.stock_name.clone(),
+        price,
+        quantity,
+        buy_order_id: buy.order_id,
+        sell_order_id: sell.order_id,
+        buyer_id: buy.trader_id,
+        seller_id: sell.trader_id,
+        aggressor,
+        venue: incoming.venue.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(trader_id: usize, side: Side, price: f64, quantity: u64) -> Order {
+        Order::new(trader_id, "TEST", side, Price::from_f64(price), Quantity::new(quantity), TimeInForce::Day)
+    }
+
+    fn book(orders: Vec<Order>) -> OrderBook {
+        let mut book = OrderBook::new("TEST");
+        for order in orders {
+            book.collect(order);
+        }
+        book
+    }
+
+    #[test]
+    fn uncross_maximizes_volume() {
+        let book = book(vec![order(1, Side::Buy, 101.0, 10), order(2, Side::Buy, 100.0, 10), order(3, Side::Sell, 100.0, 15)]);
+        let uncross = book.indicative_uncross(Price::from_f64(101.0)).unwrap();
+        assert_eq!(uncross, Uncross { price: Price::from_f64(100.0), volume: Quantity::new(15), surplus: Quantity::new(5) });
+    }
+
+    #[test]
+    fn equal_volume_goes_to_smallest_surplus() {
+        let book = book(vec![order(1, Side::Buy, 102.0, 10), order(2, Side::Buy, 100.0, 3), order(3, Side::Sell, 99.0, 10), order(4, Side::Sell, 101.0, 5)]);
+        // 101 and 102 leave a surplus of 5, 99 and 100 only 3; 100 is the closer of those to the reference
+        let uncross = book.indicative_uncross(Price::from_f64(102.0)).unwrap();
+        assert_eq!(uncross, Uncross { price: Price::from_f64(100.0), volume: Quantity::new(10), surplus: Quantity::new(3) });
+    }
+
+    #[test]
+    fn equal_volume_and_surplus_goes_to_price_nearest_reference() {
+        let book = book(vec![order(1, Side::Buy, 101.0, 10), order(2, Side::Sell, 100.0, 10)]);
+        assert_eq!(book.indicative_uncross(Price::from_f64(99.0)).unwrap().price, Price::from_f64(100.0));
+        assert_eq!(book.indicative_uncross(Price::from_f64(105.0)).unwrap().price, Price::from_f64(101.0));
+    }
+
+    #[test]
+    fn no_uncross_without_crossing_orders() {
+        let book = book(vec![order(1, Side::Buy, 99.0, 10), order(2, Side::Sell, 100.0, 10)]);
+        assert_eq!(book.indicative_uncross(Price::from_f64(100.0)), None);
+    }
+
+    #[test]
+    fn uncross_executes_at_a_single_price_and_leaves_the_surplus() {
+        let mut book = book(vec![order(1, Side::Buy, 101.0, 10), order(2, Side::Buy, 100.0, 10), order(3, Side::Sell, 100.0, 15)]);
+        let (uncross, trades) = book.uncross(Price::from_f64(101.0)).unwrap();
+        assert_eq!(trades.iter().map(|t| t.quantity).sum::<Quantity>(), uncross.volume);
+        assert!(trades.iter().all(|t| t.price == uncross.price && t.aggressor.is_none()));
+        // Price priority: the 101 bid fills in full before the 100 bid
+        assert_eq!((trades[0].buyer_id, trades[0].quantity), (1, Quantity::new(10)));
+        assert_eq!(book.depth(Side::Buy), vec![PriceLevel { price: Price::from_f64(100.0), quantity: Quantity::new(5), orders: 1 }]);
+        assert!(book.depth(Side::Sell).is_empty());
+    }
+
+    #[test]
+    fn split_rescales_resting_orders_and_drops_those_below_a_lot() {
+        let mut book = book(vec![order(1, Side::Buy, 101.0, 150), order(2, Side::Sell, 103.0, 40)]);
+        assert_eq!(book.split(2, 1, Price::from_f64(0.01), Quantity::new(100)), 1);
+        assert_eq!(book.depth(Side::Buy), vec![PriceLevel { price: Price::from_f64(50.5), quantity: Quantity::new(300), orders: 1 }]);
+        assert!(book.depth(Side::Sell).is_empty());
+    }
+
+    #[test]
+    fn end_of_day_expires_day_orders_and_keeps_gtc() {
+        let mut gtc = order(3, Side::Sell, 104.0, 10);
+        gtc.time_in_force = TimeInForce::GoodTillCancel;
+        let mut book = book(vec![order(1, Side::Buy, 101.0, 10), order(2, Side::Sell, 103.0, 10), gtc]);
+        assert_eq!(book.expire_day_orders(), 2);
+        assert_eq!(book.len(), 1);
+        assert_eq!(book.best_ask(), Some(Price::from_f64(104.0)));
+        assert_eq!(book.best_bid(), None);
+    }
+}
@@ -0,0 +1,374 @@
+use serde::{Serialize, Deserialize, Serializer, Deserializer};
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Mul, Neg, Sub, SubAssign};
+use std::str::FromStr;
+
+// Prices are stored as an integer number of 1/10_000ths of a currency unit
+pub const PRICE_DECIMALS: u32 = 4;
+pub const PRICE_SCALE: i64 = 10_i64.pow(PRICE_DECIMALS);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Price(i64);
+
+impl Price {
+    pub const ZERO: Price = Price(0);
+
+    pub fn from_raw(raw: i64) -> Self {
+        Price(raw)
+    }
+
+    pub fn raw(&self) -> i64 {
+        self.0
+    }
+
+    pub fn from_f64(value: f64) -> Self {
+        Price((value * PRICE_SCALE as f64).round() as i64)
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        self.0 as f64 / PRICE_SCALE as f64
+    }
+
+    // Multiply by a floating-point factor, e.g. a percentage move
+    pub fn scale(&self, factor: f64) -> Price {
+        Price((self.0 as f64 * factor).round() as i64)
+    }
+
+    pub fn abs(&self) -> Price {
+        Price(self.0.abs())
+    }
+
+    pub fn round_to_tick(&self, tick_size: Price) -> Price {
+        if tick_size.0 <= 0 {
+            return *self;
+        }
+        let ticks = (self.0 as f64 / tick_size.0 as f64).round() as i64;
+        Price(ticks * tick_size.0)
+    }
+
+    pub fn is_multiple_of(&self, tick_size: Price) -> bool {
+        tick_size.0 > 0 && self.0 % tick_size.0 == 0
+    }
+
+    // Cash value of `quantity` shares at this price, exact to the fourth decimal
+    pub fn notional(&self, quantity: Quantity) -> Amount {
+        Amount((self.0 as i128 * quantity.0 as i128) as i64)
+    }
+
+    // Value of a position, negative when short
+    pub fn position_value(&self, shares: i64) -> Amount {
+        Amount((self.0 as i128 * shares as i128) as i64)
+    }
+
+    // Exact decimal representation used on the wire
+    pub fn to_decimal_string(&self) -> String {
+        to_decimal_string(self.0)
+    }
+}
+
+fn to_decimal_string(raw: i64) -> String {
+    let sign = if raw < 0 { "-" } else { "" };
+    let whole = raw.abs() / PRICE_SCALE;
+    let fraction = raw.abs() % PRICE_SCALE;
+    format!("{}{}.{:0width$}", sign, whole, fraction, width = PRICE_DECIMALS as usize)
+}
+
+// Parse a decimal with at most PRICE_DECIMALS places into 1/10_000ths; `what` names it in errors
+fn parse_decimal(s: &str, what: &str) -> Result<i64, String> {
+    let s = s.trim();
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s),
+    };
+    let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+    if whole.is_empty() || fraction.len() > PRICE_DECIMALS as usize
+        || !whole.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit()) {
+        return Err(format!("invalid {}: {}", what, s));
+    }
+
+    let whole: i64 = whole.parse().map_err(|_| format!("invalid {}: {}", what, s))?;
+    let fraction: i64 = format!("{:0<width$}", fraction, width = PRICE_DECIMALS as usize)
+        .parse()
+        .map_err(|_| format!("invalid {}: {}", what, s))?;
+    let raw = whole.checked_mul(PRICE_SCALE)
+        .and_then(|w| w.checked_add(fraction))
+        .ok_or_else(|| format!("{} out of range: {}", what, s))?;
+    Ok(if negative { -raw } else { raw })
+}
+
+// Formats like an f64, so `{:.2}` keeps working in the console output
+impl fmt::Display for Price {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.to_f64(), f)
+    }
+}
+
+impl FromStr for Price {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_decimal(s, "price").map(Price)
+    }
+}
+
+impl Serialize for Price {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_decimal_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Price {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl Add for Price {
+    type Output = Price;
+
+    fn add(self, other: Price) -> Price {
+        Price(self.0 + other.0)
+    }
+}
+
+impl Sub for Price {
+    type Output = Price;
+
+    fn sub(self, other: Price) -> Price {
+        Price(self.0 - other.0)
+    }
+}
+
+// Cash: notionals, balances and fees, at the same 1/10_000th precision as prices so
+// that sums of fills add up exactly
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Amount(i64);
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0);
+
+    pub const fn from_raw(raw: i64) -> Self {
+        Amount(raw)
+    }
+
+    pub const fn from_units(units: i64) -> Self {
+        Amount(units * PRICE_SCALE)
+    }
+
+    pub fn raw(&self) -> i64 {
+        self.0
+    }
+
+    pub fn from_f64(value: f64) -> Self {
+        Amount((value * PRICE_SCALE as f64).round() as i64)
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        self.0 as f64 / PRICE_SCALE as f64
+    }
+
+    // Multiply by a rate, such as a fee percentage or an FX rate, rounding to the nearest 1/10_000th
+    pub fn scale(&self, factor: f64) -> Amount {
+        Amount((self.0 as f64 * factor).round() as i64)
+    }
+
+    pub fn abs(&self) -> Amount {
+        Amount(self.0.abs())
+    }
+
+    pub fn to_decimal_string(&self) -> String {
+        to_decimal_string(self.0)
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.to_f64(), f)
+    }
+}
+
+impl FromStr for Amount {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_decimal(s, "amount").map(Amount)
+    }
+}
+
+impl Serialize for Amount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_decimal_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Amount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl Add for Amount {
+    type Output = Amount;
+
+    fn add(self, other: Amount) -> Amount {
+        Amount(self.0 + other.0)
+    }
+}
+
+impl Sub for Amount {
+    type Output = Amount;
+
+    fn sub(self, other: Amount) -> Amount {
+        Amount(self.0 - other.0)
+    }
+}
+
+impl Neg for Amount {
+    type Output = Amount;
+
+    fn neg(self) -> Amount {
+        Amount(-self.0)
+    }
+}
+
+// Whole multiples, such as a contract multiplier or a signed position
+impl Mul<i64> for Amount {
+    type Output = Amount;
+
+    fn mul(self, factor: i64) -> Amount {
+        Amount(self.0 * factor)
+    }
+}
+
+impl AddAssign for Amount {
+    fn add_assign(&mut self, other: Amount) {
+        self.0 += other.0;
+    }
+}
+
+impl SubAssign for Amount {
+    fn sub_assign(&mut self, other: Amount) {
+        self.0 -= other.0;
+    }
+}
+
+impl Sum for Amount {
+    fn sum<I: Iterator<Item = Amount>>(iter: I) -> Amount {
+        Amount(iter.map(|a| a.0).sum())
+    }
+}
+
+// Share counts are whole numbers, serialized as plain integers
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[serde(transparent)]
+pub struct Quantity(u64);
+
+impl Quantity {
+    pub const ZERO: Quantity = Quantity(0);
+
+    pub fn new(shares: u64) -> Self {
+        Quantity(shares)
+    }
+
+    pub fn value(&self) -> u64 {
+        self.0
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn is_multiple_of(&self, lot_size: Quantity) -> bool {
+        lot_size.0 > 0 && self.0.is_multiple_of(lot_size.0)
+    }
+}
+
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl Add for Quantity {
+    type Output = Quantity;
+
+    fn add(self, other: Quantity) -> Quantity {
+        Quantity(self.0 + other.0)
+    }
+}
+
+impl Sub for Quantity {
+    type Output = Quantity;
+
+    fn sub(self, other: Quantity) -> Quantity {
+        Quantity(self.0 - other.0)
+    }
+}
+
+impl AddAssign for Quantity {
+    fn add_assign(&mut self, other: Quantity) {
+        self.0 += other.0;
+    }
+}
+
+impl SubAssign for Quantity {
+    fn sub_assign(&mut self, other: Quantity) {
+        self.0 -= other.0;
+    }
+}
+
+impl Sum for Quantity {
+    fn sum<I: Iterator<Item = Quantity>>(iter: I) -> Quantity {
+        Quantity(iter.map(|q| q.0).sum())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_decimal_prices() {
+        assert_eq!("101.25".parse::<Price>(), Ok(Price::from_raw(1_012_500)));
+        assert_eq!("7".parse::<Price>(), Ok(Price::from_raw(70_000)));
+        assert_eq!(" 0.0001 ".parse::<Price>(), Ok(Price::from_raw(1)));
+        assert_eq!("-2.5".parse::<Price>(), Ok(Price::from_raw(-25_000)));
+    }
+
+    #[test]
+    fn rejects_malformed_prices() {
+        for s in ["", ".5", "1.23456", "1,5", "abc", "1.2.3", "+1", "99999999999999999"] {
+            assert!(s.parse::<Price>().is_err(), "{} parsed", s);
+        }
+    }
+
+    #[test]
+    fn price_round_trips_through_serde() {
+        let price = Price::from_raw(1_234_567);
+        let json = serde_json::to_string(&price).unwrap();
+        assert_eq!(json, "\"123.4567\"");
+        assert_eq!(serde_json::from_str::<Price>(&json).unwrap(), price);
+        assert_eq!(serde_json::from_str::<Price>("\"-0.0100\"").unwrap(), Price::from_raw(-100));
+        assert!(serde_json::from_str::<Price>("123.45").is_err());
+    }
+
+    #[test]
+    fn amount_round_trips_through_serde() {
+        let amount = Amount::from_raw(-98_765_432);
+        let json = serde_json::to_string(&amount).unwrap();
+        assert_eq!(json, "\"-9876.5432\"");
+        assert_eq!(serde_json::from_str::<Amount>(&json).unwrap(), amount);
+        assert_eq!(amount.to_string().parse::<Amount>(), Ok(amount));
+    }
+
+    #[test]
+    fn notional_is_exact() {
+        // 0.1 + 0.2 style errors would show up here with floating point
+        let notional: Amount = (0..10).map(|_| Price::from_raw(1_001).notional(Quantity::new(3))).sum();
+        assert_eq!(notional, Amount::from_raw(30_030));
+        assert_eq!(Price::from_f64(2.5).position_value(-4), Amount::from_units(-10));
+    }
+}
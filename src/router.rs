@@ -0,0 +1,139 @@
+use crate::fees::FeeSchedule;
+use crate::order::{next_order_id, Order, Side};
+use crate::price::{Price, Quantity};
+use crate::venue::Venues;
+
+// One venue's share of a routed order
+#[derive(Debug, Clone, PartialEq)]
+pub struct Allocation {
+    pub venue: String,
+    pub quantity: Quantity,
+    // Liquidity the venue showed within the limit, which this allocation expects to take
+    pub marketable: Quantity,
+}
+
+// Best execution across venues: the marketable part of an order sweeps the
+// best prices on any venue, cheapest taker fee first at the same price and the
+// nearest venue after that. Whatever cannot execute rests where adding
+// liquidity pays the trader most.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SmartOrderRouter;
+
+impl SmartOrderRouter {
+    pub fn allocate(&self, order: &Order, venues: &Venues, monthly_volume: u64) -> Vec<Allocation> {
+        let opposite = match order.side {
+            Side::Buy => Side::Sell,
+            Side::Sell => Side::Buy,
+        };
+        let taker_fee = |fees: &FeeSchedule| fees.tier(monthly_volume).map_or(0.0, |t| t.taker_per_share);
+        let maker_fee = |fees: &FeeSchedule| fees.tier(monthly_volume).map_or(0.0, |t| t.maker_per_share);
+
+        // Every marketable level on every venue, best first
+        let mut levels: Vec<(Price, f64, usize, Quantity)> = Vec::new();
+        for (index, venue) in venues.iter().enumerate() {
+            let Some(book) = venue.books.get(&order.stock_name) else {
+                continue;
+            };
+            for level in book.depth(opposite) {
+                let marketable = match order.side {
+                    Side::Buy => level.price <= order.price,
+                    Side::Sell => level.price >= order.price,
+                };
+                if !marketable {
+                    break;
+                }
+                levels.push((level.price, taker_fee(&venue.fee_schedule), index, level.quantity));
+            }
+        }
+        let latencies: Vec<_> = venues.iter().map(|v| v.latency).collect();
+        levels.sort_by(|a, b| {
+            let by_price = match order.side {
+                Side::Buy => a.0.cmp(&b.0),
+                Side::Sell => b.0.cmp(&a.0),
+            };
+            by_price.then(a.1.total_cmp(&b.1)).then(latencies[a.2].cmp(&latencies[b.2]))
+        });
+
+        let names = venues.names();
+        let mut allocations: Vec<Allocation> = Vec::new();
+        let mut remaining = order.quantity;
+        for (_, _, index, quantity) in levels {
+            if remaining.is_zero() {
+                break;
+            }
+            let take = remaining.min(quantity);
+            remaining -= take;
+            match allocations.iter_mut().find(|a| a.venue == names[index]) {
+                Some(allocation) => {
+                    allocation.quantity += take;
+                    allocation.marketable += take;
+                }
+                None => allocations.push(Allocation { venue: names[index].clone(), quantity: take, marketable: take }),
+            }
+        }
+        if !remaining.is_zero() {
+            let resting = venues.iter()
+                .min_by(|a, b| maker_fee(&a.fee_schedule).total_cmp(&maker_fee(&b.fee_schedule)).then(a.latency.cmp(&b.latency)))
+                .map(|v| v.name.clone())
+                .unwrap_or_default();
+            match allocations.iter_mut().find(|a| a.venue == resting) {
+                Some(allocation) => allocation.quantity += remaining,
+                None => allocations.push(Allocation { venue: resting, quantity: remaining, marketable: Quantity::ZERO }),
+            }
+        }
+        allocations
+    }
+
+    // The child orders to send, one per venue, at the parent's limit price
+    pub fn route(&self, order: &Order, venues: &Venues, monthly_volume: u64) -> Vec<Order> {
+        self.allocate(order, venues, monthly_volume).into_iter()
+            .map(|allocation| Order { order_id: next_order_id(), quantity: allocation.quantity, venue: Some(allocation.venue), ..order.clone() })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order::TimeInForce;
+
+    // Asks of 100 shares at each (venue, price)
+    fn venues(asks: &[(&str, f64)]) -> Venues {
+        let mut venues = Venues::new(&["TEST".to_string()]);
+        for &(venue, price) in asks {
+            let book = venues.get_mut(venue).unwrap().books.get_mut("TEST").unwrap();
+            book.submit(Order::new(9, "TEST", Side::Sell, Price::from_f64(price), Quantity::new(100), TimeInForce::Day));
+        }
+        venues
+    }
+
+    fn buy(price: f64, quantity: u64) -> Order {
+        Order::new(1, "TEST", Side::Buy, Price::from_f64(price), Quantity::new(quantity), TimeInForce::Day)
+    }
+
+    fn allocation(venue: &str, quantity: u64, marketable: u64) -> Allocation {
+        Allocation { venue: venue.to_string(), quantity: Quantity::new(quantity), marketable: Quantity::new(marketable) }
+    }
+
+    #[test]
+    fn takes_the_best_prices_on_any_venue_first() {
+        let venues = venues(&[("XSIM", 10.01), ("ASIM", 10.00), ("LSIM", 10.02)]);
+        assert_eq!(SmartOrderRouter.allocate(&buy(10.01, 200), &venues, 0), vec![allocation("ASIM", 100, 100), allocation("XSIM", 100, 100)]);
+    }
+
+    #[test]
+    fn cheapest_taker_fee_first_at_the_same_price() {
+        // ASIM pays takers, the listing exchange charges less than LSIM
+        let venues = venues(&[("LSIM", 10.00), ("XSIM", 10.00), ("ASIM", 10.00)]);
+        assert_eq!(SmartOrderRouter.allocate(&buy(10.00, 250), &venues, 0),
+            vec![allocation("ASIM", 100, 100), allocation("XSIM", 100, 100), allocation("LSIM", 50, 50)]);
+    }
+
+    #[test]
+    fn the_rest_goes_where_resting_pays_best() {
+        let venues = venues(&[("XSIM", 10.00), ("ASIM", 10.05)]);
+        // LSIM pays the biggest maker rebate
+        assert_eq!(SmartOrderRouter.allocate(&buy(10.00, 300), &venues, 0), vec![allocation("XSIM", 100, 100), allocation("LSIM", 200, 0)]);
+        assert_eq!(SmartOrderRouter.allocate(&buy(9.99, 50), &venues, 0), vec![allocation("LSIM", 50, 0)]);
+    }
+}
@@ -0,0 +1,97 @@
+use serde::{Serialize, Deserialize};
+
+// Symbol the session's phase changes are published under
+pub const SESSION_SYMBOL: &str = "SESSION";
+
+// Number of orders collected by each call auction before it uncrosses
+pub const OPENING_AUCTION_ORDERS: usize = 15;
+pub const CLOSING_AUCTION_ORDERS: usize = 15;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MarketPhase {
+    PreOpen,
+    OpeningAuction,
+    ContinuousTrading,
+    ClosingAuction,
+    PostClose,
+    Closed,
+}
+
+impl MarketPhase {
+    pub fn is_call(&self) -> bool {
+        matches!(self, MarketPhase::OpeningAuction | MarketPhase::ClosingAuction)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MarketPhase::PreOpen => "PRE-OPEN",
+            MarketPhase::OpeningAuction => "OPENING AUCTION",
+            MarketPhase::ContinuousTrading => "CONTINUOUS TRADING",
+            MarketPhase::ClosingAuction => "CLOSING AUCTION",
+            MarketPhase::PostClose => "POST-CLOSE",
+            MarketPhase::Closed => "CLOSED",
+        }
+    }
+}
+
+// The session is driven by how many orders the broker has received, the same
+// way the simulation decides when the market is finished.
+#[derive(Debug, Clone)]
+pub struct SessionSchedule {
+    pub opening_auction_orders: usize,
+    pub closing_auction_orders: usize,
+    pub total_orders: usize,
+}
+
+impl SessionSchedule {
+    pub fn new(total_orders: usize) -> Self {
+        SessionSchedule {
+            opening_auction_orders: OPENING_AUCTION_ORDERS.min(total_orders),
+            closing_auction_orders: CLOSING_AUCTION_ORDERS.min(total_orders.saturating_sub(OPENING_AUCTION_ORDERS)),
+            total_orders,
+        }
+    }
+
+    pub fn phase_for(&self, orders_received: usize) -> MarketPhase {
+        if orders_received >= self.total_orders {
+            MarketPhase::PostClose
+        } else if orders_received < self.opening_auction_orders {
+            MarketPhase::OpeningAuction
+        } else if orders_received >= self.total_orders - self.closing_auction_orders {
+            MarketPhase::ClosingAuction
+        } else {
+            MarketPhase::ContinuousTrading
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn phases_follow_the_order_count() {
+        let schedule = SessionSchedule::new(100);
+        assert_eq!(schedule.phase_for(0), MarketPhase::OpeningAuction);
+        assert_eq!(schedule.phase_for(OPENING_AUCTION_ORDERS - 1), MarketPhase::OpeningAuction);
+        assert_eq!(schedule.phase_for(OPENING_AUCTION_ORDERS), MarketPhase::ContinuousTrading);
+        assert_eq!(schedule.phase_for(100 - CLOSING_AUCTION_ORDERS - 1), MarketPhase::ContinuousTrading);
+        assert_eq!(schedule.phase_for(100 - CLOSING_AUCTION_ORDERS), MarketPhase::ClosingAuction);
+        assert_eq!(schedule.phase_for(99), MarketPhase::ClosingAuction);
+        assert_eq!(schedule.phase_for(100), MarketPhase::PostClose);
+    }
+
+    #[test]
+    fn short_sessions_shrink_the_auctions() {
+        // Too few orders for both calls: the opening auction takes them all
+        let schedule = SessionSchedule::new(10);
+        assert_eq!((schedule.opening_auction_orders, schedule.closing_auction_orders), (10, 0));
+        assert_eq!(schedule.phase_for(9), MarketPhase::OpeningAuction);
+        assert_eq!(schedule.phase_for(10), MarketPhase::PostClose);
+
+        let schedule = SessionSchedule::new(OPENING_AUCTION_ORDERS + 5);
+        assert_eq!(schedule.closing_auction_orders, 5);
+        assert_eq!(schedule.phase_for(OPENING_AUCTION_ORDERS), MarketPhase::ClosingAuction);
+        assert!(schedule.phase_for(OPENING_AUCTION_ORDERS).is_call());
+    }
+}
@@ -0,0 +1,155 @@
+use chrono::NaiveDate;
+use std::collections::HashMap;
+
+use crate::account::Account;
+use crate::calendar::TradingCalendar;
+use crate::order::{Side, Trade};
+use crate::price::{Amount, Price, Quantity};
+
+// Trades settle this many trading days after the trade date (T+2)
+pub const SETTLEMENT_DAYS: usize = 2;
+
+// One side of a trade waiting to settle. Both sides settle against the broker
+// as central counterparty, so a failing seller does not hold up the buyer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Obligation {
+    pub trader_id: usize,
+    pub stock_name: String,
+    pub side: Side,
+    pub quantity: Quantity,
+    // Cash the trader pays (buy) or receives (sell)
+    pub cash: Amount,
+    // Fees on the fill, paid out of the same settlement
+    pub fees: Amount,
+    pub trade_date: NaiveDate,
+    pub settlement_date: NaiveDate,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SettlementResult {
+    Settled(Obligation),
+    // The seller did not hold enough settled shares; the obligation is retried next day
+    Failed { obligation: Obligation, shortfall: Quantity },
+}
+
+#[derive(Debug, Clone)]
+pub struct SettlementEngine {
+    pub cycle_days: usize,
+    calendar: TradingCalendar,
+    pending: Vec<Obligation>,
+}
+
+impl SettlementEngine {
+    pub fn new(cycle_days: usize, calendar: TradingCalendar) -> Self {
+        SettlementEngine { cycle_days, calendar, pending: Vec::new() }
+    }
+
+    pub fn settlement_date(&self, trade_date: NaiveDate) -> NaiveDate {
+        let mut date = trade_date;
+        for _ in 0..self.cycle_days {
+            date = self.calendar.next_trading_day(date);
+        }
+        date
+    }
+
+    // Both sides of a trade, with the fees each side was charged on it
+    pub fn record(&mut self, trade: &Trade, trade_date: NaiveDate, buyer_fees: Amount, seller_fees: Amount) {
+        for (trader_id, side, fees) in [(trade.buyer_id, Side::Buy, buyer_fees), (trade.seller_id, Side::Sell, seller_fees)] {
+            self.record_delivery(trader_id, &trade.stock_name, side, trade.quantity, trade.price, trade_date);
+            if let Some(obligation) = self.pending.last_mut() {
+                obligation.fees = fees;
+            }
+        }
+    }
+
+    // One side of a delivery that is not a trade, such as an option exercise
+    pub fn record_delivery(&mut self, trader_id: usize, stock_name: &str, side: Side, quantity: Quantity, price: Price, trade_date: NaiveDate) {
+        self.pending.push(Obligation {
+            trader_id,
+            stock_name: stock_name.to_string(),
+            side,
+            quantity,
+            cash: price.notional(quantity),
+            fees: Amount::ZERO,
+            trade_date,
+            settlement_date: self.settlement_date(trade_date),
+        });
+    }
+
+    pub fn pending(&self) -> &[Obligation] {
+        &self.pending
+    }
+
+    pub fn rename(&mut self, old: &str, new: &str) {
+        for obligation in self.pending.iter_mut().filter(|o| o.stock_name == old) {
+            obligation.stock_name = new.to_string();
+        }
+    }
+
+    // Pending deliveries are in post-split shares; the cash does not change
+    pub fn split(&mut self, stock_name: &str, new_shares: u64, old_shares: u64) {
+        for obligation in self.pending.iter_mut().filter(|o| o.stock_name == stock_name) {
+            obligation.quantity = Quantity::new(obligation.quantity.value() * new_shares / old_shares);
+        }
+    }
+
+    // Settle everything due on or before `date`. Receipts go first so shares
+    // bought earlier can be delivered on the same day.
+    pub fn settle(&mut self, date: NaiveDate, accounts: &mut HashMap<usize, Account>) -> Vec<SettlementResult> {
+        let (mut due, waiting): (Vec<Obligation>, Vec<Obligation>) = self.pending.drain(..)
+            .partition(|o| o.settlement_date <= date);
+        self.pending = waiting;
+        due.sort_by_key(|o| o.side == Side::Sell);
+
+        let mut results = Vec::new();
+        for obligation in due {
+            let Some(account) = accounts.get_mut(&obligation.trader_id) else {
+                continue;
+            };
+            match account.settle(&obligation) {
+                Ok(()) => results.push(SettlementResult::Settled(obligation)),
+                Err(shortfall) => {
+                    self.pending.push(obligation.clone());
+                    results.push(SettlementResult::Failed { obligation, shortfall });
+                }
+            }
+        }
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::AccountType;
+    use crate::fees::{FeeBreakdown, FeeCharge, Liquidity};
+    use crate::fx::FxRates;
+    use crate::instrument::{InstrumentRegistry, INSTRUMENTS_FILE};
+
+    #[test]
+    fn fees_come_out_of_cash_when_the_trade_settles() {
+        let stocks = InstrumentRegistry::load(INSTRUMENTS_FILE).unwrap().stocks();
+        let mut accounts: HashMap<usize, Account> = (0..2).map(|id| (id, Account::new(id, &stocks, AccountType::Cash, &FxRates::default()))).collect();
+        let currency = accounts[&0].currency("NIKE").to_string();
+        let starting_cash = accounts[&0].cash[&currency];
+        let calendar = TradingCalendar::default_calendar();
+        let days = calendar.trading_days(3);
+        let mut engine = SettlementEngine::new(SETTLEMENT_DAYS, calendar);
+
+        let trade = Trade { stock_name: "NIKE".into(), price: Price::from_f64(10.0), quantity: Quantity::new(100), buy_order_id: 1, sell_order_id: 2,
+            buyer_id: 0, seller_id: 1, aggressor: Some(Side::Buy), venue: None };
+        let fees = FeeBreakdown { per_share: Amount::from_units(1), ..FeeBreakdown::default() };
+        let buyer = accounts.get_mut(&0).unwrap();
+        buyer.apply_fill("NIKE", Side::Buy, trade.price, trade.quantity);
+        buyer.charge_fees(FeeCharge { date: days[0], stock_name: "NIKE".into(), side: Side::Buy, price: trade.price, quantity: trade.quantity,
+            liquidity: Liquidity::Taker, fees });
+        engine.record(&trade, days[0], fees.total(), Amount::ZERO);
+
+        assert_eq!(accounts[&0].cash[&currency], starting_cash);
+        assert_eq!(accounts[&0].unsettled_cash[&currency], -Amount::from_units(1001));
+
+        engine.settle(days[2], &mut accounts);
+        assert_eq!(accounts[&0].cash[&currency], starting_cash - Amount::from_units(1001));
+        assert_eq!(accounts[&0].unsettled_cash[&currency], Amount::ZERO);
+    }
+}
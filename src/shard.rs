@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Barrier, Mutex, atomic::{AtomicBool, AtomicUsize, Ordering}};
+
+use crate::account::{Account, Ledger};
+use crate::authorized_participant::NUM_AUTHORIZED_PARTICIPANTS;
+use crate::circuit_breaker::CircuitBreaker;
+use crate::fx::{FxProcess, FxRates, FX_STEP_ORDERS};
+use crate::index::MarketIndex;
+use crate::instrument::{normalize_symbol, InstrumentRegistry};
+use crate::stock_object::Stock;
+use crate::trader::Population;
+
+// Queue a single broker takes its orders from; each shard of a sharded broker has its own
+pub const ORDER_QUEUE: &str = "stock_order";
+
+// Orders, creation requests and snapshot requests all go to the order exchange keyed
+// by symbol, and each broker binds its queue to the symbols it trades
+pub fn routing_key(symbol: &str) -> String {
+    format!("orders.{}", normalize_symbol(symbol))
+}
+
+// One broker's slice of the market. A single shard is the whole market and takes
+// orders on ORDER_QUEUE; otherwise each shard has a queue of its own.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Shard {
+    pub id: usize,
+    pub count: usize,
+    pub queue: String,
+}
+
+impl Shard {
+    pub fn whole_market() -> Self {
+        Shard { id: 0, count: 1, queue: ORDER_QUEUE.to_string() }
+    }
+
+    pub fn new(id: usize, count: usize) -> Self {
+        Shard { id, count, queue: format!("{}.shard.{}", ORDER_QUEUE, id + 1) }
+    }
+
+    pub fn is_sharded(&self) -> bool {
+        self.count > 1
+    }
+}
+
+// Traders' orders are counted here whichever shard takes them, so every shard moves
+// through the session, and sees the FX rates move, as if they were one broker
+#[derive(Debug, Default)]
+pub struct SessionClock {
+    orders_received: AtomicUsize,
+    fx: Mutex<FxProcess>,
+}
+
+impl SessionClock {
+    pub fn start_session(&self) {
+        self.orders_received.store(0, Ordering::SeqCst);
+    }
+
+    pub fn orders_received(&self) -> usize {
+        self.orders_received.load(Ordering::SeqCst)
+    }
+
+    // FX moves along with the order flow, every FX_STEP_ORDERS orders
+    pub fn tick(&self) {
+        let received = self.orders_received.fetch_add(1, Ordering::SeqCst) + 1;
+        if received.is_multiple_of(FX_STEP_ORDERS) {
+            self.fx.lock().unwrap().step();
+        }
+    }
+
+    pub fn fx_rates(&self) -> FxRates {
+        self.fx.lock().unwrap().rates().clone()
+    }
+}
+
+// What the shards share so that together they act as one broker: one account per
+// trader, the session clock, and the one market index with the market-wide circuit
+// breaker watching it. Clones share the same state.
+#[derive(Debug, Clone)]
+pub struct SharedMarket {
+    pub ledger: Arc<Mutex<Ledger>>,
+    pub clock: Arc<SessionClock>,
+    pub index: Arc<Mutex<MarketIndex>>,
+    // Only its market-wide levels are used; each shard's own breaker follows them
+    pub breaker: Arc<Mutex<CircuitBreaker>>,
+    pub population: Population,
+    pub order_count: Arc<AtomicUsize>,
+    pub stop_signal: Arc<AtomicBool>,
+    shards: usize,
+    closing: Arc<Barrier>,
+    shards_closed: Arc<AtomicUsize>,
+}
+
+impl SharedMarket {
+    // `registry` is the whole market's, so accounts and the index cover every stock
+    pub fn new(registry: &InstrumentRegistry, shards: usize, population: Population, order_count: Arc<AtomicUsize>, stop_signal: Arc<AtomicBool>) -> Self {
+        let clock = SessionClock::default();
+        let config = registry.index_config();
+        let index = MarketIndex::new(&config.name, config.weighting, registry, &clock.fx_rates());
+        let market = SharedMarket { ledger: Arc::new(Mutex::new(Ledger::default())), clock: Arc::new(clock), index: Arc::new(Mutex::new(index)),
+            breaker: Arc::new(Mutex::new(CircuitBreaker::default())), population, order_count, stop_signal, shards, closing: Arc::new(Barrier::new(shards)),
+            shards_closed: Arc::new(AtomicUsize::new(0)) };
+        market.open_accounts(&registry.stocks());
+        market
+    }
+
+    // The population says which traders trade on margin and which from cash accounts
+    pub fn open_accounts(&self, stocks: &[Stock]) {
+        let fx = self.clock.fx_rates();
+        let mut ledger = self.ledger.lock().unwrap();
+        ledger.accounts = (0..self.population.first_participant_id() + NUM_AUTHORIZED_PARTICIPANTS)
+            .map(|id| (id, Account::new(id, stocks, self.population.account_type(id), &fx)))
+            .collect();
+    }
+
+    // Every shard calls this before the day's trading starts
+    pub fn start_session(&self) {
+        self.clock.start_session();
+        self.breaker.lock().unwrap().start_session(HashMap::new());
+        self.shards_closed.store(0, Ordering::SeqCst);
+    }
+
+    // Every shard waits here once its closing prices are in, so the index has closed
+    // before any of them settles against it
+    pub fn wait_for_close(&self) {
+        self.closing.wait();
+    }
+
+    // Returns true for the last shard to close, which reports the day for the whole market
+    pub fn close_shard(&self) -> bool {
+        self.shards_closed.fetch_add(1, Ordering::SeqCst) + 1 == self.shards
+    }
+}
+
+// Which shard trades each symbol
+#[derive(Debug, Clone)]
+pub struct ShardMap {
+    shards: Vec<Shard>,
+    symbols: Vec<Vec<String>>,
+}
+
+impl ShardMap {
+    // An ETF and the stocks in its basket settle against each other, so they stay
+    // on one shard. Groups go to whichever shard has the fewest symbols, biggest
+    // groups first; there are never more shards than groups.
+    pub fn new(registry: &InstrumentRegistry, count: usize) -> Self {
+        let mut groups: Vec<Vec<String>> = Vec::new();
+        for instrument in registry.instruments() {
+            let mut group = vec![instrument.symbol.clone()];
+            group.extend(instrument.basket.iter().map(|c| c.symbol.clone()));
+            groups.retain(|existing| {
+                if existing.iter().any(|symbol| group.contains(symbol)) {
+                    group.extend(existing.iter().filter(|symbol| !group.contains(symbol)).cloned().collect::<Vec<_>>());
+                    false
+                } else {
+                    true
+                }
+            });
+            groups.push(group);
+        }
+        groups.sort_by_key(|group| std::cmp::Reverse(group.len()));
+
+        let count = count.clamp(1, groups.len().max(1));
+        let mut sizes = vec![0; count];
+        let mut assignments = HashMap::new();
+        for group in groups {
+            let shard = (0..count).min_by_key(|&shard| sizes[shard]).unwrap_or(0);
+            sizes[shard] += group.len();
+            for symbol in group {
+                assignments.insert(symbol, shard);
+            }
+        }
+        // Listing order within each shard
+        let symbols = (0..count)
+            .map(|shard| registry.instruments().iter()
+                .filter(|i| assignments.get(&i.symbol) == Some(&shard))
+                .map(|i| i.symbol.clone())
+                .collect())
+            .collect();
+        let shards = if count == 1 { vec![Shard::whole_market()] } else { (0..count).map(|id| Shard::new(id, count)).collect() };
+        ShardMap { shards, symbols }
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.is_empty()
+    }
+
+    pub fn shards(&self) -> &[Shard] {
+        &self.shards
+    }
+
+    pub fn symbols(&self, shard: usize) -> &[String] {
+        &self.symbols[shard]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instrument::INSTRUMENTS_FILE;
+
+    fn registry() -> InstrumentRegistry {
+        InstrumentRegistry::load(INSTRUMENTS_FILE).unwrap()
+    }
+
+    fn shard_of(map: &ShardMap, symbol: &str) -> usize {
+        (0..map.len()).find(|&shard| map.symbols(shard).iter().any(|s| s == symbol)).unwrap()
+    }
+
+    #[test]
+    fn etf_shares_a_shard_with_its_basket() {
+        let registry = registry();
+        let map = ShardMap::new(&registry, 2);
+        for etf in registry.instruments().iter().filter(|i| i.is_etf()) {
+            let shard = shard_of(&map, &etf.symbol);
+            for component in &etf.basket {
+                assert_eq!(shard_of(&map, &component.symbol), shard);
+            }
+        }
+    }
+
+    #[test]
+    fn groups_are_spread_over_the_emptiest_shards() {
+        let registry = registry();
+        let map = ShardMap::new(&registry, 3);
+        assert_eq!(map.len(), 3);
+        // The ETF's group is the biggest, so it gets a shard to itself
+        assert_eq!(map.symbols(0).len(), 5);
+        assert!(map.symbols(0).contains(&"SPRT".to_string()));
+        assert_eq!(map.symbols(1).len(), 1);
+        assert_eq!(map.symbols(2).len(), 1);
+        let mut all: Vec<String> = (0..map.len()).flat_map(|shard| map.symbols(shard).to_vec()).collect();
+        all.sort();
+        all.dedup();
+        assert_eq!(all.len(), registry.instruments().len());
+    }
+
+    #[test]
+    fn shard_count_is_clamped_to_the_groups() {
+        let registry = registry();
+        assert_eq!(ShardMap::new(&registry, 50).len(), 3);
+        let whole = ShardMap::new(&registry, 0);
+        assert_eq!(whole.shards(), &[Shard::whole_market()]);
+        assert_eq!(whole.symbols(0).len(), registry.instruments().len());
+    }
+
+    #[test]
+    fn clock_steps_fx_with_the_order_flow_across_sessions() {
+        let clock = SessionClock::default();
+        let opening = clock.fx_rates();
+        for _ in 1..FX_STEP_ORDERS {
+            clock.tick();
+        }
+        assert_eq!(clock.fx_rates(), opening);
+        clock.tick();
+        assert_eq!(clock.orders_received(), FX_STEP_ORDERS);
+        let stepped = clock.fx_rates();
+        assert_ne!(stepped, opening);
+        clock.start_session();
+        assert_eq!(clock.orders_received(), 0);
+        assert_eq!(clock.fx_rates(), stepped);
+    }
+
+    #[test]
+    fn last_shard_to_close_reports_the_day() {
+        let registry = registry();
+        let market = SharedMarket::new(&registry, 2, Population::default(), Arc::new(AtomicUsize::new(0)), Arc::new(AtomicBool::new(false)));
+        assert!(!market.close_shard());
+        assert!(market.close_shard());
+        market.start_session();
+        assert!(!market.clone().close_shard());
+        assert!(market.close_shard());
+    }
+}
@@ -1,17 +1,83 @@
 use serde::{Serialize, Deserialize};
+use crate::fx::BASE_CURRENCY;
+use crate::price::{Price, Quantity};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+pub const DEFAULT_TICK_SIZE: f64 = 0.01;
+pub const DEFAULT_LOT_SIZE: u64 = 10;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Stock {
     pub stock_name: String,
-    pub current_price: f64,
+    pub current_price: Price,
+    // Official closing price set by the closing auction
+    #[serde(default)]
+    pub closing_price: Option<Price>,
+    pub tick_size: Price,
+    pub lot_size: Quantity,
+    // Prices are quoted in this currency
+    #[serde(default = "base_currency")]
+    pub currency: String,
+}
+
+fn base_currency() -> String {
+    BASE_CURRENCY.to_string()
 }
 
 impl Stock {
     pub fn new(stock_name: &str, current_price: f64) -> Self {
+        Stock::with_units(stock_name, current_price, DEFAULT_TICK_SIZE, DEFAULT_LOT_SIZE)
+    }
+
+    pub fn with_units(stock_name: &str, current_price: f64, tick_size: f64, lot_size: u64) -> Self {
+        let tick_size = Price::from_f64(tick_size);
         Stock {
             stock_name: stock_name.to_string(),
-            current_price,
+            current_price: Price::from_f64(current_price).round_to_tick(tick_size),
+            closing_price: None,
+            tick_size,
+            lot_size: Quantity::new(lot_size),
+            currency: base_currency(),
+        }
+    }
+
+    // Move the price by a fraction of itself, staying on the tick grid and above zero
+    pub fn move_price(&mut self, fraction: f64) {
+        self.current_price = self.current_price.scale(1.0 + fraction)
+            .round_to_tick(self.tick_size)
+            .max(self.tick_size);
+    }
+
+    // Prices after a `new_shares` for `old_shares` split
+    pub fn split(&mut self, new_shares: u64, old_shares: u64) {
+        let ratio = old_shares as f64 / new_shares as f64;
+        self.restate(|price| price.scale(ratio));
+    }
+
+    // The price drops by the dividend when the stock goes ex
+    pub fn ex_dividend(&mut self, amount: Price) {
+        self.restate(|price| price - amount);
+    }
+
+    // Adjust the last and closing prices, keeping them on the tick grid and above zero
+    fn restate(&mut self, adjust: impl Fn(Price) -> Price) {
+        let tick_size = self.tick_size;
+        self.current_price = adjust(self.current_price).round_to_tick(tick_size).max(tick_size);
+        self.closing_price = self.closing_price.map(|price| adjust(price).round_to_tick(tick_size).max(tick_size));
+    }
+
+    pub fn validate_order(&self, price: Price, quantity: Quantity) -> Result<(), String> {
+        if price <= Price::ZERO {
+            return Err(format!("price ${} must be positive", price.to_decimal_string()));
+        }
+        if !price.is_multiple_of(self.tick_size) {
+            return Err(format!("price ${} is not a multiple of the {} tick size ${}", price.to_decimal_string(),
+            self.stock_name, self.tick_size.to_decimal_string()));
+        }
+        if quantity.is_zero() || !quantity.is_multiple_of(self.lot_size) {
+            return Err(format!("quantity {} is not a multiple of the {} lot size {}", quantity,
+            self.stock_name, self.lot_size));
         }
+        Ok(())
     }
 
     pub fn adjust_price(&mut self, market_news: &MarketNews) {
@@ -20,7 +86,7 @@ impl Stock {
             MarketNews::Bad => -0.05,
             MarketNews::Neutral => 0.0,
         };
-        self.current_price *= 1.0 + adjustment;
+        self.move_price(adjustment);
     }
 }
 
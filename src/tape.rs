@@ -0,0 +1,152 @@
+use serde::{Serialize, Deserialize};
+use chrono::NaiveDateTime;
+use rand::Rng;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use crate::order::{Side, Trade};
+use crate::price::{Price, Quantity};
+use crate::venue::PRIMARY_VENUE;
+
+pub const TAPE_CSV_FILE: &str = "trade_tape.csv";
+const CSV_HEADER: &str = "sequence,time,symbol,price,quantity,aggressor,buyer,seller,venue";
+
+// One execution as it appears on the tape. Counterparties are aliases that are
+// stable for the run but say nothing about which trader is behind them.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TapeEntry {
+    pub sequence: u64,
+    pub time: NaiveDateTime,
+    pub symbol: String,
+    pub price: Price,
+    pub quantity: Quantity,
+    // None for auction trades
+    pub aggressor: Option<Side>,
+    pub buyer: String,
+    pub seller: String,
+    #[serde(default)]
+    pub venue: String,
+}
+
+impl TapeEntry {
+    pub fn to_csv(&self) -> String {
+        let aggressor = self.aggressor.map(|side| side.as_str()).unwrap_or("auction");
+        format!("{},{},{},{},{},{},{},{},{}", self.sequence, self.time.format("%Y-%m-%d %H:%M:%S%.3f"), self.symbol,
+        self.price.to_decimal_string(), self.quantity, aggressor, self.buyer, self.seller, self.venue)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TradeTape {
+    entries: Vec<TapeEntry>,
+    aliases: HashMap<usize, String>,
+    // Random per tape; together they shuffle the alias counter
+    multiplier: u32,
+    mask: u32,
+}
+
+impl Default for TradeTape {
+    fn default() -> Self {
+        let mut rng = rand::thread_rng();
+        TradeTape { entries: Vec::new(), aliases: HashMap::new(), multiplier: rng.gen::<u32>() | 1, mask: rng.gen() }
+    }
+}
+
+impl TradeTape {
+    pub fn new() -> Self {
+        TradeTape::default()
+    }
+
+    // Fixed for a trader once given out. The n-th alias is n scrambled by an odd
+    // multiplier and a mask, which never repeats within 2^32 aliases and does not
+    // give away the order traders first traded in.
+    fn alias(&mut self, trader_id: usize) -> String {
+        let next = self.aliases.len() as u32;
+        let code = next.wrapping_mul(self.multiplier) ^ self.mask;
+        self.aliases.entry(trader_id).or_insert_with(|| format!("CP{:08X}", code)).clone()
+    }
+
+    pub fn record(&mut self, trade: &Trade, time: NaiveDateTime) -> &TapeEntry {
+        let buyer = self.alias(trade.buyer_id);
+        let seller = self.alias(trade.seller_id);
+        self.entries.push(TapeEntry {
+            sequence: self.entries.len() as u64 + 1,
+            time,
+            symbol: trade.stock_name.clone(),
+            price: trade.price,
+            quantity: trade.quantity,
+            aggressor: trade.aggressor,
+            buyer,
+            seller,
+            venue: trade.venue.clone().unwrap_or_else(|| PRIMARY_VENUE.to_string()),
+        });
+        self.entries.last().unwrap()
+    }
+
+    // Several sharded brokers' tapes as one, in time order. Each shard gives out its
+    // own aliases, so a trader's alias differs between shards.
+    pub fn merge(tapes: &[&TradeTape]) -> Self {
+        let mut entries: Vec<TapeEntry> = tapes.iter().flat_map(|tape| tape.entries.iter().cloned()).collect();
+        entries.sort_by_key(|e| e.time);
+        for (index, entry) in entries.iter_mut().enumerate() {
+            entry.sequence = index as u64 + 1;
+        }
+        TradeTape { entries, ..TradeTape::default() }
+    }
+
+    pub fn entries(&self) -> &[TapeEntry] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Executions for a symbol (or all symbols) within [from, to], in tape order
+    pub fn query(&self, symbol: Option<&str>, from: Option<NaiveDateTime>, to: Option<NaiveDateTime>) -> Vec<&TapeEntry> {
+        self.entries.iter()
+            .filter(|e| symbol.is_none_or(|s| e.symbol == s))
+            .filter(|e| from.is_none_or(|from| e.time >= from))
+            .filter(|e| to.is_none_or(|to| e.time <= to))
+            .collect()
+    }
+
+    pub fn for_symbol(&self, symbol: &str) -> Vec<&TapeEntry> {
+        self.query(Some(symbol), None, None)
+    }
+
+    pub fn write_csv<W: Write>(entries: &[&TapeEntry], writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "{}", CSV_HEADER)?;
+        for entry in entries {
+            writeln!(writer, "{}", entry.to_csv())?;
+        }
+        Ok(())
+    }
+
+    pub fn export_csv(&self, path: &str) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        let entries: Vec<&TapeEntry> = self.entries.iter().collect();
+        TradeTape::write_csv(&entries, &mut writer)?;
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn aliases_are_stable_and_unique_beyond_four_hex_digits() {
+        let mut tape = TradeTape::new();
+        let first = tape.alias(7);
+        let aliases: HashSet<String> = (0..100_000).map(|id| tape.alias(id)).collect();
+        assert_eq!(aliases.len(), 100_000);
+        assert_eq!(tape.alias(7), first);
+    }
+}
@@ -2,19 +2,97 @@
 use std::sync::{Arc, RwLock, atomic::{AtomicUsize, Ordering, AtomicBool}};
 use std::thread;
 use std::time::Duration;
-use chrono::Local;
+use chrono::{Local, NaiveDate};
 use rand::Rng;
 use serde_json::to_string;
-use crate::stock_object::{Stock, MarketFactors, MarketNews};
-use crate::rmq::send;
+use crate::stock_object::{MarketFactors, MarketNews};
+use crate::market_data::{MarketDataSubscriber, Quote};
+use crate::bars::BarInterval;
+use crate::order::{Order, Side, TimeInForce};
+use crate::price::{Price, Quantity};
+use crate::account::{AccountMix, AccountType};
+use crate::futures::FutureContract;
+use crate::instrument::InstrumentRegistry;
+use crate::rmq::{publish, ORDER_EXCHANGE};
+use crate::async_rmq;
+use crate::shard::routing_key;
 use std::sync::mpsc::Sender;
+use lapin::Connection;
 
 pub const NUM_TRADERS: usize = 5;
 pub const ORDERS_PER_TRADER: usize = 20;
+// Orders already queued at a price before a trader steps a tick ahead instead of joining
+pub const LONG_QUEUE_ORDERS: usize = 3;
+// Chance that an order goes to a quoted option instead of a stock
+pub const OPTION_ORDER_PROBABILITY: f64 = 0.1;
+// Chance that an order goes to a future, priced within this much of fair value
+pub const FUTURE_ORDER_PROBABILITY: f64 = 0.1;
+pub const FUTURE_PRICE_RANGE: f64 = 0.01;
+// Chance that a stock order is sent to a venue of the trader's choosing rather than smart routed
+pub const DIRECTED_ORDER_PROBABILITY: f64 = 0.2;
+// The async mode runs traders as tasks on a few worker threads, so it can run many more of them
+pub const ASYNC_TRADERS: usize = 1000;
+pub const ASYNC_ORDERS_PER_TRADER: usize = 5;
+pub const ASYNC_WORKER_THREADS: usize = 4;
+
+// How many traders take part and how many orders each sends; the session lasts until
+// all of them are in. The market maker and the authorized participants have the accounts after the traders'.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Population {
+    pub traders: usize,
+    pub orders_per_trader: usize,
+    pub accounts: AccountMix,
+}
+
+impl Population {
+    pub fn new(traders: usize, orders_per_trader: usize) -> Self {
+        Population { traders, orders_per_trader, accounts: AccountMix::default() }
+    }
+
+    // The market maker and the authorized participants always trade on margin
+    pub fn account_type(&self, id: usize) -> AccountType {
+        if self.is_trader(id) { self.accounts.account_type(id) } else { AccountType::Margin }
+    }
+
+    pub fn total_orders(&self) -> usize {
+        self.traders * self.orders_per_trader
+    }
+
+    pub fn is_trader(&self, id: usize) -> bool {
+        id < self.traders
+    }
+
+    pub fn market_maker_id(&self) -> usize {
+        self.traders
+    }
+
+    pub fn first_participant_id(&self) -> usize {
+        self.market_maker_id() + 1
+    }
+}
+
+impl Default for Population {
+    fn default() -> Self {
+        Population::new(NUM_TRADERS, ORDERS_PER_TRADER)
+    }
+}
+
+// What traders know about the session they trade in: the date, the instruments as
+// listed after the day's corporate actions, the futures open for trading, the venues stocks trade on
+// and how many traders they trade against
+#[derive(Debug, Clone)]
+pub struct TradingDay {
+    pub date: NaiveDate,
+    pub registry: Arc<InstrumentRegistry>,
+    pub futures: Vec<FutureContract>,
+    pub venues: Vec<String>,
+    pub population: Population,
+}
 
 pub struct Trader {
     id: usize,
-    stocks: Arc<RwLock<Vec<Stock>>>,
+    market_data: MarketDataSubscriber,
+    day: Arc<TradingDay>,
     market_factors: Arc<RwLock<MarketFactors>>,
     order_count: Arc<AtomicUsize>,
     stop_signal: Arc<AtomicBool>,
@@ -22,96 +100,227 @@ pub struct Trader {
 }
 
 impl Trader {
-    fn new(id: usize, stocks: Arc<RwLock<Vec<Stock>>>, market_factors: Arc<RwLock<MarketFactors>>, order_count: Arc<AtomicUsize>, stop_signal: Arc<AtomicBool>, market_tx: Sender<MarketFactors>) -> Self {
-        Trader { id, stocks, market_factors, order_count, stop_signal, market_tx }
+    fn new(id: usize, market_data: MarketDataSubscriber, day: Arc<TradingDay>, market_factors: Arc<RwLock<MarketFactors>>, order_count: Arc<AtomicUsize>, stop_signal: Arc<AtomicBool>, market_tx: Sender<MarketFactors>) -> Self {
+        Trader { id, market_data, day, market_factors, order_count, stop_signal, market_tx }
     }
 
     fn generate_order(&self) {
-        let mut rng = rand::thread_rng();
         let mut orders_generated = 0;
 
-        while orders_generated < ORDERS_PER_TRADER {
+        while orders_generated < self.day.population.orders_per_trader {
             // Introduce a random delay between operations
-            let delay = rng.gen_range(100..500);
+            let delay = rand::thread_rng().gen_range(100..500);
             thread::sleep(Duration::from_millis(delay));
 
-            // Randomly update market factors
-            if rng.gen_bool(0.4) { //40% chance to update market factors
-                let mut market_factors = self.market_factors.write().unwrap();
-                market_factors.unemployment_rate = rng.gen_range(3.0..10.0);
-                market_factors.gdp_growth = rng.gen_range(-1.0..4.0);
-                market_factors.print_factors();
-                self.market_tx.send(market_factors.clone()).unwrap();
-
-                let market_news = market_factors.determine_market_news();
-                match market_news {
-                    MarketNews::Good => println!("\x1b[32m!!! NEWS: Stock share prices are expected to rise.\x1b[0m"),
-                    MarketNews::Bad => println!("\x1b[31m!!! NEWS: Stock share prices are expected to fall.\x1b[0m"),
-                    MarketNews::Neutral => println!("\x1b[38;5;230m!!! NEWS: No significant changes in stock share prices are expected.\x1b[0m")
+            // A tick with nothing to trade still counts, so the session can end
+            if let Some((order, units)) = self.next_order() {
+                if let Some(message) = self.order_message(&order, units) {
+                    if let Err(e) = publish(message, ORDER_EXCHANGE, &routing_key(&order.stock_name)) {
+                        eprintln!("Trader {}: Failed to send order: {}", self.id + 1, e);
+                    }
                 }
             }
 
-            let market_factors = self.market_factors.read().unwrap();
-            let market_news = market_factors.determine_market_news();
-
-            let mut stocks = self.stocks.write().unwrap();
-            let stock_index = rng.gen_range(0..stocks.len());
-            let stock = &mut stocks[stock_index];
-            let original_price = stock.current_price;
-            let price_change: f64 = rng.gen_range(-0.2..0.2);
-
-            // Adjust price based on market news
-            stock.adjust_price(&market_news);
-
-            // Determine buy or sell based on the price change
-            let activity = if price_change < 0.0 {
-                "buy"
-            } else {
-                "sell"
-            };
-
-            // Adjust stock price based on activity
-            if activity == "buy" {
-                stock.current_price += original_price * (price_change + 0.05); // Example logic for buying
-            } else {
-                stock.current_price += original_price * (price_change - 0.05); // Example logic for selling
+            orders_generated += 1;
+            if self.count_order() {
+                break;
             }
-            
+        }
+        println!("Trader {} has completed {} orders and is now stopping.", self.id + 1, self.day.population.orders_per_trader);
+    }
 
-            // Serialize the stock to JSON
-            match to_string(&*stock) {
-                Ok(order) => {
-                    let current_time = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
-                    println!("{}, Trader {}: {} {} shares at ${:.2}", current_time, self.id + 1, activity, 
-                    stock.stock_name, stock.current_price);
+    // The same trader as a task: it waits on the runtime's timer rather than holding a
+    // thread, and publishes on a channel it shares with every other trader
+    async fn trade(&self, channel: &lapin::Channel) {
+        let mut orders_generated = 0;
 
-                    if let Err(e) = send(order, "stock_order") {
+        while orders_generated < self.day.population.orders_per_trader {
+            let delay = rand::thread_rng().gen_range(100..500);
+            tokio::time::sleep(Duration::from_millis(delay)).await;
+
+            if let Some((order, units)) = self.next_order() {
+                if let Some(message) = self.order_message(&order, units) {
+                    if let Err(e) = async_rmq::publish(channel, message, ORDER_EXCHANGE, &routing_key(&order.stock_name)).await {
                         eprintln!("Trader {}: Failed to send order: {}", self.id + 1, e);
                     }
-                },
-                Err(e) => {
-                    eprintln!("Trader {}: Failed to serialize stock: {}", self.id + 1, e);
                 }
             }
 
             orders_generated += 1;
-            self.order_count.fetch_add(1, Ordering::SeqCst);
-
-            if self.order_count.load(Ordering::SeqCst) >= NUM_TRADERS * ORDERS_PER_TRADER {
-                self.stop_signal.store(true, Ordering::SeqCst);
+            if self.count_order() {
                 break;
             }
         }
-        println!("Trader {} has completed {} orders and is now stopping.", self.id + 1, ORDERS_PER_TRADER);
+        println!("Trader {} has completed {} orders and is now stopping.", self.id + 1, self.day.population.orders_per_trader);
+    }
+
+    // The trader's next order, and what it trades in; none while nothing listed has a price in the feed yet
+    fn next_order(&self) -> Option<(Order, &'static str)> {
+        let mut rng = rand::thread_rng();
+
+        // Randomly update market factors
+        if rng.gen_bool(0.4) { //40% chance to update market factors
+            let mut market_factors = self.market_factors.write().unwrap();
+            market_factors.unemployment_rate = rng.gen_range(3.0..10.0);
+            market_factors.gdp_growth = rng.gen_range(-1.0..4.0);
+            market_factors.print_factors();
+            self.market_tx.send(market_factors.clone()).unwrap();
+
+            let market_news = market_factors.determine_market_news();
+            match market_news {
+                MarketNews::Good => println!("\x1b[32m!!! NEWS: Stock share prices are expected to rise.\x1b[0m"),
+                MarketNews::Bad => println!("\x1b[31m!!! NEWS: Stock share prices are expected to fall.\x1b[0m"),
+                MarketNews::Neutral => println!("\x1b[38;5;230m!!! NEWS: No significant changes in stock share prices are expected.\x1b[0m")
+            }
+        }
+
+        // Now and then take a quote in a listed option, or trade a future, instead
+        let derivative = if rng.gen_bool(OPTION_ORDER_PROBABILITY) {
+            self.option_order(&mut rng)
+        } else if rng.gen_bool(FUTURE_ORDER_PROBABILITY) {
+            self.future_order(&mut rng)
+        } else {
+            None
+        };
+        if let Some(order) = derivative {
+            return Some((order, "contracts"));
+        }
+
+        let market_factors = self.market_factors.read().unwrap();
+        let market_news = market_factors.determine_market_news();
+
+        // Only instruments that are listed for trading are picked
+        let tradable = self.day.registry.tradable();
+        if tradable.is_empty() {
+            return None;
+        }
+        let instrument = tradable[rng.gen_range(0..tradable.len())];
+        // Work on a private copy: only the broker changes the market, through trades
+        let snapshot = self.market_data.snapshot();
+        let mut stock = snapshot.get(&instrument.symbol)?.clone();
+        let original_price = stock.current_price;
+        // Lean with the last completed one-second bar: buy into a rise, sell into a fall
+        let momentum = match self.market_data.last_completed_bar(&instrument.symbol, BarInterval::ONE_SECOND) {
+            Some(bar) if bar.close > bar.open => -0.02,
+            Some(bar) if bar.close < bar.open => 0.02,
+            _ => 0.0,
+        };
+        // ...and with the aggressive order flow seen so far
+        let flow = self.market_data.analytics(&instrument.symbol)
+            .and_then(|stats| stats.order_flow_imbalance)
+            .unwrap_or(0.0);
+        let price_change: f64 = rng.gen_range(-0.2..0.2) + momentum - flow * 0.02;
+
+        // Adjust price based on market news
+        stock.adjust_price(&market_news);
+
+        // Determine buy or sell based on the price change
+        let side = if price_change < 0.0 {
+            Side::Buy
+        } else {
+            Side::Sell
+        };
+
+        // Adjust stock price based on activity
+        let adjusted_price = stock.current_price.to_f64();
+        let new_price = if side == Side::Buy {
+            adjusted_price + original_price.to_f64() * (price_change + 0.05) // Example logic for buying
+        } else {
+            adjusted_price + original_price.to_f64() * (price_change - 0.05) // Example logic for selling
+        };
+        stock.current_price = Price::from_f64(new_price).round_to_tick(stock.tick_size).max(stock.tick_size);
+
+        // Rather than join the back of a long queue, improve on it by one tick
+        let queue = snapshot.depth(&stock.stock_name).and_then(|depth| depth.level(side, stock.current_price));
+        if queue.is_some_and(|level| level.orders >= LONG_QUEUE_ORDERS) {
+            stock.current_price = match side {
+                Side::Buy => stock.current_price + stock.tick_size,
+                Side::Sell => (stock.current_price - stock.tick_size).max(stock.tick_size),
+            };
+        }
+
+        // Place a limit order at the new price, in whole lots
+        let quantity = Quantity::new(rng.gen_range(1..=10) * stock.lot_size.value());
+        let time_in_force = if rng.gen_bool(0.2) { TimeInForce::GoodTillCancel } else { TimeInForce::Day };
+        let mut order = Order::new(self.id, &stock.stock_name, side, stock.current_price, quantity, time_in_force);
+        if !self.day.venues.is_empty() && rng.gen_bool(DIRECTED_ORDER_PROBABILITY) {
+            order.venue = Some(self.day.venues[rng.gen_range(0..self.day.venues.len())].clone());
+        }
+        Some((order, "shares"))
+    }
+
+    // Buy at the ask of a random quoted option or, sometimes, sell at its bid
+    fn option_order(&self, rng: &mut impl Rng) -> Option<Order> {
+        let snapshot = self.market_data.snapshot();
+        let quoted: Vec<(&String, &Quote)> = snapshot.quotes.iter()
+            .filter(|(symbol, quote)| snapshot.get(symbol).is_none() && !self.day.futures.iter().any(|f| f.symbol == **symbol) && quote.best_ask.is_some())
+            .collect();
+        if quoted.is_empty() {
+            return None;
+        }
+        let (symbol, quote) = quoted[rng.gen_range(0..quoted.len())];
+        let (side, price) = match quote.best_bid {
+            Some(bid) if rng.gen_bool(0.3) => (Side::Sell, bid),
+            _ => (Side::Buy, quote.best_ask?),
+        };
+        Some(Order::new(self.id, symbol, side, price, Quantity::new(rng.gen_range(1..=5)), TimeInForce::Day))
+    }
+
+    // Either side of a random future, near its fair value off the underlying's last price or the index level
+    fn future_order(&self, rng: &mut impl Rng) -> Option<Order> {
+        if self.day.futures.is_empty() {
+            return None;
+        }
+        let contract = &self.day.futures[rng.gen_range(0..self.day.futures.len())];
+        let snapshot = self.market_data.snapshot();
+        let spot = if contract.is_index {
+            snapshot.index.as_ref().map(|level| Price::from_f64(level.value))?
+        } else {
+            snapshot.get(&contract.underlying)?.current_price
+        };
+        let side = if rng.gen_bool(0.5) { Side::Buy } else { Side::Sell };
+        let price = contract.fair_value(spot, self.day.date)
+            .scale(1.0 + rng.gen_range(-FUTURE_PRICE_RANGE..FUTURE_PRICE_RANGE))
+            .round_to_tick(contract.tick_size)
+            .max(contract.tick_size);
+        Some(Order::new(self.id, &contract.symbol, side, price, Quantity::new(rng.gen_range(1..=3)), TimeInForce::Day))
+    }
+
+    // Announce the order and serialize it to JSON for sending
+    fn order_message(&self, order: &Order, units: &str) -> Option<String> {
+        match to_string(order) {
+            Ok(message) => {
+                let current_time = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+                let venue = order.venue.as_ref().map(|venue| format!(" on {}", venue)).unwrap_or_default();
+                println!("{}, Trader {}: {} {} {} {} at ${:.2}{}", current_time, self.id + 1, order.side.as_str(),
+                order.quantity, order.stock_name, units, order.price, venue);
+                Some(message)
+            },
+            Err(e) => {
+                eprintln!("Trader {}: Failed to serialize order: {}", self.id + 1, e);
+                None
+            }
+        }
+    }
+
+    // Returns true once every trader's orders have been sent
+    fn count_order(&self) -> bool {
+        self.order_count.fetch_add(1, Ordering::SeqCst);
+        if self.order_count.load(Ordering::SeqCst) >= self.day.population.total_orders() {
+            self.stop_signal.store(true, Ordering::SeqCst);
+            return true;
+        }
+        false
     }
 }
 
-pub fn start_traders(stocks: Arc<RwLock<Vec<Stock>>>, market_factors: Arc<RwLock<MarketFactors>>,
+pub fn start_traders(market_data: MarketDataSubscriber, day: TradingDay, market_factors: Arc<RwLock<MarketFactors>>,
      order_count: Arc<AtomicUsize>, stop_signal: Arc<AtomicBool>,market_tx: Sender<MarketFactors>) {
+    let day = Arc::new(day);
     let mut handles = vec![];
 
-    for id in 0..NUM_TRADERS {
-        let trader = Trader::new(id, Arc::clone(&stocks), 
+    for id in 0..day.population.traders {
+        let trader = Trader::new(id, market_data.clone(), Arc::clone(&day),
         Arc::clone(&market_factors), Arc::clone(&order_count), 
         Arc::clone(&stop_signal), market_tx.clone());
         let handle = thread::spawn(move || {
@@ -125,3 +334,32 @@ pub fn start_traders(stocks: Arc<RwLock<Vec<Stock>>>, market_factors: Arc<RwLock
     }
 }
 
+// Async mode: every trader is a task on the current runtime rather than a thread of its own
+pub async fn run_traders(connection: &Connection, market_data: MarketDataSubscriber, day: TradingDay, market_factors: Arc<RwLock<MarketFactors>>,
+     order_count: Arc<AtomicUsize>, stop_signal: Arc<AtomicBool>, market_tx: Sender<MarketFactors>) {
+    let channel = match async_rmq::open_exchange(connection, ORDER_EXCHANGE).await {
+        Ok(channel) => channel,
+        Err(e) => {
+            eprintln!("Traders: Failed to open a channel: {}", e);
+            stop_signal.store(true, Ordering::SeqCst);
+            return;
+        }
+    };
+    let day = Arc::new(day);
+    let mut handles = vec![];
+
+    for id in 0..day.population.traders {
+        let trader = Trader::new(id, market_data.clone(), Arc::clone(&day),
+        Arc::clone(&market_factors), Arc::clone(&order_count),
+        Arc::clone(&stop_signal), market_tx.clone());
+        let channel = channel.clone();
+        let handle = tokio::spawn(async move {
+            trader.trade(&channel).await;
+        });
+        handles.push(handle);
+    }
+
+    for handle in handles {
+        handle.await.unwrap();
+    }
+}
@@ -0,0 +1,172 @@
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::fees::{FeeSchedule, VolumeTier, COMMISSION_PERCENTAGE, COMMISSION_PER_SHARE, MINIMUM_TICKET_FEE};
+use crate::order::{Order, Side};
+use crate::order_book::OrderBook;
+use crate::price::{Price, Quantity};
+
+// The listing exchange runs the auctions and is what the circuit breakers watch
+pub const PRIMARY_VENUE: &str = "XSIM";
+
+// Alternative venues trading the same symbols, continuously only: one pays takers
+// and charges makers, the other pays the best rebate but sits behind a speed bump.
+// Each is (name, exchange fee tiers, one-way latency from the broker).
+pub fn alternative_venues() -> Vec<(&'static str, Vec<VolumeTier>, Duration)> {
+    vec![
+        ("ASIM", vec![VolumeTier { min_monthly_volume: 0, maker_per_share: 0.0010, taker_per_share: -0.0005 }], Duration::from_millis(2)),
+        ("LSIM", vec![
+            VolumeTier { min_monthly_volume: 0, maker_per_share: -0.0032, taker_per_share: 0.0034 },
+            VolumeTier { min_monthly_volume: 20_000, maker_per_share: -0.0034, taker_per_share: 0.0032 },
+        ], Duration::from_millis(25)),
+    ]
+}
+
+// One exchange: its own books for every symbol, its fees and how far away it is
+#[derive(Debug, Clone)]
+pub struct Venue {
+    pub name: String,
+    pub fee_schedule: FeeSchedule,
+    pub latency: Duration,
+    pub books: HashMap<String, OrderBook>,
+    // Shares traded here this session
+    pub volume: Quantity,
+}
+
+impl Venue {
+    pub fn new(name: &str, fee_schedule: FeeSchedule, latency: Duration, symbols: &[String]) -> Self {
+        let books = symbols.iter()
+            .map(|symbol| (symbol.clone(), OrderBook::new(symbol)))
+            .collect();
+        Venue { name: name.to_string(), fee_schedule, latency, books, volume: Quantity::ZERO }
+    }
+
+    pub fn is_primary(&self) -> bool {
+        self.name == PRIMARY_VENUE
+    }
+}
+
+// Best bid and offer across every venue, with the size and venues at each
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct ConsolidatedQuote {
+    pub best_bid: Option<Price>,
+    pub bid_size: Quantity,
+    pub bid_venues: Vec<String>,
+    pub best_ask: Option<Price>,
+    pub ask_size: Quantity,
+    pub ask_venues: Vec<String>,
+}
+
+// The listing exchange plus the alternative venues, and the orders on their way to them
+#[derive(Debug, Clone)]
+pub struct Venues {
+    pub primary: Venue,
+    pub alternatives: Vec<Venue>,
+    in_flight: Vec<(Instant, Order)>,
+}
+
+impl Venues {
+    pub fn new(symbols: &[String]) -> Self {
+        let primary = Venue::new(PRIMARY_VENUE, FeeSchedule::default(), Duration::ZERO, symbols);
+        let alternatives = alternative_venues().into_iter()
+            .map(|(name, tiers, latency)| {
+                let fee_schedule = FeeSchedule::new(COMMISSION_PER_SHARE, COMMISSION_PERCENTAGE, MINIMUM_TICKET_FEE, tiers);
+                Venue::new(name, fee_schedule, latency, symbols)
+            })
+            .collect();
+        Venues { primary, alternatives, in_flight: Vec::new() }
+    }
+
+    // Listing exchange first
+    pub fn iter(&self) -> impl Iterator<Item = &Venue> {
+        std::iter::once(&self.primary).chain(self.alternatives.iter())
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Venue> {
+        std::iter::once(&mut self.primary).chain(self.alternatives.iter_mut())
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.iter().map(|v| v.name.clone()).collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Venue> {
+        self.iter().find(|v| v.name == name)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Venue> {
+        self.iter_mut().find(|v| v.name == name)
+    }
+
+    // Fees are charged by the venue the trade printed on; unrouted trades are the listing exchange's
+    pub fn fee_schedule(&self, venue: Option<&str>) -> &FeeSchedule {
+        venue.and_then(|name| self.get(name)).map_or(&self.primary.fee_schedule, |v| &v.fee_schedule)
+    }
+
+    // Quantity a trader has resting on one side of a symbol, on every venue
+    pub fn open_quantity(&self, symbol: &str, trader_id: usize, side: Side) -> Quantity {
+        self.iter()
+            .filter_map(|v| v.books.get(symbol))
+            .map(|book| book.open_quantity(trader_id, side))
+            .sum()
+    }
+
+    pub fn consolidated(&self, symbol: &str) -> ConsolidatedQuote {
+        let mut quote = ConsolidatedQuote::default();
+        for venue in self.iter() {
+            let Some(book) = venue.books.get(symbol) else {
+                continue;
+            };
+            if let Some(level) = book.depth(Side::Buy).first() {
+                if quote.best_bid.is_none_or(|bid| level.price > bid) {
+                    quote.best_bid = Some(level.price);
+                    quote.bid_size = Quantity::ZERO;
+                    quote.bid_venues.clear();
+                }
+                if quote.best_bid == Some(level.price) {
+                    quote.bid_size += level.quantity;
+                    quote.bid_venues.push(venue.name.clone());
+                }
+            }
+            if let Some(level) = book.depth(Side::Sell).first() {
+                if quote.best_ask.is_none_or(|ask| level.price < ask) {
+                    quote.best_ask = Some(level.price);
+                    quote.ask_size = Quantity::ZERO;
+                    quote.ask_venues.clear();
+                }
+                if quote.best_ask == Some(level.price) {
+                    quote.ask_size += level.quantity;
+                    quote.ask_venues.push(venue.name.clone());
+                }
+            }
+        }
+        quote
+    }
+
+    // An order routed to a distant venue only reaches its book once the latency has passed
+    pub fn send(&mut self, order: Order, now: Instant) {
+        let latency = order.venue.as_deref().and_then(|name| self.get(name)).map_or(Duration::ZERO, |v| v.latency);
+        self.in_flight.push((now + latency, order));
+    }
+
+    // Orders that have arrived by `now`, in the order they arrived
+    pub fn arrived(&mut self, now: Instant) -> Vec<Order> {
+        let (mut arrived, in_flight): (Vec<_>, Vec<_>) = self.in_flight.drain(..).partition(|(due, _)| *due <= now);
+        self.in_flight = in_flight;
+        arrived.sort_by_key(|(due, _)| *due);
+        arrived.into_iter().map(|(_, order)| order).collect()
+    }
+
+    // When the next order on the wire reaches its venue
+    pub fn next_arrival(&self) -> Option<Instant> {
+        self.in_flight.iter().map(|(due, _)| *due).min()
+    }
+
+    // Everything still on the wire, as when the market closes
+    pub fn flush(&mut self) -> Vec<Order> {
+        let mut in_flight = std::mem::take(&mut self.in_flight);
+        in_flight.sort_by_key(|(due, _)| *due);
+        in_flight.into_iter().map(|(_, order)| order).collect()
+    }
+}